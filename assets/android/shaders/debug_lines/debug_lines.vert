@@ -0,0 +1,9 @@
+precision highp float;
+attribute vec3 position;
+attribute vec4 color;
+uniform mat4 u_view_proj;
+varying vec4 v_color;
+void main(void) {
+    v_color = color;
+    gl_Position = u_view_proj * vec4(position, 1.0);
+}
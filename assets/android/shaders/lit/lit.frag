@@ -0,0 +1,83 @@
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+precision highp float;
+#else
+precision mediump float;
+#endif
+// Must match MAX_POINT_LIGHTS in graphics/lighting.rs
+#define MAX_POINT_LIGHTS 4
+uniform vec3 u_camera_pos;
+uniform vec4 u_color;
+uniform vec3 u_specular;
+uniform float u_shininess;
+uniform vec3 u_ambient;
+// Direction the light travels in and its color
+// times intensity, which is black without one.
+uniform vec3 u_dir_direction;
+uniform vec3 u_dir_color;
+uniform int u_point_count;
+uniform vec3 u_point_position[MAX_POINT_LIGHTS];
+uniform vec3 u_point_color[MAX_POINT_LIGHTS];
+uniform float u_point_range[MAX_POINT_LIGHTS];
+#ifdef SHADOWS
+uniform sampler2D u_shadow_map;
+uniform float u_shadow_bias;
+uniform vec2 u_shadow_texel;
+varying vec4 v_shadow_coord;
+#endif
+varying vec3 v_world_pos;
+varying vec3 v_normal;
+
+#ifdef SHADOWS
+float shadow_depth(vec2 uv) {
+#ifdef PACKED_DEPTH
+    return dot(texture2D(u_shadow_map, uv), vec4(1.0, 1.0 / 255.0, 1.0 / 65025.0, 1.0 / 16581375.0));
+#else
+    return texture2D(u_shadow_map, uv).r;
+#endif
+}
+
+// 2x2 percentage closer filtering.
+float shadow_factor() {
+    vec3 c = v_shadow_coord.xyz / v_shadow_coord.w * 0.5 + 0.5;
+    if (c.x < 0.0 || c.x > 1.0 || c.y < 0.0 || c.y > 1.0 || c.z > 1.0) {
+        return 1.0;
+    }
+    float lit = 0.0;
+    for (int x = 0; x < 2; x++) {
+        for (int y = 0; y < 2; y++) {
+            vec2 offset = (vec2(float(x), float(y)) - 0.5) * u_shadow_texel;
+            lit += step(c.z - u_shadow_bias, shadow_depth(c.xy + offset));
+        }
+    }
+    return lit * 0.25;
+}
+#endif
+
+vec3 blinn_phong(vec3 n, vec3 v, vec3 l, vec3 radiance) {
+    float ndl = max(dot(n, l), 0.0);
+    vec3 h = normalize(l + v);
+    float spec = ndl > 0.0 ? pow(max(dot(n, h), 0.0), u_shininess) : 0.0;
+    return radiance * (u_color.rgb * ndl + u_specular * spec);
+}
+
+void main(void)
+{
+    vec3 n = normalize(v_normal);
+    vec3 v = normalize(u_camera_pos - v_world_pos);
+    float shadow = 1.0;
+#ifdef SHADOWS
+    shadow = shadow_factor();
+#endif
+    vec3 color = u_ambient * u_color.rgb;
+    color += shadow * blinn_phong(n, v, -u_dir_direction, u_dir_color);
+    for (int i = 0; i < MAX_POINT_LIGHTS; i++) {
+        if (i >= u_point_count) {
+            break;
+        }
+        vec3 to_light = u_point_position[i] - v_world_pos;
+        float dist = length(to_light);
+        float falloff = clamp(1.0 - dist / u_point_range[i], 0.0, 1.0);
+        color += blinn_phong(n, v, to_light / max(dist, 0.0001), u_point_color[i] * falloff * falloff);
+    }
+    gl_FragColor = vec4(color, u_color.a);
+}
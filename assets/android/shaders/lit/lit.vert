@@ -0,0 +1,36 @@
+precision highp float;
+attribute vec3 position;
+attribute vec3 normal;
+uniform mat4 u_model;
+uniform mat4 u_view_proj;
+uniform mat3 u_normal_matrix;
+#ifdef SKINNING
+// MAX_JOINTS is defined alongside the feature.
+attribute vec4 joints;
+attribute vec4 weights;
+uniform mat4 u_joints[MAX_JOINTS];
+#endif
+#ifdef SHADOWS
+uniform mat4 u_light_view_proj;
+varying vec4 v_shadow_coord;
+#endif
+varying vec3 v_world_pos;
+varying vec3 v_normal;
+void main(void) {
+    vec4 local = vec4(position, 1.0);
+    vec3 local_normal = normal;
+#ifdef SKINNING
+    mat4 skin = u_joints[int(joints.x)] * weights.x + u_joints[int(joints.y)] * weights.y
+              + u_joints[int(joints.z)] * weights.z + u_joints[int(joints.w)] * weights.w;
+    local = skin * local;
+    // Fine for rigs without non-uniform scale.
+    local_normal = mat3(skin[0].xyz, skin[1].xyz, skin[2].xyz) * normal;
+#endif
+    vec4 world = u_model * local;
+    v_world_pos = world.xyz;
+    v_normal = u_normal_matrix * local_normal;
+#ifdef SHADOWS
+    v_shadow_coord = u_light_view_proj * world;
+#endif
+    gl_Position = u_view_proj * world;
+}
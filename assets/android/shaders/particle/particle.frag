@@ -0,0 +1,10 @@
+precision mediump float;
+varying vec2 v_uv;
+varying vec4 v_color;
+void main(void)
+{
+    // Soft round sprite, no texture needed.
+    float d = length(v_uv - 0.5) * 2.0;
+    float a = 1.0 - smoothstep(0.5, 1.0, d);
+    gl_FragColor = vec4(v_color.rgb, v_color.a * a);
+}
@@ -0,0 +1,18 @@
+precision highp float;
+// Corner of the unit quad, from -0.5 to 0.5.
+attribute vec2 corner;
+attribute vec3 center;
+attribute float size;
+attribute vec4 color;
+uniform mat4 u_view_proj;
+// Camera axes in world space for billboarding.
+uniform vec3 u_camera_right;
+uniform vec3 u_camera_up;
+varying vec2 v_uv;
+varying vec4 v_color;
+void main(void) {
+    vec3 world = center + (u_camera_right * corner.x + u_camera_up * corner.y) * size;
+    v_uv = corner + 0.5;
+    v_color = color;
+    gl_Position = u_view_proj * vec4(world, 1.0);
+}
@@ -0,0 +1,125 @@
+precision mediump float;
+// One pass of the post-process chain, picked by feature:
+// BRIGHT, DOWNSAMPLE, UPSAMPLE, COMPOSITE or FXAA.
+uniform sampler2D u_source;
+// Size of one source texel in uv units.
+uniform vec2 u_texel;
+varying vec2 v_uv;
+
+#ifdef BRIGHT
+uniform float u_threshold;
+uniform float u_knee;
+void main(void)
+{
+    vec3 c = texture2D(u_source, v_uv).rgb;
+    float brightness = max(c.r, max(c.g, c.b));
+    // Soft knee so bloom fades in instead of popping.
+    float soft = clamp(brightness - u_threshold + u_knee, 0.0, 2.0 * u_knee);
+    soft = soft * soft / (4.0 * u_knee + 0.0001);
+    float contribution = max(soft, brightness - u_threshold) / max(brightness, 0.0001);
+    gl_FragColor = vec4(c * contribution, 1.0);
+}
+#endif
+
+#ifdef DOWNSAMPLE
+void main(void)
+{
+    // Four bilinear taps average a 4x4 texel block.
+    vec4 o = u_texel.xyxy * vec4(-1.0, -1.0, 1.0, 1.0);
+    vec3 c = texture2D(u_source, v_uv + o.xy).rgb + texture2D(u_source, v_uv + o.zy).rgb
+           + texture2D(u_source, v_uv + o.xw).rgb + texture2D(u_source, v_uv + o.zw).rgb;
+    gl_FragColor = vec4(c * 0.25, 1.0);
+}
+#endif
+
+#ifdef UPSAMPLE
+uniform float u_radius;
+void main(void)
+{
+    // 3x3 tent filter, added onto the next level up.
+    vec4 o = u_texel.xyxy * vec4(1.0, 1.0, -1.0, 0.0) * u_radius;
+    vec3 c = texture2D(u_source, v_uv - o.xy).rgb;
+    c += texture2D(u_source, v_uv - o.wy).rgb * 2.0;
+    c += texture2D(u_source, v_uv - o.zy).rgb;
+    c += texture2D(u_source, v_uv + o.zw).rgb * 2.0;
+    c += texture2D(u_source, v_uv).rgb * 4.0;
+    c += texture2D(u_source, v_uv + o.xw).rgb * 2.0;
+    c += texture2D(u_source, v_uv + o.zy).rgb;
+    c += texture2D(u_source, v_uv + o.wy).rgb * 2.0;
+    c += texture2D(u_source, v_uv + o.xy).rgb;
+    gl_FragColor = vec4(c * (1.0 / 16.0), 1.0);
+}
+#endif
+
+#ifdef COMPOSITE
+uniform sampler2D u_bloom;
+uniform float u_bloom_intensity;
+uniform sampler2D u_lut;
+uniform float u_lut_size;
+uniform float u_grading;
+uniform float u_vignette;
+uniform float u_vignette_softness;
+
+// The 3D LUT is stored as a strip of size x size slices,
+// one per blue value, laid out left to right.
+vec3 grade(vec3 c)
+{
+    c = clamp(c, 0.0, 1.0);
+    float scale = u_lut_size - 1.0;
+    float b = c.b * scale;
+    float b0 = floor(b);
+    float b1 = min(b0 + 1.0, scale);
+    vec2 uv = vec2((c.r * scale + 0.5) / (u_lut_size * u_lut_size), (c.g * scale + 0.5) / u_lut_size);
+    vec3 s0 = texture2D(u_lut, uv + vec2(b0 / u_lut_size, 0.0)).rgb;
+    vec3 s1 = texture2D(u_lut, uv + vec2(b1 / u_lut_size, 0.0)).rgb;
+    return mix(s0, s1, b - b0);
+}
+
+void main(void)
+{
+    vec3 c = texture2D(u_source, v_uv).rgb;
+    c += texture2D(u_bloom, v_uv).rgb * u_bloom_intensity;
+    if (u_grading > 0.0) {
+        c = mix(c, grade(c), u_grading);
+    }
+    vec2 d = v_uv - 0.5;
+    float v = 1.0 - smoothstep(0.8 - u_vignette_softness, 0.8, length(d) * 1.414);
+    c *= mix(1.0, v, u_vignette);
+    gl_FragColor = vec4(c, 1.0);
+}
+#endif
+
+#ifdef FXAA
+#define FXAA_REDUCE_MIN (1.0 / 128.0)
+#define FXAA_REDUCE_MUL (1.0 / 8.0)
+#define FXAA_SPAN_MAX 8.0
+void main(void)
+{
+    vec3 luma = vec3(0.299, 0.587, 0.114);
+    vec3 rgb_nw = texture2D(u_source, v_uv + vec2(-1.0, -1.0) * u_texel).rgb;
+    vec3 rgb_ne = texture2D(u_source, v_uv + vec2(1.0, -1.0) * u_texel).rgb;
+    vec3 rgb_sw = texture2D(u_source, v_uv + vec2(-1.0, 1.0) * u_texel).rgb;
+    vec3 rgb_se = texture2D(u_source, v_uv + vec2(1.0, 1.0) * u_texel).rgb;
+    vec3 rgb_m = texture2D(u_source, v_uv).rgb;
+    float luma_nw = dot(rgb_nw, luma);
+    float luma_ne = dot(rgb_ne, luma);
+    float luma_sw = dot(rgb_sw, luma);
+    float luma_se = dot(rgb_se, luma);
+    float luma_m = dot(rgb_m, luma);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)),
+                    ((luma_nw + luma_sw) - (luma_ne + luma_se)));
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * FXAA_REDUCE_MUL), FXAA_REDUCE_MIN);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * u_texel;
+
+    vec3 rgb_a = 0.5 * (texture2D(u_source, v_uv + dir * (1.0 / 3.0 - 0.5)).rgb
+                      + texture2D(u_source, v_uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (texture2D(u_source, v_uv - dir * 0.5).rgb
+                                     + texture2D(u_source, v_uv + dir * 0.5).rgb);
+    float luma_b = dot(rgb_b, luma);
+    gl_FragColor = vec4((luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b, 1.0);
+}
+#endif
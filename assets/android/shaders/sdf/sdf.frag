@@ -0,0 +1,28 @@
+precision mediump float;
+uniform sampler2D u_atlas;
+uniform vec4 u_color;
+uniform vec4 u_outline_color;
+uniform float u_outline_width;
+uniform vec4 u_shadow_color;
+uniform vec2 u_shadow_offset;
+uniform float u_shadow_softness;
+uniform float u_smoothing;
+varying vec2 v_uv;
+void main(void)
+{
+    // 0.5 is the glyph edge, larger values are inside.
+    float dist = texture2D(u_atlas, v_uv).r;
+    float edge = 0.5 - u_outline_width;
+    float fill = smoothstep(0.5 - u_smoothing, 0.5 + u_smoothing, dist);
+    float cover = smoothstep(edge - u_smoothing, edge + u_smoothing, dist);
+    vec4 body = mix(u_outline_color, u_color, fill);
+    float body_a = body.a * cover;
+
+    float shadow_dist = texture2D(u_atlas, v_uv - u_shadow_offset).r;
+    float soft = u_shadow_softness + u_smoothing;
+    float shadow_a = u_shadow_color.a * smoothstep(edge - soft, edge + soft, shadow_dist);
+
+    // Premultiplied output, the body composited over its shadow.
+    vec3 rgb = body.rgb * body_a + u_shadow_color.rgb * shadow_a * (1.0 - body_a);
+    gl_FragColor = vec4(rgb, body_a + shadow_a * (1.0 - body_a));
+}
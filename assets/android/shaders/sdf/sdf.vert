@@ -0,0 +1,10 @@
+precision mediump float;
+attribute vec2 position;
+attribute vec2 texcoord;
+uniform vec2 u_viewport;
+varying vec2 v_uv;
+void main(void) {
+    vec2 ndc = position / u_viewport * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    v_uv = texcoord;
+}
@@ -0,0 +1,17 @@
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+precision highp float;
+#else
+precision mediump float;
+#endif
+void main(void)
+{
+#ifdef PACKED_DEPTH
+    // ES 2.0 without depth textures: spread the depth
+    // over the 8 bit channels of an RGBA target.
+    vec4 enc = fract(vec4(1.0, 255.0, 65025.0, 16581375.0) * gl_FragCoord.z);
+    enc -= enc.yzww * vec4(1.0 / 255.0, 1.0 / 255.0, 1.0 / 255.0, 0.0);
+    gl_FragColor = enc;
+#else
+    gl_FragColor = vec4(1.0);
+#endif
+}
@@ -0,0 +1,6 @@
+precision highp float;
+attribute vec3 position;
+uniform mat4 u_mvp;
+void main(void) {
+    gl_Position = u_mvp * vec4(position, 1.0);
+}
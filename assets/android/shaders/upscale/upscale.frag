@@ -0,0 +1,31 @@
+precision mediump float;
+// Stretches the dynamic resolution target over the window,
+// with an unsharp mask to win back some lost detail.
+uniform sampler2D u_source;
+// Size of one source texel in uv units.
+uniform vec2 u_texel;
+// Fraction of the texture the scene was rendered into.
+uniform vec2 u_uv_scale;
+uniform float u_sharpness;
+varying vec2 v_uv;
+
+// Keeps bilinear taps off the unused part of the texture.
+vec3 tap(vec2 uv)
+{
+    return texture2D(u_source, clamp(uv, 0.5 * u_texel, u_uv_scale - 0.5 * u_texel)).rgb;
+}
+
+void main(void)
+{
+    vec2 uv = v_uv * u_uv_scale;
+    vec3 c = tap(uv);
+    vec3 n = tap(uv + vec2(0.0, u_texel.y));
+    vec3 s = tap(uv - vec2(0.0, u_texel.y));
+    vec3 e = tap(uv + vec2(u_texel.x, 0.0));
+    vec3 w = tap(uv - vec2(u_texel.x, 0.0));
+    vec3 sharpened = c + (4.0 * c - n - s - e - w) * u_sharpness;
+    // Limit to the neighbourhood's range so edges don't ring.
+    vec3 lo = min(c, min(min(n, s), min(e, w)));
+    vec3 hi = max(c, max(max(n, s), max(e, w)));
+    gl_FragColor = vec4(clamp(sharpened, lo, hi), 1.0);
+}
@@ -0,0 +1,105 @@
+// Headless runner for Linux: renders the game offscreen for
+// a fixed number of frames or seconds, optionally saving
+// the frames, so rendering can be exercised in CI without
+// a device.
+
+#[cfg(target_os="linux")]
+mod runner {
+    use hbat::headless::{self, Options};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    const USAGE: &str = "\
+usage: headless [options]
+  --frames N          stop after N frames
+  --seconds S         stop after S seconds
+  --width W           width in pixels (default 1280)
+  --height H          height in pixels (default 720)
+  --assets DIR        asset directory (default $HBAT_ASSET_DIR or ./assets)
+  --data DIR          data directory (default $HBAT_DATA_DIR or the XDG data dir)
+  --output DIR        save frames as PNGs in DIR
+  --capture-every N   with --output, save one frame in N (default 1)
+  --software          use the software renderer
+With neither --frames nor --seconds, renders 60 frames.";
+
+    fn value<T: std::str::FromStr>(flag: &str, arg: Option<String>) -> Result<T, String> {
+        let arg = match arg {
+            Some(a) => a,
+            None => return Err(format!("{} needs a value", flag)),
+        };
+        return match arg.parse::<T>() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(format!("bad value for {}: {}", flag, arg)),
+        };
+    }
+
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--frames" => options.frames = Some(value(&flag, args.next())?),
+                "--seconds" => {
+                    let seconds: f64 = value(&flag, args.next())?;
+                    if !seconds.is_finite() || seconds < 0.0 {
+                        return Err(format!("bad value for --seconds: {}", seconds));
+                    }
+                    options.time_limit = Some(Duration::from_secs_f64(seconds));
+                },
+                "--width" => options.width = value(&flag, args.next())?,
+                "--height" => options.height = value(&flag, args.next())?,
+                "--assets" => options.asset_dir = value::<PathBuf>(&flag, args.next())?,
+                "--data" => options.data_dir = value::<PathBuf>(&flag, args.next())?,
+                "--output" => options.output_dir = Some(value::<PathBuf>(&flag, args.next())?),
+                "--capture-every" => options.capture_every = value(&flag, args.next())?,
+                "--software" => options.software = true,
+                _ => return Err(format!("unknown option {}", flag)),
+            };
+        }
+        if options.width <= 0 || options.height <= 0 {
+            return Err(format!("bad resolution {}x{}", options.width, options.height));
+        }
+        if options.capture_every == 0 {
+            return Err("--capture-every must be at least 1".to_string());
+        }
+        if options.frames.is_none() && options.time_limit.is_none() {
+            options.frames = Some(60);
+        }
+        return Ok(options);
+    }
+
+    pub fn main() -> i32 {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.iter().any(|a| a == "--help" || a == "-h") {
+            println!("{}", USAGE);
+            return 0;
+        }
+        let options = match parse(args.into_iter()) {
+            Ok(o) => o,
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                return 2;
+            },
+        };
+        return match headless::run(&options) {
+            Ok(frames) => {
+                println!("rendered {} frames", frames);
+                0
+            },
+            Err(e) => {
+                eprintln!("headless run failed: {:?}", e);
+                1
+            },
+        };
+    }
+}
+
+#[cfg(target_os="linux")]
+fn main() {
+    std::process::exit(runner::main());
+}
+
+#[cfg(not(target_os="linux"))]
+fn main() {
+    eprintln!("The headless runner needs the Linux bridge.");
+    std::process::exit(1);
+}
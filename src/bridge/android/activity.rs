@@ -61,6 +61,11 @@ impl Activity {
             internal_path: internal_buf,
         });
     }
+
+    // App-private storage directory (Context.getFilesDir).
+    pub fn internal_path(&self) -> &Path {
+        return &self.internal_path;
+    }
 }
 
 // Read from Assets folder on Android.
@@ -6,6 +6,7 @@ use crate::bridge::graphics::*;
 use crate::bridge::activity::{Activity,Asset};
 use android_logger::*;
 use crate::bridge::{Result,Error};
+use crate::graphics::{debug_draw, handle, pacing, profiler, restore};
 use std::ops::Deref;
 use std::panic::catch_unwind;
 use std::path::{PathBuf,Path};
@@ -36,15 +37,33 @@ extern "system" fn Java_com_binaryquackers_hbat_MainActivity_bridgeOnCreate(
                     tidcell.set(tid).expect("Local thread id already set?");
                 });
                 RENDERER_THREAD_ID.set(tid).expect("Renderer thread id already set?");
+                if cfg!(debug_assertions) {
+                    profiler::set_logging(Some(std::time::Duration::from_secs(5)));
+                }
                 loop {
                     let mut graphics = GRAPHICS_MUTEX.lock().unwrap();
-                    while graphics.as_ref().is_none() {
+                    while !graphics.as_ref().map_or(false, |g| g.has_window()) {
+                        if let Some(g) = graphics.as_mut() {
+                            unsafe { g.release_surface(); }
+                        }
                         graphics = GRAPHICS_CONDVAR.wait(graphics).unwrap();
                     }
                     let graphics_unwrapped = graphics.as_mut().unwrap();
+                    unsafe {
+                        profiler::begin_frame();
+                        if handle::drain() > 0 {
+                            restore::prune();
+                        }
+                    }
                     crate::mainloop::render(graphics_unwrapped);
-                    unsafe { graphics_unwrapped.swap_buffers(); }
+                    unsafe {
+                        let _swap = profiler::pass("swap");
+                        graphics_unwrapped.swap_buffers();
+                    }
+                    unsafe { profiler::end_frame(); }
+                    debug_draw::end_frame();
                     drop(graphics);
+                    pacing::wait();
                 }
             });
         }
@@ -69,11 +88,27 @@ extern "system" fn Java_com_binaryquackers_hbat_MainActivity_bridgeOnDestroy(
 extern "system" fn Java_com_binaryquackers_hbat_MainActivity_00024MainSurfaceCallback_bridgeSurfaceChanged(
   env: JNIEnv, _callback: JObject, surface: JObject) -> jboolean {
     return catch_unwind(|| -> jboolean {
-        let graphics = Graphics::new(env, surface).expect("Failed to initialized graphics!");
         let mut guard = GRAPHICS_MUTEX.lock().unwrap();
-        *guard = Some(graphics);
+        // The EGL context outlives surfaces; only the window changes.
+        // Nothing here may panic with the guard held: a poisoned
+        // mutex would take the render thread down too.
+        let result = match guard.as_mut() {
+            Some(graphics) => graphics.set_surface(env, surface),
+            None => Graphics::new(env, surface).map(|g| *guard = Some(g)),
+        };
+        if let Err(e) = result.as_ref() {
+            // Without a window the render thread just waits for
+            // the next one.
+            error!("Failed to update graphics surface: {:?}", e);
+            if let Some(graphics) = guard.as_mut() {
+                graphics.clear_window();
+            }
+        }
         GRAPHICS_CONDVAR.notify_all();
         drop(guard);
+        if result.is_err() {
+            return JNI_FALSE;
+        }
         info!("Surface changed!");
         JNI_TRUE
     }).unwrap_or(JNI_FALSE);
@@ -84,7 +119,10 @@ extern "system" fn Java_com_binaryquackers_hbat_MainActivity_00024MainSurfaceCal
   _env: JNIEnv, _callback: JObject) -> jboolean {
     return catch_unwind(|| -> jboolean {
         let mut guard = GRAPHICS_MUTEX.lock().unwrap();
-        *guard = None;
+        if let Some(graphics) = guard.as_mut() {
+            graphics.clear_window();
+        }
+        GRAPHICS_CONDVAR.notify_all();
         drop(guard);
         info!("Surface destroyed!");
         JNI_TRUE
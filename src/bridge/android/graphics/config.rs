@@ -0,0 +1,282 @@
+use crate::bridge::{Result, Error};
+use khronos_egl as egl;
+use std::fmt;
+use std::sync::Mutex;
+
+type Api = egl::Instance<egl::Dynamic<libloading::Library, egl::EGL1_0>>;
+
+// What the window surface should have. Nothing is a hard
+// requirement: every window-capable config is scored, and
+// the closest one wins, so a device that can't do 4x MSAA
+// or a 24-bit depth buffer still gets the next best thing.
+// Set it before the surface exists; it applies to the next
+// context created.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SurfaceRequest {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+    pub depth: u8,
+    pub stencil: u8,
+    pub samples: u8,
+    pub srgb: bool,
+}
+
+const DEFAULT_REQUEST: SurfaceRequest = SurfaceRequest {
+    red: 8,
+    green: 8,
+    blue: 8,
+    alpha: 0,
+    depth: 24,
+    stencil: 8,
+    samples: 0,
+    srgb: false,
+};
+
+impl Default for SurfaceRequest {
+    fn default() -> Self {
+        return DEFAULT_REQUEST;
+    }
+}
+
+static REQUEST: Mutex<SurfaceRequest> = Mutex::new(DEFAULT_REQUEST);
+
+pub fn set_request(request: SurfaceRequest) {
+    *REQUEST.lock().unwrap() = request;
+}
+
+pub fn request() -> SurfaceRequest {
+    return *REQUEST.lock().unwrap();
+}
+
+// The config that was picked, as EGL reports it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ChosenConfig {
+    pub id: i32,
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+    pub depth: u8,
+    pub stencil: u8,
+    pub samples: u8,
+    // Whether the surface was created with an sRGB colorspace.
+    pub srgb: bool,
+}
+
+impl fmt::Display for ChosenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "config {}: R{}G{}B{}A{} D{} S{} {}x MSAA{}",
+            self.id, self.red, self.green, self.blue, self.alpha, self.depth,
+            self.stencil, self.samples, if self.srgb { " sRGB" } else { "" });
+    }
+}
+
+// Falling short of the request costs far more than
+// exceeding it, and by how much depends on what's missing:
+// color depth matters most, then alpha, depth and stencil
+// that rendering relies on, then MSAA. Extra bits cost a
+// little bandwidth. Slow (software) configs rank last.
+fn score(request: &SurfaceRequest, got: &ChosenConfig, slow: bool) -> u32 {
+    let cost = |want: u8, have: u8, short: u32, over: u32| -> u32 {
+        if have < want {
+            return (want - have) as u32 * short;
+        }
+        return (have - want) as u32 * over;
+    };
+    let mut total = 0;
+    total += cost(request.red, got.red, 1000, 2);
+    total += cost(request.green, got.green, 1000, 2);
+    total += cost(request.blue, got.blue, 1000, 2);
+    total += cost(request.alpha, got.alpha, 500, 4);
+    total += cost(request.depth, got.depth, 100, 1);
+    total += cost(request.stencil, got.stencil, 100, 1);
+    total += cost(request.samples, got.samples, 200, 50);
+    if slow {
+        total += 100_000;
+    }
+    return total;
+}
+
+unsafe fn describe(api: &Api, display: egl::Display, config: egl::Config) -> Result<ChosenConfig> {
+    let get = |attribute| -> Result<u8> {
+        return Ok(api.get_config_attrib(display, config, attribute)?.clamp(0, 255) as u8);
+    };
+    return Ok(ChosenConfig {
+        id: api.get_config_attrib(display, config, egl::CONFIG_ID)?,
+        red: get(egl::RED_SIZE)?,
+        green: get(egl::GREEN_SIZE)?,
+        blue: get(egl::BLUE_SIZE)?,
+        alpha: get(egl::ALPHA_SIZE)?,
+        depth: get(egl::DEPTH_SIZE)?,
+        stencil: get(egl::STENCIL_SIZE)?,
+        samples: get(egl::SAMPLES)?,
+        srgb: false,
+    });
+}
+
+// Whether surfaces can be created with an sRGB colorspace.
+pub fn supports_srgb(api: &Api, display: egl::Display) -> bool {
+    return match api.query_string(Some(display), egl::EXTENSIONS) {
+        Ok(e) => e.to_string_lossy().split_whitespace().any(|e| e == "EGL_KHR_gl_colorspace"),
+        Err(_) => false,
+    };
+}
+
+// A config's attributes as choose() weighs them.
+struct Candidate {
+    described: ChosenConfig,
+    slow: bool,
+    surface_types: egl::Int,
+    renderable_type: egl::Int,
+    buffer_type: egl::Int,
+}
+
+// Index of the best scoring candidate that supports
+// `surface_type` and `renderable` with an RGB color buffer.
+// Ties go to the earlier one.
+fn pick(request: &SurfaceRequest, candidates: &[Candidate], surface_type: egl::Int,
+  renderable: egl::Int) -> Option<usize> {
+    let mut best: Option<(u32, usize)> = None;
+    for (i, c) in candidates.iter().enumerate() {
+        if c.surface_types & surface_type == 0 || c.renderable_type & renderable == 0
+          || c.buffer_type != egl::RGB_BUFFER {
+            continue;
+        }
+        let s = score(request, &c.described, c.slow);
+        if best.map_or(true, |b| s < b.0) {
+            best = Some((s, i));
+        }
+    }
+    return best.map(|b| b.1);
+}
+
+// Whether `chosen` falls short of `request` anywhere.
+fn falls_short(request: &SurfaceRequest, chosen: &ChosenConfig) -> bool {
+    return chosen.red < request.red || chosen.green < request.green || chosen.blue < request.blue
+        || chosen.alpha < request.alpha || chosen.depth < request.depth || chosen.stencil < request.stencil
+        || chosen.samples < request.samples || chosen.srgb != request.srgb;
+}
+
+// Picks the config closest to `request` among those that
+// support `surface_type` (EGL_WINDOW_BIT, EGL_PBUFFER_BIT)
+// and can render with `renderable` (an EGL_OPENGL_ES*_BIT).
+// Ties go to whichever EGL listed first.
+pub unsafe fn choose(api: &Api, display: egl::Display, request: &SurfaceRequest,
+  surface_type: egl::Int, renderable: egl::Int) -> Result<(egl::Config, ChosenConfig)> {
+    let mut configs = Vec::with_capacity(api.get_config_count(display)?);
+    api.get_configs(display, &mut configs)?;
+    let mut candidates = Vec::with_capacity(configs.len());
+    for config in configs.iter() {
+        candidates.push(Candidate {
+            described: describe(api, display, *config)?,
+            slow: api.get_config_attrib(display, *config, egl::CONFIG_CAVEAT)? == egl::SLOW_CONFIG,
+            surface_types: api.get_config_attrib(display, *config, egl::SURFACE_TYPE)?,
+            renderable_type: api.get_config_attrib(display, *config, egl::RENDERABLE_TYPE)?,
+            buffer_type: api.get_config_attrib(display, *config, egl::COLOR_BUFFER_TYPE)?,
+        });
+    }
+    let best = match pick(request, &candidates, surface_type, renderable) {
+        Some(i) => i,
+        None => return Err(Error::NoEGLConfigs),
+    };
+    let mut chosen = candidates[best].described;
+    chosen.srgb = request.srgb && supports_srgb(api, display);
+    if falls_short(request, &chosen) {
+        log::warn!("EGL config doesn't match request {:?}, got {}", request, chosen);
+    }
+    log::info!("Chose EGL {}", chosen);
+    return Ok((configs[best], chosen));
+}
+
+// Attributes for eglCreateWindowSurface that go with the
+// chosen config, NONE-terminated.
+pub fn surface_attributes(chosen: &ChosenConfig) -> Vec<egl::Int> {
+    let mut attributes = Vec::new();
+    if chosen.srgb {
+        attributes.extend_from_slice(&[egl::GL_COLORSPACE, egl::GL_COLORSPACE_SRGB]);
+    }
+    attributes.push(egl::NONE);
+    return attributes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: i32, rgba: [u8; 4], depth: u8, stencil: u8, samples: u8) -> Candidate {
+        return Candidate {
+            described: ChosenConfig {
+                id: id,
+                red: rgba[0],
+                green: rgba[1],
+                blue: rgba[2],
+                alpha: rgba[3],
+                depth: depth,
+                stencil: stencil,
+                samples: samples,
+                srgb: false,
+            },
+            slow: false,
+            surface_types: egl::WINDOW_BIT | egl::PBUFFER_BIT,
+            renderable_type: egl::OPENGL_ES2_BIT,
+            buffer_type: egl::RGB_BUFFER,
+        };
+    }
+
+    fn picked(request: &SurfaceRequest, candidates: &[Candidate]) -> Option<i32> {
+        return pick(request, candidates, egl::WINDOW_BIT, egl::OPENGL_ES2_BIT)
+            .map(|i| candidates[i].described.id);
+    }
+
+    #[test]
+    fn prefers_an_exact_match() {
+        let request = SurfaceRequest { samples: 4, ..DEFAULT_REQUEST };
+        let candidates = [
+            candidate(1, [8, 8, 8, 8], 24, 8, 4),
+            candidate(2, [8, 8, 8, 0], 24, 8, 4),
+            candidate(3, [8, 8, 8, 0], 16, 8, 4),
+        ];
+        assert_eq!(picked(&request, &candidates), Some(2));
+        assert!(!falls_short(&request, &candidates[1].described));
+        // Slow configs lose to anything fast.
+        let mut slow = candidate(4, [8, 8, 8, 0], 24, 8, 4);
+        slow.slow = true;
+        assert_eq!(picked(&request, &[slow, candidate(5, [8, 8, 8, 8], 24, 8, 4)]), Some(5));
+    }
+
+    #[test]
+    fn falls_back_without_msaa_or_srgb() {
+        let request = SurfaceRequest { samples: 4, srgb: true, ..DEFAULT_REQUEST };
+        let candidates = [
+            candidate(1, [8, 8, 8, 0], 24, 8, 0),
+            candidate(2, [8, 8, 8, 0], 24, 8, 2),
+            candidate(3, [5, 6, 5, 0], 24, 8, 4),
+        ];
+        // Color depth matters more than samples.
+        assert_eq!(picked(&request, &candidates), Some(2));
+        // Without EGL_KHR_gl_colorspace the pick stands,
+        // it's just reported as short.
+        let chosen = candidates[1].described;
+        assert!(falls_short(&request, &chosen));
+        assert!(falls_short(&SurfaceRequest { samples: 2, ..request }, &chosen));
+        assert!(!falls_short(&SurfaceRequest { samples: 2, srgb: false, ..request }, &chosen));
+    }
+
+    #[test]
+    fn rejects_unusable_configs() {
+        let request = DEFAULT_REQUEST;
+        let mut pbuffer_only = candidate(1, [8, 8, 8, 0], 24, 8, 0);
+        pbuffer_only.surface_types = egl::PBUFFER_BIT;
+        let mut gles1 = candidate(2, [8, 8, 8, 0], 24, 8, 0);
+        gles1.renderable_type = egl::OPENGL_ES_BIT;
+        let mut luminance = candidate(3, [8, 8, 8, 0], 24, 8, 0);
+        luminance.buffer_type = egl::LUMINANCE_BUFFER;
+        let weak = candidate(4, [4, 4, 4, 0], 0, 0, 0);
+        assert_eq!(picked(&request, &[pbuffer_only, gles1, luminance]), None);
+        assert_eq!(picked(&request, &[candidate(5, [8, 8, 8, 0], 24, 8, 0), weak]), Some(5));
+        // Falling short still beats having nothing.
+        assert_eq!(picked(&request, &[candidate(6, [4, 4, 4, 0], 0, 0, 0)]), Some(6));
+    }
+}
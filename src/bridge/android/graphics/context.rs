@@ -0,0 +1,162 @@
+use crate::bridge::{Result, Error};
+use crate::graphics::gl::ContextFlags;
+use super::config::{self, SurfaceRequest, ChosenConfig};
+use khronos_egl as egl;
+use std::sync::Mutex;
+
+type Api = egl::Instance<egl::Dynamic<libloading::Library, egl::EGL1_0>>;
+
+// OpenGL ES context negotiation. Versions are tried from
+// the newest down, each first with the requested flags and
+// then without, so the result is the best context the
+// device will give. Picking minor versions and flags needs
+// EGL_KHR_create_context (or EGL 1.5); without it, only a
+// major version can be asked for.
+
+// EGL_KHR_create_context names, for drivers that only
+// have the extension and not EGL 1.5.
+const CONTEXT_FLAGS_KHR: egl::Int = 0x30FC;
+const CONTEXT_OPENGL_DEBUG_BIT_KHR: egl::Int = 0x0001;
+// EGL_EXT_create_context_robustness.
+const CONTEXT_OPENGL_ROBUST_ACCESS_EXT: egl::Int = 0x30BF;
+const CONTEXT_OPENGL_RESET_NOTIFICATION_STRATEGY_EXT: egl::Int = 0x3138;
+const LOSE_CONTEXT_ON_RESET_EXT: egl::Int = 0x31BF;
+
+const VERSIONS: [(u8, u8); 4] = [(3, 2), (3, 1), (3, 0), (2, 0)];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ContextRequest {
+    // Nothing newer than this is tried.
+    pub max_version: (u8, u8),
+    pub flags: ContextFlags,
+}
+
+const DEFAULT_REQUEST: ContextRequest = ContextRequest {
+    max_version: (3, 2),
+    flags: ContextFlags { debug: cfg!(debug_assertions), robust: false },
+};
+
+impl Default for ContextRequest {
+    fn default() -> Self {
+        return DEFAULT_REQUEST;
+    }
+}
+
+static REQUEST: Mutex<ContextRequest> = Mutex::new(DEFAULT_REQUEST);
+
+// Applies to the next context created.
+pub fn set_request(request: ContextRequest) {
+    *REQUEST.lock().unwrap() = request;
+}
+
+pub fn request() -> ContextRequest {
+    return *REQUEST.lock().unwrap();
+}
+
+pub struct Negotiated {
+    pub context: egl::Context,
+    pub config: egl::Config,
+    pub chosen: ChosenConfig,
+    // What EGL was asked for and accepted. The driver may
+    // report a higher version once it's current.
+    pub version: (u8, u8),
+    pub flags: ContextFlags,
+}
+
+fn has_extension(api: &Api, display: egl::Display, name: &str) -> bool {
+    return match api.query_string(Some(display), egl::EXTENSIONS) {
+        Ok(e) => e.to_string_lossy().split_whitespace().any(|e| e == name),
+        Err(_) => false,
+    };
+}
+
+struct Support {
+    create_context: bool,
+    egl15: bool,
+    robustness: bool,
+}
+
+fn attributes(version: (u8, u8), flags: ContextFlags, support: &Support) -> Vec<egl::Int> {
+    let mut attrs = Vec::new();
+    if support.create_context {
+        attrs.extend_from_slice(&[
+            egl::CONTEXT_MAJOR_VERSION, version.0 as egl::Int,
+            egl::CONTEXT_MINOR_VERSION, version.1 as egl::Int,
+        ]);
+    } else {
+        attrs.extend_from_slice(&[egl::CONTEXT_CLIENT_VERSION, version.0 as egl::Int]);
+    }
+    if flags.debug {
+        if support.egl15 {
+            attrs.extend_from_slice(&[egl::CONTEXT_OPENGL_DEBUG, egl::TRUE as egl::Int]);
+        } else {
+            attrs.extend_from_slice(&[CONTEXT_FLAGS_KHR, CONTEXT_OPENGL_DEBUG_BIT_KHR]);
+        }
+    }
+    if flags.robust {
+        attrs.extend_from_slice(&[
+            CONTEXT_OPENGL_ROBUST_ACCESS_EXT, egl::TRUE as egl::Int,
+            CONTEXT_OPENGL_RESET_NOTIFICATION_STRATEGY_EXT, LOSE_CONTEXT_ON_RESET_EXT,
+        ]);
+    }
+    attrs.push(egl::NONE);
+    return attrs;
+}
+
+// `surface_type` is the kind of surface the config must
+// support, as for config::choose.
+pub unsafe fn negotiate(api: &Api, display: egl::Display, surface: &SurfaceRequest,
+  surface_type: egl::Int, request: &ContextRequest) -> Result<Negotiated> {
+    let egl15 = api.version() >= egl::Version::EGL1_5;
+    let support = Support {
+        create_context: egl15 || has_extension(api, display, "EGL_KHR_create_context"),
+        egl15: egl15,
+        robustness: has_extension(api, display, "EGL_EXT_create_context_robustness"),
+    };
+    // ES 3 configs can only be named with the ES3 bit, which
+    // comes from EGL_KHR_create_context. Without it, Android
+    // still hands out ES 3 contexts on ES 2 configs.
+    let es3 = if support.create_context {
+        config::choose(api, display, surface, surface_type, egl::OPENGL_ES3_BIT).ok()
+    } else {
+        None
+    };
+    let es2 = config::choose(api, display, surface, surface_type, egl::OPENGL_ES2_BIT).ok();
+    let mut requested = request.flags;
+    if requested.robust && !support.robustness {
+        log::warn!("Robust context requested, but EGL_EXT_create_context_robustness is missing");
+        requested.robust = false;
+    }
+    let mut attempts = vec![requested];
+    if requested != ContextFlags::default() {
+        attempts.push(ContextFlags::default());
+    }
+    for version in VERSIONS.iter().filter(|v| **v <= request.max_version) {
+        // Minor versions can't be asked for without the extension.
+        if !support.create_context && version.1 != 0 {
+            continue;
+        }
+        let (config, chosen) = match (version.0 >= 3, es3, es2) {
+            (true, Some(c), _) => c,
+            (_, _, Some(c)) => c,
+            _ => continue,
+        };
+        for flags in attempts.iter() {
+            let attrs = attributes(*version, *flags, &support);
+            match api.create_context(display, config, None, &attrs) {
+                Ok(context) => {
+                    log::info!("Created OpenGL ES {}.{} context with {:?}", version.0, version.1, flags);
+                    return Ok(Negotiated {
+                        context: context,
+                        config: config,
+                        chosen: chosen,
+                        version: *version,
+                        flags: *flags,
+                    });
+                },
+                Err(e) => log::debug!("OpenGL ES {}.{} with {:?} refused: {:?}", version.0, version.1, flags, e),
+            }
+        }
+    }
+    return Err(Error::NoEGLContext);
+}
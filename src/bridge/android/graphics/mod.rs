@@ -21,6 +21,9 @@ use std::boxed::Box;
 use std::ffi::CStr;
 use std::ffi::c_char;
 
+pub mod config;
+pub mod context;
+
 #[repr(C)]
 pub struct ANativeWindow {
     _data: [u8; 0],
@@ -96,53 +99,198 @@ impl Drop for WEGLContext {
     }
 }
 
+// eglPresentationTimeANDROID, from EGL_ANDROID_presentation_time.
+// The time is in nanoseconds on CLOCK_MONOTONIC.
+type PresentationTimeFn = extern "C" fn(egl::EGLDisplay, egl::EGLSurface, i64) -> egl::Boolean;
+
+// Everything EGL, kept for the app's lifetime. Only the
+// window surface comes and goes with the Android Surface;
+// the display and context outlive it, so GL objects do too.
 pub struct PlatformGLContext {
     api: Rc<egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>>,
     display: egl::Display,
-    surface: WEGLSurface,
+    config: egl::Config,
+    // None while there's no window to draw to.
+    surface: Option<WEGLSurface>,
     egl_ctx: WEGLContext,
+    presentation_time: Option<PresentationTimeFn>,
+    // Version of the pacing settings last applied. Swap
+    // interval is per surface, so a new surface resets it.
+    pacing_version: u64,
+    pub surface_config: config::ChosenConfig,
     pub context: Context,
 }
 
+fn has_egl_extension(api: &egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>,
+  display: egl::Display, name: &str) -> bool {
+    return match api.query_string(Some(display), egl::EXTENSIONS) {
+        Ok(e) => e.to_string_lossy().split_whitespace().any(|e| e == name),
+        Err(_) => false,
+    };
+}
+
+unsafe fn make_surface(api: &Rc<egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>>,
+  display: egl::Display, config: egl::Config, chosen: &config::ChosenConfig,
+  ctx: &WEGLContext, window: &WWindow) -> Result<WEGLSurface> {
+    let surface_attributes = config::surface_attributes(chosen);
+    let surface = api.create_window_surface(
+        display,
+        config,
+        window.get_raw() as *mut c_void,
+        Some(&surface_attributes),
+    )?;
+    let surface = WEGLSurface(surface, display, api.clone());
+    api.make_current(display, Some(surface.0), Some(surface.0), Some(ctx.0))?;
+    return Ok(surface);
+}
+
+fn monotonic_nanos(at: std::time::Instant) -> i64 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now); }
+    let now_ns = now.tv_sec as i64 * 1_000_000_000 + now.tv_nsec as i64;
+    return now_ns + at.saturating_duration_since(std::time::Instant::now()).as_nanos() as i64;
+}
+
 impl PlatformGLContext {
-    pub unsafe fn swap_buffers(&mut self) {
-        match self.api.swap_buffers(self.display, self.surface.0) {
-            Err(e) => { log::warn!("Failed to swap buffers: {:?}", e); }
-            _ => {}
+    // False if the context was lost and must be recreated.
+    pub unsafe fn swap_buffers(&mut self) -> bool {
+        let (settings, version) = graphics::pacing::versioned_settings();
+        if version != self.pacing_version {
+            self.pacing_version = version;
+            let interval = settings.effective_swap_interval();
+            // eglSwapInterval is EGL 1.1, which every Android has.
+            match (*self.api).upcast::<egl::EGL1_1>().map(|api| api.swap_interval(self.display, interval)) {
+                Some(Ok(_)) => log::info!("Swap interval {}, frame cap {:?}", interval, settings.cap),
+                Some(Err(e)) => log::warn!("Failed to set swap interval {}: {:?}", interval, e),
+                None => log::warn!("EGL 1.1 unavailable, can't set swap interval"),
+            };
+        }
+        let surface = match self.surface.as_ref() {
+            Some(s) => s.0,
+            None => return true,
+        };
+        if let Some(present) = self.presentation_time {
+            if let Some(at) = graphics::pacing::next_present_time() {
+                present(self.display.as_ptr(), surface.as_ptr(), monotonic_nanos(at));
+            }
+        }
+        return match self.api.swap_buffers(self.display, surface) {
+            Err(egl::Error::ContextLost) => {
+                log::warn!("EGL context lost");
+                false
+            },
+            Err(e) => {
+                log::warn!("Failed to swap buffers: {:?}", e);
+                true
+            },
+            _ => true,
         };
     }
+
+    // Creates a surface for the window and makes it current.
+    unsafe fn attach(&mut self, window: &WWindow) -> Result<()> {
+        self.detach();
+        self.surface = Some(make_surface(&self.api, self.display, self.config,
+            &self.surface_config, &self.egl_ctx, window)?);
+        self.pacing_version = 0;
+        return Ok(());
+    }
+
+    // Unbinds and destroys the surface, keeping the context.
+    // Also finishes off a surface dropped on another thread
+    // while it was still current here.
+    unsafe fn detach(&mut self) {
+        let _ = self.api.make_current(self.display, None, None, None);
+        self.surface = None;
+    }
 }
 
 unsafe impl Send for PlatformGLContext {}
 
+// The window and, once the render thread asks for it, the
+// EGL context. This lives as long as the app: a new Android
+// Surface only swaps the window, and the render thread then
+// makes a new EGL surface for the same context.
 pub struct Graphics {
-    window: WWindow,
+    window: Option<WWindow>,
     context: Option<PlatformGLContext>,
+    // The window changed since the EGL surface was made.
+    surface_stale: bool,
     pub width: i32,
     pub height: i32,
 }
 
 impl Graphics {
     pub fn new(env: JNIEnv, surface: JObject) -> Result<Graphics> {
+        let mut graphics = Graphics {
+            window: None,
+            context: None,
+            surface_stale: true,
+            width: 0,
+            height: 0,
+        };
+        graphics.set_surface(env, surface)?;
+        return Ok(graphics);
+    }
+    pub unsafe fn from_window(handle: NonNull<ANativeWindow>) -> Result<Graphics> {
+        let mut graphics = Graphics {
+            window: None,
+            context: None,
+            surface_stale: true,
+            width: 0,
+            height: 0,
+        };
+        graphics.set_window(handle);
+        return Ok(graphics);
+    }
+    // For bridgeSurfaceChanged. The same window again is
+    // just a resize; a different one needs a new EGL surface.
+    pub fn set_surface(&mut self, env: JNIEnv, surface: JObject) -> Result<()> {
         let w = unsafe { ANativeWindow_fromSurface(env, surface) };
         return match NonNull::new(w) {
-            Some(window) => unsafe { Self::from_window(window) },
+            Some(window) => {
+                unsafe { self.set_window(window); }
+                Ok(())
+            },
             None => Err(Error::NoWindow),
         };
     }
-    pub unsafe fn from_window(handle: NonNull<ANativeWindow>) -> Result<Graphics> {
-        let width = ANativeWindow_getWidth(handle.as_ptr());
-        let height = ANativeWindow_getHeight(handle.as_ptr());
+    // Takes over the reference `handle` carries.
+    pub unsafe fn set_window(&mut self, handle: NonNull<ANativeWindow>) {
+        self.width = ANativeWindow_getWidth(handle.as_ptr());
+        self.height = ANativeWindow_getHeight(handle.as_ptr());
         let window = WWindow(handle.as_ptr());
-        let ctx = Graphics {
-            window: window,
-            context: None,
-            width: width,
-            height: height,
-        };
-        return Ok(ctx);
+        if self.window.as_ref().map_or(false, |w| w.0 == window.0) {
+            // Dropping releases the extra reference.
+            return;
+        }
+        // EGL defers destroying a surface that's current on
+        // the render thread until that thread lets go of it.
+        if let Some(context) = self.context.as_mut() {
+            context.surface = None;
+        }
+        self.window = Some(window);
+        self.surface_stale = true;
     }
-    pub unsafe fn get_gl_context<'a>(&'a mut self) -> Result<PlatformGLContext> {
+    // For bridgeSurfaceDestroyed. The context stays.
+    pub fn clear_window(&mut self) {
+        if let Some(context) = self.context.as_mut() {
+            context.surface = None;
+        }
+        self.window = None;
+        self.surface_stale = true;
+    }
+    pub fn has_window(&self) -> bool {
+        return self.window.is_some();
+    }
+    // Render thread only: lets go of the current surface
+    // while there's no window, so EGL can destroy it.
+    pub unsafe fn release_surface(&mut self) {
+        if let Some(context) = self.context.as_mut() {
+            context.detach();
+        }
+    }
+    unsafe fn create_context(&self) -> Result<PlatformGLContext> {
         let egl_api = Rc::new(match egl::DynamicInstance::<egl::EGL1_0>::load_required() {
             Ok(api) => Ok(api),
             Err(_e) => Err(Error::EGLInvalidLibrary),
@@ -152,52 +300,30 @@ impl Graphics {
             None => Err(Error::EGLNoDisplay),
         }?;
         egl_api.initialize(display)?;
-        let attrs = [
-            egl::RED_SIZE, 8,
-            egl::GREEN_SIZE, 8,
-            egl::BLUE_SIZE, 8,
-            egl::RENDERABLE_TYPE, egl::OPENGL_ES2_BIT,
-            egl::NONE,
-        ];
-        let cfg = match egl_api.choose_first_config(display, &attrs)? {
-            Some(c) => c,
-            None => return Err(Error::NoEGLConfigs),
+        let negotiated = context::negotiate(&egl_api, display, &config::request(),
+            egl::WINDOW_BIT, &context::request())?;
+        let (cfg, surface_config) = (negotiated.config, negotiated.chosen);
+        let ctx = WEGLContext(negotiated.context, display, egl_api.clone());
+        let presentation_time = match egl_api.get_proc_address("eglPresentationTimeANDROID") {
+            Some(p) if has_egl_extension(&egl_api, display, "EGL_ANDROID_presentation_time") =>
+                Some(std::mem::transmute::<extern "C" fn(), PresentationTimeFn>(p)),
+            _ => None,
+        };
+        // GL functions can only be queried with the context current.
+        let surface = match self.window.as_ref() {
+            Some(window) => make_surface(&egl_api, display, cfg, &surface_config, &ctx, window)?,
+            None => return Err(Error::NoWindow),
         };
-        // To start, only use OpenGL 2 so we maintain compatibility.
-        // Optimize later for OpenGL 3 contexts.
-        let supported_versions = [
-            (4,0),
-            (3,0),
-            (2,0),
-        ];
-        let mut ctx_unwrapped: std::result::Result<egl::Context, egl::Error> = Err(egl::Error::BadContext);
-        for version in &supported_versions {
-            let context_attributes = [
-                egl::CONTEXT_MAJOR_VERSION, version.0,
-                egl::CONTEXT_MINOR_VERSION, version.1,
-                egl::NONE,
-            ];
-            ctx_unwrapped = egl_api.create_context(display, cfg, None, &context_attributes);
-            if ctx_unwrapped.is_ok() { break; }
-        }
-        let ctx = WEGLContext(ctx_unwrapped?, display, egl_api.clone());
-        let surface_attributes = [
-            egl::NONE,
-        ];
-        let surface_unwrapped = egl_api.create_window_surface(
-            display,
-            cfg,
-            self.window.get_raw() as *mut c_void,
-            Some(&surface_attributes),
-        )?;
-        let surface = WEGLSurface(surface_unwrapped, display, egl_api.clone());
-        let _ = egl_api.make_current(display, Some(surface.0), Some(surface.0), Some(ctx.0));
         gl::load_with(|s| -> *const _ {
             return match egl_api.get_proc_address(s) {
                 Some(p) => p as *const c_void,
                 None => null() as *const c_void,
             };
         });
+        // The state cache and every GL name made so far belonged
+        // to the old context.
+        graphics::state::invalidate();
+        graphics::restore::new_context();
         // If the glGetIntegerv call here fails, we know
         // that this context is version 2.0 or 1.1. We require
         // OpenGL ES 2.0+, so if it fails, we'll assume it's 2.0.
@@ -210,15 +336,29 @@ impl Graphics {
         } else {
             major = 2;
         }
+        let gl_context = crate::graphics::gl::Context::new(major as u8, minor as u8, negotiated.flags);
+        log::info!("OpenGL ES {}.{} (asked for {}.{}), {:?}", gl_context.major, gl_context.minor,
+            negotiated.version.0, negotiated.version.1, gl_context.flags);
+        graphics::debug::install(&gl_context);
+        graphics::profiler::install(&gl_context, |s| -> *const c_void {
+            return match egl_api.get_proc_address(s) {
+                Some(p) => p as *const c_void,
+                None => null() as *const c_void,
+            };
+        });
+        // Anything made under a previous context died with it.
+        graphics::restore::context_created(ACTIVITY_LOCK.read().unwrap().as_ref());
+        graphics::commands::reset(&gl_context);
         return Ok(PlatformGLContext {
             api: egl_api,
             display: display,
-            surface: surface,
+            config: cfg,
+            surface: Some(surface),
             egl_ctx: ctx,
-            context: Context::GL(crate::graphics::gl::Context {
-                major: major as u8,
-                minor: minor as u8,
-            }),
+            presentation_time: presentation_time,
+            pacing_version: 0,
+            surface_config: surface_config,
+            context: Context::GL(gl_context),
         });
     }
     pub unsafe fn get_context<'a>(&'a mut self) -> Result<&'a Context> {
@@ -231,14 +371,34 @@ impl Graphics {
         if !valid_id {
             return Err(Error::WrongThread);
         }
+        if self.window.is_none() {
+            self.release_surface();
+            return Err(Error::NoWindow);
+        }
         if self.context.is_none() {
-            self.context = Some(self.get_gl_context()?);
-        };
+            self.context = Some(self.create_context()?);
+            self.surface_stale = false;
+        } else if self.surface_stale {
+            let context = self.context.as_mut().unwrap();
+            context.attach(self.window.as_ref().unwrap())?;
+            self.surface_stale = false;
+            log::info!("Recreated EGL surface, kept context");
+        }
         return Ok(&self.context.as_ref().unwrap().context);
     }
+    // The EGL config in use, once there's a context.
+    pub fn surface_config(&self) -> Option<config::ChosenConfig> {
+        return self.context.as_ref().map(|c| c.surface_config);
+    }
     pub unsafe fn swap_buffers(&mut self) {
-        if self.context.is_some() {
-            self.context.as_mut().unwrap().swap_buffers();
+        let lost = match self.context.as_mut() {
+            Some(context) => !context.swap_buffers(),
+            None => false,
+        };
+        // The next get_context starts over, and restore.rs
+        // brings back what it can.
+        if lost {
+            self.context = None;
         }
     }
 }
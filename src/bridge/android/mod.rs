@@ -25,6 +25,7 @@ pub enum Error {
     EGLNoDisplay,
     EGLInvalidLibrary,
     NoEGLConfigs,
+    NoEGLContext,
     NumericConversionError,
     JNINotInitialized,
     UTF8DecodeError,
@@ -0,0 +1,75 @@
+use crate::bridge::{Result,Error};
+use std::fs::File;
+use std::io::{BufRead,BufReader,Read};
+use std::path::{Path,PathBuf};
+use memmap2::*;
+
+// The Linux stand-in for the Android activity: assets come
+// from a directory laid out like the APK's assets folder,
+// and app-private storage is a data directory.
+pub struct Activity {
+    asset_dir: PathBuf,
+    internal_path: PathBuf,
+}
+
+impl Activity {
+    // Creates the data directory if it isn't there yet.
+    pub fn new<A: AsRef<Path>, D: AsRef<Path>>(asset_dir: A, data_dir: D) -> Result<Activity> {
+        std::fs::create_dir_all(data_dir.as_ref())?;
+        return Ok(Activity {
+            asset_dir: asset_dir.as_ref().to_path_buf(),
+            internal_path: data_dir.as_ref().to_path_buf(),
+        });
+    }
+
+    pub fn asset_dir(&self) -> &Path {
+        return &self.asset_dir;
+    }
+
+    // App-private storage directory, like Context.getFilesDir.
+    pub fn internal_path(&self) -> &Path {
+        return &self.internal_path;
+    }
+
+    // Asset paths are always relative, as they are in the
+    // APK, so a leading slash doesn't escape the asset dir.
+    fn asset_path<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let relative = match path.as_ref().to_str() {
+            Some(s) => Ok(s.trim_start_matches('/')),
+            None => Err(Error::UTF8DecodeError),
+        }?;
+        return Ok(self.asset_dir.join(relative));
+    }
+}
+
+// Read from the asset directory.
+pub struct Asset {
+    reader: BufReader<File>,
+}
+
+impl Asset {
+    pub fn open<P: AsRef<Path>>(path: P, activity: &Activity) -> Result<Asset> {
+        let file = File::open(activity.asset_path(path)?)?;
+        return Ok(Asset { reader: BufReader::new(file) });
+    }
+
+    pub fn map<P: AsRef<Path>>(path: P, activity: &Activity) -> Result<Mmap> {
+        let file = File::open(activity.asset_path(path)?)?;
+        return unsafe { Ok(MmapOptions::new().map(&file)?) };
+    }
+}
+
+impl BufRead for Asset {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        return self.reader.fill_buf();
+    }
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+    }
+}
+
+impl Read for Asset {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        return self.reader.read(buf);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::bridge::globals::*;
+use crate::bridge::graphics::*;
+use crate::bridge::activity::Activity;
+use crate::bridge::{Result,Error};
+use crate::graphics::{debug_draw, handle, pacing, profiler, restore};
+use crate::graphics::capture::{self, Image};
+use std::env;
+use std::path::PathBuf;
+use std::thread;
+use log::*;
+
+// The Linux entry points. Where Android calls into the
+// bridge from lifecycle callbacks and runs its own render
+// thread, a Linux host calls init once and then
+// render_frame on the same thread for as long as it wants
+// frames.
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub width: i32,
+    pub height: i32,
+    // Laid out like the APK's assets folder.
+    pub asset_dir: PathBuf,
+    // Stands in for the app's files directory.
+    pub data_dir: PathBuf,
+    pub software: bool,
+}
+
+// $HBAT_DATA_DIR, else $XDG_DATA_HOME/hbat, else
+// ~/.local/share/hbat.
+pub fn default_data_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("HBAT_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("hbat");
+    }
+    return match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".local").join("share").join("hbat"),
+        None => PathBuf::from(".hbat"),
+    };
+}
+
+impl Default for Settings {
+    // Asset dir from $HBAT_ASSET_DIR, else ./assets.
+    // $HBAT_SOFTWARE_GL=1 picks the software renderer.
+    fn default() -> Self {
+        return Settings {
+            width: 1280,
+            height: 720,
+            asset_dir: env::var_os("HBAT_ASSET_DIR").map_or(PathBuf::from("assets"), PathBuf::from),
+            data_dir: default_data_dir(),
+            software: env::var("HBAT_SOFTWARE_GL").map_or(false, |v| v == "1"),
+        };
+    }
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        return metadata.level() <= log::max_level();
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{:5} {}: {}", record.level(), record.target(), record.args());
+        }
+    }
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// Sets up logging, the activity and graphics, and makes the
+// calling thread the render thread. The context itself is
+// made by the first render_frame.
+pub fn init(settings: &Settings) -> Result<()> {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(if cfg!(debug_assertions) { LevelFilter::Trace } else { LevelFilter::Info });
+    }
+    let tid = thread::current().id();
+    if RENDERER_THREAD_ID.get_or_init(|| tid) != &tid {
+        return Err(Error::WrongThread);
+    }
+    LOCAL_THREAD_ID.with(|tidcell| {
+        let _ = tidcell.set(tid);
+    });
+    if settings.software {
+        use_software_renderer();
+    }
+    let activity = Activity::new(&settings.asset_dir, &settings.data_dir)?;
+    let mut guard = ACTIVITY_LOCK.write().unwrap();
+    *guard = Some(activity);
+    ACTIVITY_CONDVAR.notify_all();
+    drop(guard);
+    let mut guard = GRAPHICS_MUTEX.lock().unwrap();
+    match guard.as_mut() {
+        Some(graphics) => graphics.resize(settings.width, settings.height),
+        None => *guard = Some(Graphics::new(settings.width, settings.height)?),
+    };
+    GRAPHICS_CONDVAR.notify_all();
+    drop(guard);
+    info!("Linux bridge ready, {}x{}, assets in {}", settings.width, settings.height,
+        settings.asset_dir.display());
+    return Ok(());
+}
+
+// One pass of the Android render loop. Render thread only.
+// With `capture`, the frame is also read back before the
+// swap. Unlike on a device there's nobody to retry later,
+// so failing to make the context fails the frame.
+pub fn render_frame(capture: bool) -> Result<Option<Image>> {
+    let mut graphics = GRAPHICS_MUTEX.lock().unwrap();
+    let graphics_unwrapped = match graphics.as_mut() {
+        Some(g) => g,
+        None => return Err(Error::NoWindow),
+    };
+    unsafe {
+        graphics_unwrapped.get_context()?;
+        profiler::begin_frame();
+        if handle::drain() > 0 {
+            restore::prune();
+        }
+    }
+    crate::mainloop::render(graphics_unwrapped);
+    let mut image = None;
+    if capture {
+        image = Some(unsafe {
+            let _capture = profiler::pass("capture");
+            capture::read_pixels(0, 0, graphics_unwrapped.width, graphics_unwrapped.height)
+        });
+    }
+    unsafe {
+        let _swap = profiler::pass("swap");
+        graphics_unwrapped.swap_buffers();
+    }
+    unsafe { profiler::end_frame(); }
+    debug_draw::end_frame();
+    drop(graphics);
+    pacing::wait();
+    return Ok(image);
+}
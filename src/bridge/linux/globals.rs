@@ -0,0 +1,21 @@
+use crate::bridge::{LazyCell,SyncOnceCell};
+use crate::bridge::graphics::Graphics;
+use crate::bridge::activity::Activity;
+use std::sync::{RwLock,Mutex,Condvar};
+use std::thread::ThreadId;
+
+// Kept the same shape as on Android so shared code doesn't
+// care which bridge it's built against. On Linux there's
+// no JVM, and the activity and graphics are set up once by
+// bindings::init instead of by lifecycle callbacks.
+pub static ACTIVITY_LOCK: RwLock<Option<Activity>> = RwLock::new(None);
+pub static ACTIVITY_CONDVAR: Condvar = Condvar::new();
+
+pub static GRAPHICS_MUTEX: Mutex<Option<Graphics>> = Mutex::new(None);
+pub static GRAPHICS_CONDVAR: Condvar = Condvar::new();
+
+thread_local! {
+  pub static LOCAL_THREAD_ID: SyncOnceCell<ThreadId> = SyncOnceCell::new();
+}
+
+pub static RENDERER_THREAD_ID: SyncOnceCell<ThreadId> = SyncOnceCell::new();
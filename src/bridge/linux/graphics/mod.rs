@@ -0,0 +1,285 @@
+use core::ffi::c_void;
+use std::ptr::{null_mut,null};
+use crate::bridge::{Result,Error};
+use crate::graphics::{self, Context};
+use std::rc::Rc;
+use libloading::Library;
+use khronos_egl as egl;
+use egl::*;
+use gl;
+use crate::bridge::globals::*;
+
+// EGL config scoring and context negotiation don't care
+// what the surface is, so they're shared with Android.
+#[path="../../android/graphics/config.rs"]
+pub mod config;
+#[path="../../android/graphics/context.rs"]
+pub mod context;
+
+// EGL_MESA_platform_surfaceless: a display that needs no
+// X11 or Wayland server, for running headless.
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+// Wrapper around EGL surface for impl Drop
+pub struct WEGLSurface(pub egl::Surface, pub egl::Display,
+  pub Rc<egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>>);
+
+impl Drop for WEGLSurface {
+    fn drop(&mut self) {
+        match self.2.destroy_surface(self.1,self.0) {
+            Err(e) => log::error!("Failed to destroy EGL surface: {:?}", e),
+            Ok(_) => {},
+        };
+    }
+}
+
+// Wrapper around context
+pub struct WEGLContext(pub egl::Context, pub egl::Display,
+    pub Rc<egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>>);
+
+impl Drop for WEGLContext {
+    fn drop(&mut self) {
+        match self.2.destroy_context(self.1,self.0) {
+            Err(e) => log::error!("Failed to destroy EGL context: {:?}", e),
+            Ok(_) => {},
+        };
+    }
+}
+
+// Forces Mesa's software rasterizer (llvmpipe/softpipe),
+// for machines without a usable GPU. Only takes effect if
+// called before the first context is created.
+pub fn use_software_renderer() {
+    std::env::set_var("LIBGL_ALWAYS_SOFTWARE", "1");
+}
+
+fn has_client_extension(api: &egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>,
+  name: &str) -> bool {
+    // Client extensions are listed on EGL_NO_DISPLAY. EGL
+    // without EGL_EXT_client_extensions errors instead.
+    return match api.query_string(None, egl::EXTENSIONS) {
+        Ok(e) => e.to_string_lossy().split_whitespace().any(|e| e == name),
+        Err(_) => false,
+    };
+}
+
+// Prefers the surfaceless platform, so nothing needs a
+// display server; otherwise whatever EGL defaults to.
+unsafe fn open_display(api: &egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>)
+  -> Result<egl::Display> {
+    if has_client_extension(api, "EGL_MESA_platform_surfaceless") {
+        if let Some(api15) = (*api).upcast::<egl::EGL1_5>() {
+            match api15.get_platform_display(PLATFORM_SURFACELESS_MESA, null_mut(), &[egl::ATTRIB_NONE]) {
+                Ok(display) => {
+                    log::info!("Using the surfaceless EGL platform");
+                    return Ok(display);
+                },
+                Err(e) => log::warn!("Surfaceless EGL display unavailable: {:?}", e),
+            };
+        }
+    }
+    return match api.get_display(egl::DEFAULT_DISPLAY) {
+        Some(d) => Ok(d),
+        None => Err(Error::EGLNoDisplay),
+    };
+}
+
+unsafe fn make_surface(api: &Rc<egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>>,
+  display: egl::Display, config: egl::Config, chosen: &config::ChosenConfig,
+  ctx: &WEGLContext, width: i32, height: i32) -> Result<WEGLSurface> {
+    let mut attributes = vec![egl::WIDTH, width.max(1), egl::HEIGHT, height.max(1)];
+    attributes.extend_from_slice(&config::surface_attributes(chosen));
+    let surface = api.create_pbuffer_surface(display, config, &attributes)?;
+    let surface = WEGLSurface(surface, display, api.clone());
+    api.make_current(display, Some(surface.0), Some(surface.0), Some(ctx.0))?;
+    return Ok(surface);
+}
+
+// Everything EGL. There's no window: frames go to a pbuffer
+// the size of the requested resolution, so the default
+// framebuffer, viewport and captures all work as they do
+// on a device.
+pub struct PlatformGLContext {
+    api: Rc<egl::Instance<Dynamic<libloading::Library,egl::EGL1_0>>>,
+    display: egl::Display,
+    config: egl::Config,
+    // None after release_surface, until the next get_context.
+    surface: Option<WEGLSurface>,
+    egl_ctx: WEGLContext,
+    pub surface_config: config::ChosenConfig,
+    pub context: Context,
+}
+
+impl PlatformGLContext {
+    // False if the context was lost and must be recreated.
+    // Swapping a pbuffer shows nothing, and swap intervals
+    // don't apply to it, but EGL still reports a lost context.
+    pub unsafe fn swap_buffers(&mut self) -> bool {
+        let surface = match self.surface.as_ref() {
+            Some(s) => s.0,
+            None => return true,
+        };
+        return match self.api.swap_buffers(self.display, surface) {
+            Err(egl::Error::ContextLost) => {
+                log::warn!("EGL context lost");
+                false
+            },
+            Err(e) => {
+                log::warn!("Failed to swap buffers: {:?}", e);
+                true
+            },
+            _ => true,
+        };
+    }
+
+    unsafe fn attach(&mut self, width: i32, height: i32) -> Result<()> {
+        self.detach();
+        self.surface = Some(make_surface(&self.api, self.display, self.config,
+            &self.surface_config, &self.egl_ctx, width, height)?);
+        return Ok(());
+    }
+
+    unsafe fn detach(&mut self) {
+        let _ = self.api.make_current(self.display, None, None, None);
+        self.surface = None;
+    }
+}
+
+unsafe impl Send for PlatformGLContext {}
+
+// Offscreen graphics at a fixed resolution. Like Android's,
+// the context is made on the render thread's first
+// get_context and kept; resizing only replaces the pbuffer.
+pub struct Graphics {
+    context: Option<PlatformGLContext>,
+    // The size changed since the pbuffer was made.
+    surface_stale: bool,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Graphics {
+    pub fn new(width: i32, height: i32) -> Result<Graphics> {
+        return Ok(Graphics {
+            context: None,
+            surface_stale: true,
+            width: width,
+            height: height,
+        });
+    }
+    pub fn resize(&mut self, width: i32, height: i32) {
+        if width == self.width && height == self.height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.surface_stale = true;
+    }
+    // There's always something to draw to.
+    pub fn has_window(&self) -> bool {
+        return true;
+    }
+    // Render thread only. The context stays; the next
+    // get_context makes a new pbuffer.
+    pub unsafe fn release_surface(&mut self) {
+        if let Some(context) = self.context.as_mut() {
+            context.detach();
+            self.surface_stale = true;
+        }
+    }
+    unsafe fn create_context(&self) -> Result<PlatformGLContext> {
+        let egl_api = Rc::new(match egl::DynamicInstance::<egl::EGL1_0>::load_required() {
+            Ok(api) => Ok(api),
+            Err(_e) => Err(Error::EGLInvalidLibrary),
+        }?);
+        let display = open_display(&egl_api)?;
+        egl_api.initialize(display)?;
+        // Desktop EGL may default to desktop GL.
+        match (*egl_api).upcast::<egl::EGL1_2>() {
+            Some(api) => api.bind_api(egl::OPENGL_ES_API)?,
+            None => log::warn!("EGL 1.2 unavailable, can't bind the OpenGL ES API"),
+        };
+        let negotiated = context::negotiate(&egl_api, display, &config::request(),
+            egl::PBUFFER_BIT, &context::request())?;
+        let (cfg, surface_config) = (negotiated.config, negotiated.chosen);
+        let ctx = WEGLContext(negotiated.context, display, egl_api.clone());
+        // GL functions can only be queried with the context current.
+        let surface = make_surface(&egl_api, display, cfg, &surface_config, &ctx,
+            self.width, self.height)?;
+        gl::load_with(|s| -> *const _ {
+            return match egl_api.get_proc_address(s) {
+                Some(p) => p as *const c_void,
+                None => null() as *const c_void,
+            };
+        });
+        // The state cache and every GL name made so far belonged
+        // to the old context.
+        graphics::state::invalidate();
+        graphics::restore::new_context();
+        // Same as on Android: no MAJOR_VERSION means ES 2.0.
+        let mut major: i32 = 2;
+        let mut minor: i32 = 0;
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major as *mut i32);
+        let err = gl::GetError();
+        if err == gl::NO_ERROR {
+            gl::GetIntegerv(gl::MINOR_VERSION, &mut minor as *mut i32);
+        } else {
+            major = 2;
+        }
+        let gl_context = crate::graphics::gl::Context::new(major as u8, minor as u8, negotiated.flags);
+        log::info!("OpenGL ES {}.{} (asked for {}.{}), {:?}", gl_context.major, gl_context.minor,
+            negotiated.version.0, negotiated.version.1, gl_context.flags);
+        graphics::debug::install(&gl_context);
+        graphics::profiler::install(&gl_context, |s| -> *const c_void {
+            return match egl_api.get_proc_address(s) {
+                Some(p) => p as *const c_void,
+                None => null() as *const c_void,
+            };
+        });
+        graphics::restore::context_created(ACTIVITY_LOCK.read().unwrap().as_ref());
+        graphics::commands::reset(&gl_context);
+        return Ok(PlatformGLContext {
+            api: egl_api,
+            display: display,
+            config: cfg,
+            surface: Some(surface),
+            egl_ctx: ctx,
+            surface_config: surface_config,
+            context: Context::GL(gl_context),
+        });
+    }
+    pub unsafe fn get_context<'a>(&'a mut self) -> Result<&'a Context> {
+        let valid_id = LOCAL_THREAD_ID.with(|idcell| -> bool {
+            let id_opt = idcell.get();
+            let renderer_opt = RENDERER_THREAD_ID.get();
+            return id_opt.is_none() || renderer_opt.is_none()
+                   || (id_opt.unwrap() == renderer_opt.unwrap());
+        });
+        if !valid_id {
+            return Err(Error::WrongThread);
+        }
+        if self.context.is_none() {
+            self.context = Some(self.create_context()?);
+            self.surface_stale = false;
+        } else if self.surface_stale {
+            let (width, height) = (self.width, self.height);
+            self.context.as_mut().unwrap().attach(width, height)?;
+            self.surface_stale = false;
+            log::info!("Recreated pbuffer at {}x{}, kept context", width, height);
+        }
+        return Ok(&self.context.as_ref().unwrap().context);
+    }
+    // The EGL config in use, once there's a context.
+    pub fn surface_config(&self) -> Option<config::ChosenConfig> {
+        return self.context.as_ref().map(|c| c.surface_config);
+    }
+    pub unsafe fn swap_buffers(&mut self) {
+        let lost = match self.context.as_mut() {
+            Some(context) => !context.swap_buffers(),
+            None => false,
+        };
+        if lost {
+            self.context = None;
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use crate::graphics::{Result, Error};
+use crate::graphics::shader::read_source;
+use crate::graphics::directives;
+use crate::bridge::activity::Activity;
+use crate::math::{Matrix4, Quaternion, Vector3, M4_IDENTITY, Q_IDENTITY};
+use std::rc::Rc;
+
+// Skeletons and clips are text assets in the same style as
+// materials. Rotations are quaternions as x y z w.
+//
+// Skeleton, parents listed before their children:
+//
+//   joint root - 0 0 0  0 0 0 1  1 1 1
+//   joint head root 0 1.2 0  0 0 0 1  1 1 1
+//
+// each line being name, parent (or -), then the rest pose
+// translation, rotation and scale. Inverse bind matrices are
+// the inverse of each joint's rest pose in model space unless
+// given explicitly, column-major, with
+//
+//   bind head m00 m01 ... m33
+//
+// Clip, keys in time order per joint and channel:
+//
+//   clip bob 1.0 loop        name, seconds, loop or once
+//   t head 0.0 0 1.2 0       translation keys
+//   r head 0.5 0 0 0.087 0.996
+//   s head 1.0 1 1 1
+
+#[derive(Copy, Clone)]
+pub struct Transform {
+    pub translation: Vector3,
+    pub rotation: Quaternion,
+    pub scale: Vector3,
+}
+
+pub static T_IDENTITY: Transform = Transform {
+    translation: Vector3 { x: 0.0, y: 0.0, z: 0.0 },
+    rotation: Q_IDENTITY,
+    scale: Vector3 { x: 1.0, y: 1.0, z: 1.0 },
+};
+
+fn lerp3(a: Vector3, b: Vector3, t: f32) -> Vector3 {
+    return a + (b - a) * t;
+}
+
+impl Transform {
+    pub fn to_matrix(&self) -> Matrix4 {
+        return Matrix4::from_trs(self.translation, self.rotation, self.scale);
+    }
+
+    pub fn blend(&self, other: &Transform, t: f32) -> Transform {
+        return Transform {
+            translation: lerp3(self.translation, other.translation, t),
+            rotation: self.rotation.nlerp(other.rotation, t),
+            scale: lerp3(self.scale, other.scale, t),
+        };
+    }
+}
+
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub rest: Transform,
+    pub inverse_bind: Matrix4,
+}
+
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    // Joints must come after their parents. Returns None
+    // otherwise, since posing relies on that order.
+    pub fn new(joints: Vec<Joint>) -> Option<Skeleton> {
+        for (i, j) in joints.iter().enumerate() {
+            if j.parent.map_or(false, |p| p >= i) {
+                return None;
+            }
+        }
+        return Some(Skeleton { joints: joints });
+    }
+
+    pub fn load(path: &str, activity: &Activity) -> Result<Skeleton> {
+        return Self::parse(&read_source(path, activity)?);
+    }
+
+    pub fn parse(text: &str) -> Result<Skeleton> {
+        let kind = "skeleton";
+        let mut joints: Vec<Joint> = Vec::new();
+        let mut explicit_bind: Vec<bool> = Vec::new();
+        for (line, words) in directives::lines(text) {
+            match words[0] {
+                "joint" if words.len() == 13 => {
+                    if joints.iter().any(|j| j.name == words[1]) {
+                        return Err(directives::error(kind, line, "duplicate joint name"));
+                    }
+                    let parent = match words[2] {
+                        "-" => None,
+                        name => Some(joints.iter().position(|j| j.name == name)
+                            .ok_or(directives::error(kind, line, "parent must be declared before its children"))?),
+                    };
+                    let v = directives::floats(kind, line, &words[3..])?;
+                    joints.push(Joint {
+                        name: words[1].to_string(),
+                        parent: parent,
+                        rest: Transform {
+                            translation: Vector3::new(v[0], v[1], v[2]),
+                            rotation: Quaternion::new(v[3], v[4], v[5], v[6]).normalize(),
+                            scale: Vector3::new(v[7], v[8], v[9]),
+                        },
+                        inverse_bind: M4_IDENTITY,
+                    });
+                    explicit_bind.push(false);
+                },
+                "bind" if words.len() == 18 => {
+                    let i = joints.iter().position(|j| j.name == words[1])
+                        .ok_or(directives::error(kind, line, "unknown joint"))?;
+                    let v = directives::floats(kind, line, &words[2..])?;
+                    let mut m = [0.0; 16];
+                    m.copy_from_slice(&v);
+                    joints[i].inverse_bind = Matrix4::from_array(&m);
+                    explicit_bind[i] = true;
+                },
+                "joint" | "bind" => return Err(directives::error(kind, line, "wrong number of arguments")),
+                _ => return Err(directives::error(kind, line, "unknown directive")),
+            };
+        }
+        // Fill in the rest from the rest pose.
+        let mut world: Vec<Matrix4> = Vec::with_capacity(joints.len());
+        for (i, j) in joints.iter_mut().enumerate() {
+            let local = j.rest.to_matrix();
+            let w = match j.parent {
+                Some(p) => world[p] * local,
+                None => local,
+            };
+            world.push(w);
+            if !explicit_bind[i] {
+                j.inverse_bind = w.inverse().ok_or(Error::ParseError(
+                    format!("skeleton: rest pose of joint {} can't be inverted", j.name)))?;
+            }
+        }
+        return Ok(Skeleton { joints: joints });
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        return &self.joints;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.joints.len();
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        return self.joints.iter().position(|j| j.name == name);
+    }
+}
+
+// Keyframes for one channel, times ascending.
+#[derive(Clone)]
+pub struct Keys<T: Copy> {
+    pub times: Vec<f32>,
+    pub values: Vec<T>,
+}
+
+impl<T: Copy> Keys<T> {
+    pub fn new() -> Keys<T> {
+        return Keys { times: Vec::new(), values: Vec::new() };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.times.is_empty();
+    }
+
+    // Clamps outside the first and last key.
+    fn sample<F: Fn(T, T, f32) -> T>(&self, time: f32, interpolate: F) -> Option<T> {
+        let n = self.times.len();
+        if n == 0 {
+            return None;
+        }
+        let next = self.times.partition_point(|t| *t <= time);
+        if next == 0 {
+            return Some(self.values[0]);
+        }
+        if next == n {
+            return Some(self.values[n - 1]);
+        }
+        let (t0, t1) = (self.times[next - 1], self.times[next]);
+        let f = if t1 > t0 { (time - t0) / (t1 - t0) } else { 0.0 };
+        return Some(interpolate(self.values[next - 1], self.values[next], f));
+    }
+}
+
+#[derive(Clone)]
+pub struct Track {
+    pub joint: usize,
+    pub translation: Keys<Vector3>,
+    pub rotation: Keys<Quaternion>,
+    pub scale: Keys<Vector3>,
+}
+
+#[derive(Clone)]
+pub struct Clip {
+    pub name: String,
+    pub duration: f32,
+    pub looping: bool,
+    pub tracks: Vec<Track>,
+}
+
+impl Clip {
+    pub fn load(path: &str, skeleton: &Skeleton, activity: &Activity) -> Result<Clip> {
+        return Self::parse(&read_source(path, activity)?, skeleton);
+    }
+
+    // Joint names are resolved against the skeleton, so a clip
+    // only plays on the skeleton it was parsed for.
+    pub fn parse(text: &str, skeleton: &Skeleton) -> Result<Clip> {
+        let kind = "clip";
+        let mut clip = Clip { name: String::new(), duration: 0.0, looping: true, tracks: Vec::new() };
+        let mut header = false;
+        for (line, words) in directives::lines(text) {
+            match (words[0], words.len()) {
+                ("clip", 4) => {
+                    clip.name = words[1].to_string();
+                    clip.duration = directives::floats(kind, line, &words[2..3])?[0].max(0.0);
+                    clip.looping = match words[3] {
+                        "loop" => true,
+                        "once" => false,
+                        _ => return Err(directives::error(kind, line, "playback must be loop or once")),
+                    };
+                    header = true;
+                },
+                ("t", 6) | ("s", 6) | ("r", 7) => {
+                    let joint = skeleton.find(words[1]).ok_or(directives::error(kind, line, "unknown joint"))?;
+                    let v = directives::floats(kind, line, &words[2..])?;
+                    let i = match clip.tracks.iter().position(|t| t.joint == joint) {
+                        Some(i) => i,
+                        None => {
+                            clip.tracks.push(Track { joint: joint, translation: Keys::new(), rotation: Keys::new(), scale: Keys::new() });
+                            clip.tracks.len() - 1
+                        },
+                    };
+                    let track = &mut clip.tracks[i];
+                    let last = match words[0] {
+                        "t" => track.translation.times.last().copied(),
+                        "r" => track.rotation.times.last().copied(),
+                        _ => track.scale.times.last().copied(),
+                    };
+                    if last.map_or(false, |t| v[0] < t) {
+                        return Err(directives::error(kind, line, "keys must be in time order"));
+                    }
+                    match words[0] {
+                        "t" => {
+                            track.translation.times.push(v[0]);
+                            track.translation.values.push(Vector3::new(v[1], v[2], v[3]));
+                        },
+                        "r" => {
+                            track.rotation.times.push(v[0]);
+                            track.rotation.values.push(Quaternion::new(v[1], v[2], v[3], v[4]).normalize());
+                        },
+                        _ => {
+                            track.scale.times.push(v[0]);
+                            track.scale.values.push(Vector3::new(v[1], v[2], v[3]));
+                        },
+                    };
+                },
+                ("clip", _) | ("t", _) | ("r", _) | ("s", _) => return Err(directives::error(kind, line, "wrong number of arguments")),
+                _ => return Err(directives::error(kind, line, "unknown directive")),
+            };
+        }
+        if !header {
+            return Err(Error::ParseError("clip: missing clip line".to_string()));
+        }
+        return Ok(clip);
+    }
+
+    // Maps playback time into the clip.
+    pub fn local_time(&self, time: f32) -> f32 {
+        if self.duration <= 0.0 {
+            return 0.0;
+        }
+        return if self.looping { time.rem_euclid(self.duration) } else { time.clamp(0.0, self.duration) };
+    }
+
+    // Overwrites the animated channels of `pose`. Channels
+    // without keys keep whatever the pose already had.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        let t = self.local_time(time);
+        for track in &self.tracks {
+            let local = &mut pose.locals[track.joint];
+            if let Some(v) = track.translation.sample(t, lerp3) {
+                local.translation = v;
+            }
+            if let Some(q) = track.rotation.sample(t, |a, b, f| a.nlerp(b, f)) {
+                local.rotation = q;
+            }
+            if let Some(v) = track.scale.sample(t, lerp3) {
+                local.scale = v;
+            }
+        }
+    }
+}
+
+// Local transform of every joint in a skeleton.
+#[derive(Clone)]
+pub struct Pose {
+    pub locals: Vec<Transform>,
+}
+
+impl Pose {
+    pub fn rest(skeleton: &Skeleton) -> Pose {
+        return Pose { locals: skeleton.joints.iter().map(|j| j.rest).collect() };
+    }
+
+    pub fn reset(&mut self, skeleton: &Skeleton) {
+        self.locals.clear();
+        self.locals.extend(skeleton.joints.iter().map(|j| j.rest));
+    }
+
+    // Moves `t` of the way from this pose towards `other`.
+    pub fn blend(&mut self, other: &Pose, t: f32) {
+        for (a, b) in self.locals.iter_mut().zip(&other.locals) {
+            *a = a.blend(b, t);
+        }
+    }
+
+    // Model-space joint matrices times their inverse bind,
+    // ready for skinning. `out` is resized to fit.
+    pub fn skin_matrices(&self, skeleton: &Skeleton, out: &mut Vec<Matrix4>) {
+        out.clear();
+        let mut world: Vec<Matrix4> = Vec::with_capacity(self.locals.len());
+        for (joint, local) in skeleton.joints.iter().zip(&self.locals) {
+            let w = match joint.parent {
+                Some(p) => world[p] * local.to_matrix(),
+                None => local.to_matrix(),
+            };
+            world.push(w);
+            out.push(w * joint.inverse_bind);
+        }
+    }
+}
+
+pub struct Layer {
+    pub clip: Rc<Clip>,
+    pub time: f32,
+    pub speed: f32,
+    pub weight: f32,
+    // Weight change per second while cross-fading.
+    fade: f32,
+}
+
+// Plays and blends clips. Each layer is sampled and mixed by
+// weight; crossfade() ramps the current layers out while a
+// new one ramps in.
+pub struct Sampler {
+    layers: Vec<Layer>,
+    scratch: Pose,
+}
+
+impl Sampler {
+    pub fn new() -> Sampler {
+        return Sampler { layers: Vec::new(), scratch: Pose { locals: Vec::new() } };
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        return &self.layers;
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [Layer] {
+        return &mut self.layers;
+    }
+
+    // Adds a layer on top of what's playing.
+    pub fn play(&mut self, clip: Rc<Clip>, weight: f32) {
+        self.layers.push(Layer { clip: clip, time: 0.0, speed: 1.0, weight: weight, fade: 0.0 });
+    }
+
+    // Replaces everything playing with `clip` over `duration` seconds.
+    pub fn crossfade(&mut self, clip: Rc<Clip>, duration: f32) {
+        if duration <= 0.0 {
+            self.layers.clear();
+            self.play(clip, 1.0);
+            return;
+        }
+        for layer in self.layers.iter_mut() {
+            layer.fade = -1.0 / duration;
+        }
+        self.layers.push(Layer { clip: clip, time: 0.0, speed: 1.0, weight: 0.0, fade: 1.0 / duration });
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for layer in self.layers.iter_mut() {
+            layer.time += dt * layer.speed;
+            if layer.fade != 0.0 {
+                layer.weight = (layer.weight + layer.fade * dt).clamp(0.0, 1.0);
+                if layer.weight == 1.0 {
+                    layer.fade = 0.0;
+                }
+            }
+        }
+        self.layers.retain(|l| !(l.fade < 0.0 && l.weight == 0.0));
+    }
+
+    // Writes the blended pose. With nothing playing, or all
+    // weights zero, that's the rest pose.
+    pub fn evaluate(&mut self, skeleton: &Skeleton, pose: &mut Pose) {
+        pose.reset(skeleton);
+        let mut total = 0.0;
+        for layer in &self.layers {
+            if layer.weight <= 0.0 {
+                continue;
+            }
+            total += layer.weight;
+            if total == layer.weight {
+                layer.clip.sample(layer.time, pose);
+                continue;
+            }
+            // Running average keeps every layer at its share.
+            self.scratch.reset(skeleton);
+            layer.clip.sample(layer.time, &mut self.scratch);
+            pose.blend(&self.scratch, layer.weight / total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::directives::error_message;
+
+    const ANTENNA: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shared/rigs/antenna.skeleton"));
+    const SWAY: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shared/rigs/antenna_sway.clip"));
+
+    fn close(a: f32, b: f32) -> bool {
+        return (a - b).abs() < 1e-4;
+    }
+
+    #[test]
+    fn parses_the_antenna() {
+        let skeleton = Skeleton::parse(ANTENNA).unwrap();
+        assert_eq!(skeleton.len(), 3);
+        let parents: Vec<Option<usize>> = skeleton.joints().iter().map(|j| j.parent).collect();
+        assert_eq!(parents, vec![None, Some(0), Some(1)]);
+        assert_eq!(skeleton.find("tip"), Some(2));
+        // Without bind lines, the bind pose is the rest pose,
+        // which puts the tip 0.8 up.
+        let tip = skeleton.joints()[2].inverse_bind.to_array();
+        assert!(close(tip[13], -0.8));
+    }
+
+    #[test]
+    fn parses_the_sway() {
+        let skeleton = Skeleton::parse(ANTENNA).unwrap();
+        let clip = Clip::parse(SWAY, &skeleton).unwrap();
+        assert_eq!((clip.name.as_str(), clip.duration, clip.looping), ("sway", 2.0, true));
+        assert_eq!(clip.tracks.len(), 2);
+        assert_eq!(clip.tracks[0].joint, 1);
+        assert_eq!(clip.tracks[0].rotation.times, vec![0.0, 0.5, 1.5, 2.0]);
+        assert!(clip.tracks[0].translation.is_empty());
+        assert!(close(clip.local_time(2.5), 0.5));
+        let mut pose = Pose::rest(&skeleton);
+        clip.sample(2.5, &mut pose);
+        let q = pose.locals[2].rotation;
+        assert!(close(q.z, 0.1305) && close(q.w, 0.9914));
+        // Unanimated channels keep the rest pose.
+        assert!(close(pose.locals[2].translation.y, 0.4));
+    }
+
+    // One joint, held at x by a single translation key.
+    fn hold(x: f32) -> Rc<Clip> {
+        let mut track = Track { joint: 0, translation: Keys::new(), rotation: Keys::new(), scale: Keys::new() };
+        track.translation.times.push(0.0);
+        track.translation.values.push(Vector3::new(x, 0.0, 0.0));
+        return Rc::new(Clip { name: "hold".to_string(), duration: 1.0, looping: true, tracks: vec![track] });
+    }
+
+    fn x_after(sampler: &mut Sampler, skeleton: &Skeleton) -> f32 {
+        let mut pose = Pose::rest(skeleton);
+        sampler.evaluate(skeleton, &mut pose);
+        return pose.locals[0].translation.x;
+    }
+
+    #[test]
+    fn crossfades_through_the_midpoint() {
+        let skeleton = Skeleton::parse("joint root - 0 0 0 0 0 0 1 1 1 1").unwrap();
+        let mut sampler = Sampler::new();
+        assert_eq!(x_after(&mut sampler, &skeleton), 0.0);
+        sampler.crossfade(hold(2.0), 0.0);
+        assert_eq!(x_after(&mut sampler, &skeleton), 2.0);
+        sampler.crossfade(hold(4.0), 1.0);
+        sampler.update(0.5);
+        let weights: Vec<f32> = sampler.layers().iter().map(|l| l.weight).collect();
+        assert!(close(weights[0], 0.5) && close(weights[1], 0.5));
+        assert!(close(x_after(&mut sampler, &skeleton), 3.0));
+        // Once it's done, the old layer is gone.
+        sampler.update(0.6);
+        assert_eq!(sampler.layers().len(), 1);
+        assert_eq!(sampler.layers()[0].weight, 1.0);
+        assert_eq!(x_after(&mut sampler, &skeleton), 4.0);
+    }
+
+    #[test]
+    fn weights_are_renormalized() {
+        let skeleton = Skeleton::parse("joint root - 0 0 0 0 0 0 1 1 1 1").unwrap();
+        let mut sampler = Sampler::new();
+        // A lone layer plays fully whatever its weight.
+        sampler.play(hold(4.0), 0.25);
+        assert_eq!(x_after(&mut sampler, &skeleton), 4.0);
+        sampler.play(hold(1.0), 0.75);
+        assert!(close(x_after(&mut sampler, &skeleton), 1.75));
+        sampler.layers_mut()[0].weight = 3.0;
+        sampler.layers_mut()[1].weight = 3.0;
+        assert!(close(x_after(&mut sampler, &skeleton), 2.5));
+    }
+
+    #[test]
+    fn skin_matrices_follow_the_pose() {
+        let skeleton = Skeleton::parse(ANTENNA).unwrap();
+        let mut pose = Pose::rest(&skeleton);
+        let mut skin = Vec::new();
+        pose.skin_matrices(&skeleton, &mut skin);
+        assert_eq!(skin.len(), 3);
+        // The rest pose is the bind pose, so nothing moves.
+        for m in &skin {
+            assert!(m.to_array().iter().zip(M4_IDENTITY.to_array().iter()).all(|(a, b)| close(*a, *b)));
+        }
+        // Moving the root carries every joint with it.
+        pose.locals[0].translation.x += 1.0;
+        pose.skin_matrices(&skeleton, &mut skin);
+        for m in &skin {
+            assert!(close(m.to_array()[12], 1.0) && close(m.to_array()[13], 0.0));
+        }
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(error_message(Skeleton::parse("joint a b 0 0 0 0 0 0 1 1 1 1")),
+            "skeleton line 1: parent must be declared before its children");
+        assert_eq!(error_message(Skeleton::parse("joint a - 0 0 0 0 0 0 1 1 1 1\njoint a - 0 0 0 0 0 0 1 1 1 1")),
+            "skeleton line 2: duplicate joint name");
+        assert_eq!(error_message(Skeleton::parse("joint a - 0 0 0")), "skeleton line 1: wrong number of arguments");
+        let skeleton = Skeleton::parse(ANTENNA).unwrap();
+        assert_eq!(error_message(Clip::parse("clip a 1 loop\nr antenna 0 0 0 0 1", &skeleton)),
+            "clip line 2: unknown joint");
+        assert_eq!(error_message(Clip::parse("clip a 1 loop\nt tip 0.5 0 0 0\nt tip 0.2 0 0 0", &skeleton)),
+            "clip line 3: keys must be in time order");
+        assert_eq!(error_message(Clip::parse("clip a 1 twice", &skeleton)),
+            "clip line 1: playback must be loop or once");
+        assert_eq!(error_message(Clip::parse("t tip 0 0 0 0", &skeleton)), "clip: missing clip line");
+    }
+}
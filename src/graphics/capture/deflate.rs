@@ -0,0 +1,407 @@
+use crate::graphics::{Result, Error};
+
+// Just enough of zlib (RFC 1950) and deflate (RFC 1951) for
+// PNG. Compression uses LZ77 with the fixed Huffman tables,
+// which gets most of the way there for rendered frames
+// without building dynamic trees. Decompression handles all
+// three block types, so PNGs from other tools load too.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order code length code lengths are stored in.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+// How far down a hash chain to look for a longer match.
+const MAX_CHAIN: usize = 64;
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the most bytes before the sums can overflow.
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    return (b << 16) | a;
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go out most significant bit first.
+    fn put_code(&mut self, code: u32, count: u32) {
+        self.put(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        return self.out;
+    }
+}
+
+fn put_literal(w: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => w.put_code(0x30 + symbol, 8),
+        144..=255 => w.put_code(0x190 + symbol - 144, 9),
+        256..=279 => w.put_code(symbol - 256, 7),
+        _ => w.put_code(0xc0 + symbol - 280, 8),
+    };
+}
+
+fn put_match(w: &mut BitWriter, length: usize, distance: usize) {
+    let li = LENGTH_BASE.iter().rposition(|b| *b as usize <= length).unwrap();
+    put_literal(w, 257 + li as u32);
+    w.put((length - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+    let di = DIST_BASE.iter().rposition(|b| *b as usize <= distance).unwrap();
+    w.put_code(di as u32, 5);
+    w.put((distance - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+    return (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize;
+}
+
+// zlib stream holding a single fixed-Huffman block.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: vec![0x78, 0x01], bits: 0, count: 0 };
+    // BFINAL = 1, BTYPE = 01 (fixed).
+    w.put(1, 1);
+    w.put(1, 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(data, i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(data, i)];
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(&data[i..i + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW];
+                // Stale slots from further back than the window.
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+        if best_len >= MIN_MATCH {
+            put_match(&mut w, best_len, best_dist);
+            for j in i..i + best_len {
+                insert(&mut head, &mut prev, j);
+            }
+            i += best_len;
+        } else {
+            put_literal(&mut w, data[i] as u32);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    put_literal(&mut w, 256);
+    let mut out = w.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    return out;
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn get(&mut self, count: u32) -> Result<u32> {
+        while self.count < count {
+            let byte = *self.data.get(self.pos).ok_or(Error::InvalidFormat("truncated deflate stream"))?;
+            self.pos += 1;
+            self.bits |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1u64 << count) - 1) as u32;
+        self.bits = if count == 32 { 0 } else { self.bits >> count };
+        self.count -= count;
+        return Ok(value);
+    }
+
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+// Canonical Huffman table as counts per code length
+// and symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for l in lengths {
+            counts[*l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, l) in lengths.iter().enumerate() {
+            if *l != 0 {
+                symbols[offsets[*l as usize] as usize] = symbol as u16;
+                offsets[*l as usize] += 1;
+            }
+        }
+        return Huffman { counts: counts, symbols: symbols };
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= r.get(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err(Error::InvalidFormat("bad Huffman code in deflate stream"));
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (i, l) in lengths.iter_mut().enumerate() {
+        *l = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    return (Huffman::new(&lengths), Huffman::new(&[5u8; 30]));
+}
+
+fn dynamic_tables(r: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let nlen = r.get(5)? as usize + 257;
+    let ndist = r.get(5)? as usize + 1;
+    let ncode = r.get(4)? as usize + 4;
+    let mut clen = [0u8; 19];
+    for i in 0..ncode {
+        clen[CLEN_ORDER[i]] = r.get(3)? as u8;
+    }
+    let clen_table = Huffman::new(&clen);
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = clen_table.decode(r)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(Error::InvalidFormat("deflate length repeat with no previous length"));
+                }
+                (lengths[i - 1], 3 + r.get(2)? as usize)
+            },
+            17 => (0, 3 + r.get(3)? as usize),
+            _ => (0, 11 + r.get(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(Error::InvalidFormat("deflate code lengths overrun"));
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    return Ok((Huffman::new(&lengths[..nlen]), Huffman::new(&lengths[nlen..])));
+}
+
+fn inflate_block(r: &mut BitReader, lit: &Huffman, dist: &Huffman, out: &mut Vec<u8>) -> Result<()> {
+    loop {
+        let symbol = lit.decode(r)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let li = symbol - 257;
+        if li >= LENGTH_BASE.len() {
+            return Err(Error::InvalidFormat("bad deflate length symbol"));
+        }
+        let length = LENGTH_BASE[li] as usize + r.get(LENGTH_EXTRA[li] as u32)? as usize;
+        let di = dist.decode(r)? as usize;
+        if di >= DIST_BASE.len() {
+            return Err(Error::InvalidFormat("bad deflate distance symbol"));
+        }
+        let distance = DIST_BASE[di] as usize + r.get(DIST_EXTRA[di] as u32)? as usize;
+        if distance > out.len() {
+            return Err(Error::InvalidFormat("deflate distance before start of data"));
+        }
+        // Copies may overlap what they produce, so go bytewise.
+        let start = out.len() - distance;
+        for k in 0..length {
+            out.push(out[start + k]);
+        }
+    }
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 6 || data[0] & 0x0f != 8 || ((data[0] as u16) << 8 | data[1] as u16) % 31 != 0 {
+        return Err(Error::InvalidFormat("bad zlib header"));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(Error::InvalidFormat("zlib preset dictionaries are not supported"));
+    }
+    let mut r = BitReader { data: data, pos: 2, bits: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = r.get(1)? == 1;
+        match r.get(2)? {
+            0 => {
+                r.align();
+                let header = data.get(r.pos..r.pos + 4).ok_or(Error::InvalidFormat("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let nlen = u16::from_le_bytes([header[2], header[3]]) as usize;
+                if len != !nlen & 0xffff {
+                    return Err(Error::InvalidFormat("stored block length mismatch"));
+                }
+                r.pos += 4;
+                out.extend_from_slice(data.get(r.pos..r.pos + len).ok_or(Error::InvalidFormat("truncated stored block"))?);
+                r.pos += len;
+            },
+            1 => {
+                let (lit, dist) = fixed_tables();
+                inflate_block(&mut r, &lit, &dist, &mut out)?;
+            },
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut r)?;
+                inflate_block(&mut r, &lit, &dist, &mut out)?;
+            },
+            _ => return Err(Error::InvalidFormat("bad deflate block type")),
+        };
+        if last {
+            break;
+        }
+    }
+    // Leftover bits belong to the last byte read.
+    r.align();
+    let checksum = data.get(r.pos..r.pos + 4).ok_or(Error::InvalidFormat("missing zlib checksum"))?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&out) {
+        return Err(Error::InvalidFormat("zlib checksum mismatch"));
+    }
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x12345678u32;
+        return (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        }).collect();
+    }
+
+    #[test]
+    fn round_trips() {
+        let repetitive: Vec<u8> = b"abcabcabd".iter().copied().cycle().take(3 * WINDOW).collect();
+        for data in [Vec::new(), b"a".to_vec(), repetitive, noise(100_000)] {
+            assert_eq!(decompress(&compress(&data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn compresses_runs() {
+        assert!(compress(&vec![7u8; 10_000]).len() < 200);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn reads_stored_blocks() {
+        let data = b"stored, not compressed";
+        let mut stream = vec![0x78, 0x01, 0x01];
+        stream.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(data.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(data);
+        stream.extend_from_slice(&adler32(data).to_be_bytes());
+        assert_eq!(decompress(&stream).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        assert!(decompress(&[0x78, 0x02, 0, 0, 0, 0]).is_err());
+        let mut truncated = compress(&noise(1000));
+        truncated.truncate(100);
+        assert!(decompress(&truncated).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+pub mod deflate;
+pub mod png;
+
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::debug::gl_call;
+use crate::bridge::activity::Activity;
+use std::path::{Path, PathBuf};
+
+// Frame capture for screenshots and golden-image tests.
+// Frames are read back from whatever framebuffer is bound,
+// saved as PNG, and compared against references with diff().
+
+// Tightly packed 8-bit RGBA, top row first.
+#[derive(Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        return Image { width: width, height: height, pixels: vec![0; width as usize * height as usize * 4] };
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        return [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]];
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    pub fn flip_vertical(&mut self) {
+        let stride = self.width as usize * 4;
+        let rows = self.height as usize;
+        for y in 0..rows / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((rows - 1 - y) * stride);
+            top[y * stride..(y + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+        }
+    }
+
+    pub fn from_png(data: &[u8]) -> Result<Image> {
+        let (width, height, pixels) = png::decode(data)?;
+        return Ok(Image { width: width, height: height, pixels: pixels });
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        return png::encode(self.width, self.height, &self.pixels);
+    }
+
+    // Host filesystem paths, for tests and tools.
+    pub fn load(path: &Path) -> Result<Image> {
+        return Self::from_png(&std::fs::read(path)?);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_png())?;
+        return Ok(());
+    }
+
+    // Saves under captures/ in the app's internal storage
+    // and returns where it went.
+    pub fn save_internal(&self, name: &str, activity: &Activity) -> Result<PathBuf> {
+        let path = activity.internal_path().join("captures").join(name);
+        self.save(&path)?;
+        return Ok(path);
+    }
+}
+
+// Reads a rectangle of the bound framebuffer. GL rows start
+// at the bottom, so the result is flipped to match PNG.
+pub unsafe fn read_pixels(x: i32, y: i32, width: i32, height: i32) -> Image {
+    let mut image = Image::new(width.max(0) as u32, height.max(0) as u32);
+    // RGBA rows are always 4-byte aligned.
+    gl_call!(PixelStorei(::gl::PACK_ALIGNMENT, 4));
+    gl_call!(ReadPixels(x, y, width, height, ::gl::RGBA, ::gl::UNSIGNED_BYTE,
+        image.pixels.as_mut_ptr() as *mut std::ffi::c_void));
+    image.flip_vertical();
+    return image;
+}
+
+// Reads everything inside the current viewport.
+pub unsafe fn read_viewport() -> Image {
+    let mut v: [GLint; 4] = [0; 4];
+    gl_call!(GetIntegerv(::gl::VIEWPORT, v.as_mut_ptr()));
+    return read_pixels(v[0], v[1], v[2], v[3]);
+}
+
+pub struct Diff {
+    // Pixels where some channel differed by more than the tolerance.
+    pub mismatched: usize,
+    pub max_delta: u8,
+    // Mismatches in red over a dimmed copy of the expected image.
+    pub image: Image,
+}
+
+impl Diff {
+    pub fn passed(&self, allowed_mismatches: usize) -> bool {
+        return self.mismatched <= allowed_mismatches;
+    }
+}
+
+// Compares two images channel by channel. Differences up to
+// `tolerance` are ignored, which absorbs GPU rounding noise.
+pub fn diff(expected: &Image, actual: &Image, tolerance: u8) -> Result<Diff> {
+    if expected.width != actual.width || expected.height != actual.height {
+        return Err(Error::InvalidFormat("compared images differ in size"));
+    }
+    let mut result = Diff { mismatched: 0, max_delta: 0, image: Image::new(expected.width, expected.height) };
+    let pixels = expected.pixels.chunks_exact(4).zip(actual.pixels.chunks_exact(4));
+    for ((e, a), out) in pixels.zip(result.image.pixels.chunks_exact_mut(4)) {
+        let delta = (0..4).map(|c| (e[c] as i16 - a[c] as i16).unsigned_abs() as u8).max().unwrap();
+        result.max_delta = result.max_delta.max(delta);
+        if delta > tolerance {
+            result.mismatched += 1;
+            // Brighter red for bigger differences, but always visible.
+            out.copy_from_slice(&[128 + delta / 2, 0, 0, 255]);
+        } else {
+            let grey = ((e[0] as u16 * 77 + e[1] as u16 * 150 + e[2] as u16 * 29) >> 10) as u8;
+            out.copy_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+    return Ok(result);
+}
@@ -0,0 +1,239 @@
+use crate::graphics::{Result, Error};
+use crate::graphics::capture::deflate;
+use crate::utils::ByteReader;
+
+// PNG reading and writing for capture and golden images.
+// Writes 8-bit RGBA. Reads non-interlaced 8-bit grey, grey
+// with alpha, RGB and RGBA, always returning RGBA.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    return table;
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut c = 0xffffffffu32;
+    for part in parts {
+        for byte in *part {
+            c = CRC_TABLE[((c ^ *byte as u32) & 0xff) as usize] ^ (c >> 8);
+        }
+    }
+    return c ^ 0xffffffff;
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc32(&[kind, data]).to_be_bytes());
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        return a;
+    }
+    if pb <= pc {
+        return b;
+    }
+    return c;
+}
+
+// Applies filter `kind` to `row` into `out`, given the
+// unfiltered previous row (zeros for the first).
+fn filter_row(kind: u8, row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(kind);
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match kind {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+// Encodes tightly packed RGBA rows, top row first.
+pub fn encode(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 4;
+    assert!(rgba.len() >= stride * height as usize);
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    let zeros = vec![0u8; stride];
+    let mut candidate = Vec::with_capacity(stride + 1);
+    let mut best = Vec::with_capacity(stride + 1);
+    for y in 0..height as usize {
+        let row = &rgba[y * stride..(y + 1) * stride];
+        let prev = if y == 0 { &zeros[..] } else { &rgba[(y - 1) * stride..y * stride] };
+        // Usual heuristic: keep the filter whose output has the
+        // smallest sum of magnitudes as signed bytes.
+        let mut best_score = u64::MAX;
+        for kind in 0..5 {
+            candidate.clear();
+            filter_row(kind, row, prev, 4, &mut candidate);
+            let score: u64 = candidate[1..].iter().map(|v| (*v as i8).unsigned_abs() as u64).sum();
+            if score < best_score {
+                best_score = score;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+        raw.extend_from_slice(&best);
+    }
+    let mut out = SIGNATURE.to_vec();
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits, RGBA, deflate, adaptive filtering, no interlace.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &deflate::compress(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    return out;
+}
+
+// Returns width, height and RGBA pixels, top row first.
+pub fn decode(data: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    let bad = |msg: &'static str| Error::InvalidFormat(msg);
+    let mut reader = ByteReader::new(data);
+    if reader.bytes(8) != Some(&SIGNATURE[..]) {
+        return Err(bad("not a PNG file"));
+    }
+    let mut header: Option<(u32, u32, usize)> = None;
+    let mut compressed = Vec::new();
+    loop {
+        let len = reader.u32_be().ok_or(bad("truncated PNG chunk"))? as usize;
+        let kind = reader.bytes(4).ok_or(bad("truncated PNG chunk"))?;
+        let body = reader.bytes(len).ok_or(bad("truncated PNG chunk"))?;
+        let crc = reader.u32_be().ok_or(bad("truncated PNG chunk"))?;
+        if crc != crc32(&[kind, body]) {
+            return Err(bad("PNG chunk CRC mismatch"));
+        }
+        match kind {
+            b"IHDR" => {
+                let mut h = ByteReader::new(body);
+                let width = h.u32_be().ok_or(bad("short IHDR"))?;
+                let height = h.u32_be().ok_or(bad("short IHDR"))?;
+                let fields = h.bytes(5).ok_or(bad("short IHDR"))?;
+                if fields[0] != 8 {
+                    return Err(bad("only 8-bit PNGs are supported"));
+                }
+                if fields[4] != 0 {
+                    return Err(bad("interlaced PNGs are not supported"));
+                }
+                let channels = match fields[1] {
+                    0 => 1,
+                    2 => 3,
+                    4 => 2,
+                    6 => 4,
+                    _ => return Err(bad("unsupported PNG color type")),
+                };
+                header = Some((width, height, channels));
+            },
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {},
+        };
+    }
+    let (width, height, channels) = header.ok_or(bad("PNG has no IHDR"))?;
+    let raw = deflate::decompress(&compressed)?;
+    let stride = width as usize * channels;
+    if raw.len() < (stride + 1) * height as usize {
+        return Err(bad("PNG image data is too short"));
+    }
+    let mut pixels = vec![0u8; stride * height as usize];
+    for y in 0..height as usize {
+        let kind = raw[y * (stride + 1)];
+        let src = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = pixels.split_at_mut(y * stride);
+        let prev = if y == 0 { None } else { Some(&done[(y - 1) * stride..]) };
+        let row = &mut rest[..stride];
+        for i in 0..stride {
+            let a = if i >= channels { row[i - channels] } else { 0 };
+            let b = prev.map_or(0, |p| p[i]);
+            let c = if i >= channels { prev.map_or(0, |p| p[i - channels]) } else { 0 };
+            let predicted = match kind {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(bad("bad PNG filter type")),
+            };
+            row[i] = src[i].wrapping_add(predicted);
+        }
+    }
+    if channels == 4 {
+        return Ok((width, height, pixels));
+    }
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for p in pixels.chunks_exact(channels) {
+        match channels {
+            1 => rgba.extend_from_slice(&[p[0], p[0], p[0], 255]),
+            2 => rgba.extend_from_slice(&[p[0], p[0], p[0], p[1]]),
+            _ => rgba.extend_from_slice(&[p[0], p[1], p[2], 255]),
+        };
+    }
+    return Ok((width, height, rgba));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_rgba() {
+        let (width, height) = (37u32, 19u32);
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                rgba.extend_from_slice(&[(x * 7) as u8, (y * 13) as u8, (x * y) as u8, 255 - x as u8]);
+            }
+        }
+        let (w, h, decoded) = decode(&encode(width, height, &rgba)).unwrap();
+        assert_eq!((w, h), (width, height));
+        assert_eq!(decoded, rgba);
+    }
+
+    #[test]
+    fn expands_rgb_to_rgba() {
+        // 2x1 RGB, no filtering.
+        let mut png = SIGNATURE.to_vec();
+        let mut header = Vec::new();
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&1u32.to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &deflate::compress(&[0, 10, 20, 30, 40, 50, 60]));
+        write_chunk(&mut png, b"IEND", &[]);
+        assert_eq!(decode(&png).unwrap(), (2, 1, vec![10, 20, 30, 255, 40, 50, 60, 255]));
+    }
+
+    #[test]
+    fn rejects_corrupt_chunks() {
+        let mut png = encode(1, 1, &[1, 2, 3, 4]);
+        assert!(decode(&png[1..]).is_err());
+        // Flip a bit in the IHDR body.
+        png[20] ^= 1;
+        assert!(matches!(decode(&png), Err(Error::InvalidFormat("PNG chunk CRC mismatch"))));
+    }
+}
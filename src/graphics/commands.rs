@@ -0,0 +1,508 @@
+use ::gl::types::*;
+use crate::graphics::handle::{Handle, Kind, BufferHandle, TextureHandle, ProgramHandle, FramebufferHandle};
+use crate::graphics::texture::{Texture, PixelFormat, Filter, upload_size};
+use crate::graphics::pipeline::Pipeline;
+use crate::graphics::gl::Context;
+use crate::graphics::state;
+use crate::graphics::debug::gl_call;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
+use std::sync::{Condvar, Mutex};
+
+// Recorded rendering. Game code on any thread fills a
+// CommandList with draws, uploads and state changes, all
+// in terms of handles rather than GL names, and submits a
+// frame's worth of lists to QUEUE. The render thread takes
+// the newest frame and replays it with execute_pending().
+//
+// The queue is double-buffered: one frame can wait while
+// the render thread replays another. submit() blocks while
+// that slot is full, so simulation runs at most one frame
+// ahead; try_submit() hands the frame back instead.
+//
+// When no new frame has arrived, the last one is replayed
+// without its uploads, so the screen never goes stale.
+//
+// Game threads can't make GL objects themselves, so they
+// create buffers and textures with create_buffer() and
+// create_texture(), which reserve a handle and record a
+// command that makes the object on the render thread. Such
+// objects aren't in restore's registry: after a context
+// loss the create runs again on the next replay, but the
+// contents are gone until the game uploads them again.
+
+#[derive(Clone, Debug)]
+pub enum Uniform {
+    Int(i32),
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Mat4([f32; 16]),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexAttrib {
+    pub location: GLuint,
+    pub components: GLint,
+    // FLOAT, UNSIGNED_BYTE etc.
+    pub kind: GLenum,
+    pub normalized: bool,
+    pub stride: GLsizei,
+    pub offset: usize,
+}
+
+impl VertexAttrib {
+    pub fn float(location: GLuint, components: GLint, stride: GLsizei, offset: usize) -> VertexAttrib {
+        return VertexAttrib {
+            location: location,
+            components: components,
+            kind: ::gl::FLOAT,
+            normalized: false,
+            stride: stride,
+            offset: offset,
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    // None for the default framebuffer.
+    BindFramebuffer(Option<FramebufferHandle>),
+    Viewport(i32, i32, i32, i32),
+    Scissor(i32, i32, i32, i32),
+    Clear { color: Option<[f32; 4]>, depth: Option<f32> },
+    SetEnabled(GLenum, bool),
+    BlendFunc(GLenum, GLenum),
+    DepthMask(bool),
+    DepthFunc(GLenum),
+    CullFace(GLenum),
+    Pipeline(Pipeline),
+    UseProgram(ProgramHandle),
+    // Applies to the program from the last UseProgram.
+    Uniform(String, Uniform),
+    BindTexture(u32, TextureHandle),
+    UploadBuffer { buffer: BufferHandle, target: GLenum, usage: GLenum, data: Vec<u8> },
+    UploadTexture { texture: TextureHandle, x: i32, y: i32, width: i32, height: i32, format: PixelFormat, pixels: Vec<u8> },
+    // Fill in a reserved handle, or one that was lost. Does
+    // nothing while the handle has an object.
+    CreateBuffer(BufferHandle),
+    CreateTexture { texture: TextureHandle, width: i32, height: i32, format: PixelFormat, filter: Filter },
+    Draw {
+        vertices: BufferHandle,
+        attributes: Vec<VertexAttrib>,
+        // Index buffer and its index type.
+        indices: Option<(BufferHandle, GLenum)>,
+        mode: GLenum,
+        first: i32,
+        count: i32,
+    },
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct CommandList {
+    commands: Vec<Command>,
+}
+
+impl CommandList {
+    pub fn new() -> CommandList {
+        return CommandList { commands: Vec::new() };
+    }
+
+    pub fn push(&mut self, command: Command) -> &mut CommandList {
+        self.commands.push(command);
+        return self;
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        return &self.commands;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.commands.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.commands.is_empty();
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub fn bind_framebuffer(&mut self, framebuffer: Option<FramebufferHandle>) -> &mut CommandList {
+        return self.push(Command::BindFramebuffer(framebuffer));
+    }
+
+    pub fn viewport(&mut self, x: i32, y: i32, width: i32, height: i32) -> &mut CommandList {
+        return self.push(Command::Viewport(x, y, width, height));
+    }
+
+    pub fn clear_color(&mut self, color: [f32; 4]) -> &mut CommandList {
+        return self.push(Command::Clear { color: Some(color), depth: Some(1.0) });
+    }
+
+    pub fn set_enabled(&mut self, capability: GLenum, enabled: bool) -> &mut CommandList {
+        return self.push(Command::SetEnabled(capability, enabled));
+    }
+
+    pub fn pipeline(&mut self, pipeline: Pipeline) -> &mut CommandList {
+        return self.push(Command::Pipeline(pipeline));
+    }
+
+    pub fn use_program(&mut self, program: ProgramHandle) -> &mut CommandList {
+        return self.push(Command::UseProgram(program));
+    }
+
+    pub fn uniform(&mut self, name: &str, value: Uniform) -> &mut CommandList {
+        return self.push(Command::Uniform(name.to_string(), value));
+    }
+
+    pub fn bind_texture(&mut self, unit: u32, texture: TextureHandle) -> &mut CommandList {
+        return self.push(Command::BindTexture(unit, texture));
+    }
+
+    // A buffer the render thread creates when it replays
+    // this list. Give it contents with upload_buffer().
+    pub fn create_buffer(&mut self) -> BufferHandle {
+        let buffer = BufferHandle::reserve();
+        self.push(Command::CreateBuffer(buffer));
+        return buffer;
+    }
+
+    // A texture the render thread creates, with undefined
+    // contents, when it replays this list.
+    pub fn create_texture(&mut self, width: i32, height: i32, format: PixelFormat, filter: Filter) -> TextureHandle {
+        let texture = TextureHandle::reserve();
+        self.push(Command::CreateTexture { texture: texture, width: width, height: height, format: format, filter: filter });
+        return texture;
+    }
+
+    pub fn upload_buffer(&mut self, buffer: BufferHandle, target: GLenum, data: Vec<u8>) -> &mut CommandList {
+        return self.push(Command::UploadBuffer { buffer: buffer, target: target, usage: ::gl::DYNAMIC_DRAW, data: data });
+    }
+
+    pub fn draw_arrays(&mut self, vertices: BufferHandle, attributes: &[VertexAttrib], mode: GLenum, first: i32, count: i32) -> &mut CommandList {
+        return self.push(Command::Draw {
+            vertices: vertices,
+            attributes: attributes.to_vec(),
+            indices: None,
+            mode: mode,
+            first: first,
+            count: count,
+        });
+    }
+
+    pub fn draw_elements(&mut self, vertices: BufferHandle, attributes: &[VertexAttrib], indices: BufferHandle,
+      index_type: GLenum, mode: GLenum, count: i32) -> &mut CommandList {
+        return self.push(Command::Draw {
+            vertices: vertices,
+            attributes: attributes.to_vec(),
+            indices: Some((indices, index_type)),
+            mode: mode,
+            first: 0,
+            count: count,
+        });
+    }
+}
+
+pub type Frame = Vec<CommandList>;
+
+pub struct FrameQueue {
+    ready: Mutex<Option<Frame>>,
+    taken: Condvar,
+}
+
+impl FrameQueue {
+    pub const fn new() -> FrameQueue {
+        return FrameQueue { ready: Mutex::new(None), taken: Condvar::new() };
+    }
+
+    // Waits for the render thread to take the previous frame.
+    pub fn submit(&self, frame: Frame) {
+        let mut ready = self.ready.lock().unwrap();
+        while ready.is_some() {
+            ready = self.taken.wait(ready).unwrap();
+        }
+        *ready = Some(frame);
+    }
+
+    // Gives the frame back if the previous one is still waiting.
+    pub fn try_submit(&self, frame: Frame) -> std::result::Result<(), Frame> {
+        let mut ready = self.ready.lock().unwrap();
+        if ready.is_some() {
+            return Err(frame);
+        }
+        *ready = Some(frame);
+        return Ok(());
+    }
+
+    pub fn take(&self) -> Option<Frame> {
+        let frame = self.ready.lock().unwrap().take();
+        if frame.is_some() {
+            self.taken.notify_all();
+        }
+        return frame;
+    }
+}
+
+pub static QUEUE: FrameQueue = FrameQueue::new();
+
+// Whether a create command should make an object: the
+// handle is still wanted but has nothing behind it.
+fn needs_object<K: Kind>(handle: Handle<K>) -> bool {
+    return handle.is_alive() && handle.get().is_none();
+}
+
+// Replays lists on the render thread. Uniform locations are
+// cached per program, since lists carry uniform names.
+// Pipelines are checked against the context before they're
+// applied, since whoever recorded them had none.
+pub struct Executor {
+    program: Option<GLuint>,
+    locations: HashMap<(GLuint, String), GLint>,
+    last: Frame,
+    context: Option<Context>,
+    // Already reported as unsupported, to log each once.
+    rejected: Vec<Pipeline>,
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        return Executor { program: None, locations: HashMap::new(), last: Vec::new(), context: None, rejected: Vec::new() };
+    }
+
+    // Call when the context changes; GL names get reused.
+    pub fn reset(&mut self, context: &Context) {
+        self.program = None;
+        self.locations.clear();
+        self.context = Some(context.clone());
+        self.rejected.clear();
+    }
+
+    // Unsupported pipelines are skipped, leaving the state
+    // as it was.
+    unsafe fn apply_pipeline(&mut self, pipeline: &Pipeline) {
+        let result = match self.context.as_ref() {
+            Some(context) => pipeline.check(context),
+            None => Ok(()),
+        };
+        match result {
+            Ok(_) => pipeline.apply(),
+            Err(e) => if !self.rejected.contains(pipeline) {
+                log::error!("Skipped a pipeline the context can't do: {:?}", e);
+                self.rejected.push(*pipeline);
+            },
+        };
+    }
+
+    unsafe fn location(&mut self, name: &str) -> GLint {
+        let program = match self.program {
+            Some(p) => p,
+            None => return -1,
+        };
+        if let Some(loc) = self.locations.get(&(program, name.to_string())) {
+            return *loc;
+        }
+        let loc = match CString::new(name) {
+            Ok(cname) => gl_call!(GetUniformLocation(program, cname.as_ptr())),
+            Err(_) => -1,
+        };
+        self.locations.insert((program, name.to_string()), loc);
+        return loc;
+    }
+
+    // Commands naming freed or lost objects are skipped.
+    // With `repeat` set, uploads are skipped too.
+    pub unsafe fn execute(&mut self, list: &CommandList, repeat: bool) {
+        for command in list.commands.iter() {
+            match command {
+                Command::BindFramebuffer(None) => state::bind_framebuffer(0),
+                Command::BindFramebuffer(Some(h)) => if let Some(id) = h.get() {
+                    state::bind_framebuffer(id);
+                },
+                Command::Viewport(x, y, w, h) => state::viewport(*x, *y, *w, *h),
+                Command::Scissor(x, y, w, h) => state::scissor(*x, *y, *w, *h),
+                Command::Clear { color, depth } => {
+                    let mut mask = 0;
+                    if let Some(c) = color {
+                        // Likewise colour writes, which a pipeline may mask.
+                        state::color_mask([true; 4]);
+                        gl_call!(ClearColor(c[0], c[1], c[2], c[3]));
+                        mask |= ::gl::COLOR_BUFFER_BIT;
+                    }
+                    if let Some(d) = depth {
+                        // Depth writes must be on for the clear to reach it.
+                        state::depth_mask(true);
+                        gl_call!(ClearDepthf(*d));
+                        mask |= ::gl::DEPTH_BUFFER_BIT;
+                    }
+                    gl_call!(Clear(mask));
+                },
+                Command::SetEnabled(cap, enabled) => state::set_enabled(*cap, *enabled),
+                Command::BlendFunc(src, dst) => state::blend_func(*src, *dst),
+                Command::DepthMask(enabled) => state::depth_mask(*enabled),
+                Command::DepthFunc(func) => state::depth_func(*func),
+                Command::CullFace(face) => state::cull_face(*face),
+                Command::Pipeline(pipeline) => self.apply_pipeline(pipeline),
+                Command::UseProgram(h) => {
+                    // Unbind rather than leave the previous program
+                    // drawing with the wrong shader and uniforms.
+                    self.program = h.get();
+                    state::use_program(self.program.unwrap_or(0));
+                },
+                Command::Uniform(name, value) => {
+                    let loc = self.location(name);
+                    if loc < 0 {
+                        continue;
+                    }
+                    match value {
+                        Uniform::Int(v) => gl_call!(Uniform1i(loc, *v)),
+                        Uniform::Float(v) => gl_call!(Uniform1f(loc, *v)),
+                        Uniform::Vec2(v) => gl_call!(Uniform2fv(loc, 1, v.as_ptr())),
+                        Uniform::Vec3(v) => gl_call!(Uniform3fv(loc, 1, v.as_ptr())),
+                        Uniform::Vec4(v) => gl_call!(Uniform4fv(loc, 1, v.as_ptr())),
+                        Uniform::Mat4(v) => gl_call!(UniformMatrix4fv(loc, 1, ::gl::FALSE, v.as_ptr())),
+                    }
+                },
+                Command::BindTexture(unit, h) => if let Some(id) = h.get() {
+                    state::bind_texture(*unit, id);
+                },
+                Command::UploadBuffer { buffer, target, usage, data } => {
+                    if repeat {
+                        continue;
+                    }
+                    if let Some(id) = buffer.get() {
+                        state::bind_buffer(*target, id);
+                        gl_call!(BufferData(*target, data.len() as GLsizeiptr, data.as_ptr() as *const c_void, *usage));
+                    }
+                },
+                Command::UploadTexture { texture, x, y, width, height, format, pixels } => {
+                    if repeat {
+                        continue;
+                    }
+                    if let Some(id) = texture.get() {
+                        match upload_size(*width, *height, *format) {
+                            Some(size) if size <= pixels.len() => {},
+                            _ => {
+                                log::error!("Skipped a {}x{} {:?} upload to {:?} with {} bytes",
+                                    width, height, format, texture, pixels.len());
+                                continue;
+                            },
+                        };
+                        state::bind_texture(0, id);
+                        gl_call!(PixelStorei(::gl::UNPACK_ALIGNMENT, 1));
+                        gl_call!(TexSubImage2D(::gl::TEXTURE_2D, 0, *x, *y, *width, *height,
+                            format.gl_format(), ::gl::UNSIGNED_BYTE, pixels.as_ptr() as *const c_void));
+                    }
+                },
+                Command::CreateBuffer(h) => if needs_object(*h) {
+                    let mut id: GLuint = 0;
+                    gl_call!(GenBuffers(1, &mut id));
+                    h.restore(id);
+                },
+                Command::CreateTexture { texture, width, height, format, filter } => if needs_object(*texture) {
+                    if *width <= 0 || *height <= 0 {
+                        log::error!("Skipped creating a {}x{} texture for {:?}", width, height, texture);
+                        continue;
+                    }
+                    match Texture::new(*width, *height, *format, *filter, None) {
+                        Ok(t) => {
+                            texture.restore(t.into_raw());
+                        },
+                        Err(e) => log::error!("Skipped creating a texture for {:?}: {:?}", texture, e),
+                    };
+                },
+                Command::Draw { vertices, attributes, indices, mode, first, count } => {
+                    if self.program.is_none() {
+                        continue;
+                    }
+                    let vbo = match vertices.get() {
+                        Some(id) => id,
+                        None => continue,
+                    };
+                    let ibo = match indices {
+                        Some((h, index_type)) => match h.get() {
+                            Some(id) => Some((id, *index_type)),
+                            None => continue,
+                        },
+                        None => None,
+                    };
+                    state::bind_buffer(::gl::ARRAY_BUFFER, vbo);
+                    for a in attributes.iter() {
+                        gl_call!(EnableVertexAttribArray(a.location));
+                        gl_call!(VertexAttribPointer(a.location, a.components, a.kind,
+                            if a.normalized { ::gl::TRUE } else { ::gl::FALSE }, a.stride, a.offset as *const c_void));
+                    }
+                    match ibo {
+                        Some((id, index_type)) => {
+                            state::bind_buffer(::gl::ELEMENT_ARRAY_BUFFER, id);
+                            gl_call!(DrawElements(*mode, *count, index_type, std::ptr::null()));
+                        },
+                        None => gl_call!(DrawArrays(*mode, *first, *count)),
+                    }
+                    for a in attributes.iter() {
+                        gl_call!(DisableVertexAttribArray(a.location));
+                    }
+                },
+            }
+        }
+    }
+
+    // Replays the newest submitted frame, or the last one
+    // again without uploads. Returns whether it was new.
+    pub unsafe fn execute_pending(&mut self, queue: &FrameQueue) -> bool {
+        let fresh = match queue.take() {
+            Some(frame) => {
+                self.last = frame;
+                true
+            },
+            None => false,
+        };
+        let frame = std::mem::take(&mut self.last);
+        for list in frame.iter() {
+            self.execute(list, !fresh);
+        }
+        self.last = frame;
+        return fresh;
+    }
+}
+
+thread_local! {
+    static EXECUTOR: RefCell<Executor> = RefCell::new(Executor::new());
+}
+
+// execute_pending on QUEUE with the render thread's executor.
+pub unsafe fn execute_pending() -> bool {
+    return EXECUTOR.with(|e| e.borrow_mut().execute_pending(&QUEUE));
+}
+
+// Forgets cached program state after a context change.
+pub fn reset(context: &Context) {
+    EXECUTOR.with(|e| e.borrow_mut().reset(context));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_sizes_reject_bad_dimensions() {
+        assert_eq!(upload_size(4, 2, PixelFormat::RGBA8), Some(32));
+        assert_eq!(upload_size(0, 7, PixelFormat::R8), Some(0));
+        assert_eq!(upload_size(-1, 4, PixelFormat::R8), None);
+        assert_eq!(upload_size(4, i32::MIN, PixelFormat::RGB8), None);
+    }
+
+    #[test]
+    fn reserved_handles_wait_for_their_object() {
+        let mut list = CommandList::new();
+        let buffer = list.create_buffer();
+        assert!(matches!(list.commands()[0], Command::CreateBuffer(b) if b == buffer));
+        assert!(needs_object(buffer));
+        assert!(buffer.restore(7));
+        assert_eq!(buffer.get(), Some(7));
+        assert!(!needs_object(buffer));
+    }
+}
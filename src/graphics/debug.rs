@@ -0,0 +1,138 @@
+use ::gl::types::*;
+use crate::graphics::gl::Context;
+use std::ffi::{c_void, CStr};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Debug layer for the renderer. While enabled, every call made
+// through gl_call! is followed by glGetError and failures are
+// logged with the call site. Where KHR_debug is available the
+// driver's own messages are routed into `log` as well. It can
+// be flipped at any time from any thread with set_enabled.
+
+static ENABLED: AtomicBool = AtomicBool::new(cfg!(debug_assertions));
+static KHR_DEBUG: AtomicBool = AtomicBool::new(false);
+
+// Errors kept for inspection before the oldest are dropped.
+const MAX_RECORDED_ERRORS: usize = 64;
+
+static ERRORS: Mutex<Vec<CallError>> = Mutex::new(Vec::new());
+
+// ES 3.2 / KHR_debug enums missing from the desktop bindings
+// or spelled differently there. The values are shared.
+const DEBUG_OUTPUT: GLenum = 0x92E0;
+const DEBUG_OUTPUT_SYNCHRONOUS: GLenum = 0x8242;
+pub const LABEL_BUFFER: GLenum = 0x82E0;
+pub const LABEL_SHADER: GLenum = 0x82E1;
+pub const LABEL_PROGRAM: GLenum = 0x82E2;
+pub const LABEL_TEXTURE: GLenum = ::gl::TEXTURE;
+pub const LABEL_FRAMEBUFFER: GLenum = ::gl::FRAMEBUFFER;
+pub const LABEL_RENDERBUFFER: GLenum = ::gl::RENDERBUFFER;
+
+#[derive(Clone, Debug)]
+pub struct CallError {
+    pub error: GLenum,
+    pub call: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+}
+
+// Wraps a GL call, e.g. gl_call!(BindBuffer(target, id)).
+// It expands to the same unsafe call, so it must be used
+// where ::gl::BindBuffer could be.
+macro_rules! gl_call {
+    ($func:ident($($arg:expr),* $(,)?)) => {{
+        let result = ::gl::$func($($arg),*);
+        if $crate::graphics::debug::is_enabled() {
+            $crate::graphics::debug::check(stringify!($func), file!(), line!());
+        }
+        result
+    }};
+}
+pub(crate) use gl_call;
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    return ENABLED.load(Ordering::Relaxed);
+}
+
+pub fn error_name(error: GLenum) -> &'static str {
+    return match error {
+        ::gl::INVALID_ENUM => "GL_INVALID_ENUM",
+        ::gl::INVALID_VALUE => "GL_INVALID_VALUE",
+        ::gl::INVALID_OPERATION => "GL_INVALID_OPERATION",
+        ::gl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION",
+        ::gl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+        _ => "unknown GL error",
+    };
+}
+
+// Drains glGetError, logging and recording each error against
+// the call that produced it. GL keeps one flag per error kind,
+// so loop until they're all clear.
+pub unsafe fn check(call: &'static str, file: &'static str, line: u32) {
+    loop {
+        let error = ::gl::GetError();
+        if error == ::gl::NO_ERROR {
+            return;
+        }
+        log::error!("gl{} failed with {} (0x{:04x}) at {}:{}", call, error_name(error), error, file, line);
+        let mut errors = ERRORS.lock().unwrap();
+        if errors.len() == MAX_RECORDED_ERRORS {
+            errors.remove(0);
+        }
+        errors.push(CallError { error: error, call: call, file: file, line: line });
+    }
+}
+
+// Errors recorded since the last call, oldest first.
+pub fn take_errors() -> Vec<CallError> {
+    return std::mem::take(&mut *ERRORS.lock().unwrap());
+}
+
+extern "system" fn message_callback(source: GLenum, kind: GLenum, id: GLuint, severity: GLenum,
+                                    _length: GLsizei, message: *const GLchar, _user: *mut c_void) {
+    if !is_enabled() || message.is_null() {
+        return;
+    }
+    let text = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    let level = match severity {
+        ::gl::DEBUG_SEVERITY_HIGH => log::Level::Error,
+        ::gl::DEBUG_SEVERITY_MEDIUM => log::Level::Warn,
+        ::gl::DEBUG_SEVERITY_LOW => log::Level::Info,
+        _ => log::Level::Debug,
+    };
+    log::log!(level, "GL [source 0x{:x}, type 0x{:x}, id {}]: {}", source, kind, id, text);
+}
+
+// Hooks the driver's message log up to ours. Call once the
+// context is current and functions are loaded. The gl crate
+// falls back to the KHR-suffixed entry points on ES < 3.2.
+pub unsafe fn install(context: &Context) {
+    // Some EGL implementations hand out pointers for any name,
+    // so don't trust is_loaded without the extension too.
+    let available = (context.is_version_at_least(3, 2) || context.has_extension("GL_KHR_debug"))
+        && ::gl::DebugMessageCallback::is_loaded();
+    KHR_DEBUG.store(available, Ordering::Relaxed);
+    if !available {
+        log::info!("KHR_debug unavailable, only glGetError checking is active");
+        return;
+    }
+    ::gl::DebugMessageCallback(Some(message_callback), std::ptr::null());
+    ::gl::Enable(DEBUG_OUTPUT);
+    // Synchronous output puts the callback on the offending
+    // call's stack, which is what makes it useful to debug.
+    ::gl::Enable(DEBUG_OUTPUT_SYNCHRONOUS);
+}
+
+// Names an object for the driver's messages and GPU debuggers.
+// `identifier` is one of the LABEL_* kinds.
+pub unsafe fn label(identifier: GLenum, id: GLuint, name: &str) {
+    if !KHR_DEBUG.load(Ordering::Relaxed) || !::gl::ObjectLabel::is_loaded() {
+        return;
+    }
+    ::gl::ObjectLabel(identifier, id, name.len() as GLsizei, name.as_ptr() as *const GLchar);
+}
@@ -0,0 +1,409 @@
+// Immediate-mode debug drawing. Anything on any thread can
+// queue lines, shapes and labels during a frame, and the
+// render thread draws them all at once with DebugRenderer,
+// which also empties the queue. A frame that ends without
+// a DebugRenderer drawing drops what was queued, so the
+// queue can't grow without bound.
+//
+// Call through the debug_draw! macro, as in
+//
+//   debug_draw!(text(position, &format!("hp {}", hp), WHITE));
+//
+// In release builds the functions are empty stubs and the
+// macro's call sits behind a constant false, so neither the
+// call nor its arguments are ever evaluated.
+
+#[allow(unused_macros)]
+macro_rules! debug_draw {
+    ($func:ident($($arg:expr),* $(,)?)) => {
+        if cfg!(debug_assertions) {
+            $crate::graphics::debug_draw::$func($($arg),*);
+        }
+    };
+}
+pub(crate) use debug_draw;
+
+pub type Color = [f32; 4];
+
+pub const RED: Color = [1.0, 0.2, 0.2, 1.0];
+pub const GREEN: Color = [0.2, 1.0, 0.2, 1.0];
+pub const BLUE: Color = [0.3, 0.5, 1.0, 1.0];
+pub const YELLOW: Color = [1.0, 0.9, 0.2, 1.0];
+pub const WHITE: Color = [1.0, 1.0, 1.0, 1.0];
+
+// Whether a primitive is hidden behind scene geometry
+// or always drawn on top.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Depth {
+    Test,
+    Overlay,
+}
+
+#[cfg(debug_assertions)]
+pub use enabled::*;
+#[cfg(not(debug_assertions))]
+pub use disabled::*;
+
+#[cfg(debug_assertions)]
+mod enabled {
+    use ::gl::types::*;
+    use super::{Color, Depth};
+    use crate::graphics::Result;
+    use crate::graphics::shader::Program;
+    use crate::graphics::font::sdf::{TextRenderer, TextStyle};
+    use crate::graphics::font::layout::LayoutOptions;
+    use crate::graphics::state;
+    use crate::graphics::pipeline::{Pipeline, PipelineDesc, BlendState, DepthState, Compare};
+    use crate::graphics::restore::ContextStamp;
+    use crate::graphics::debug::gl_call;
+    use crate::bridge::activity::Activity;
+    use crate::math::{Matrix4, Vector3, Vector4};
+    use std::ffi::c_void;
+    use std::sync::Mutex;
+
+    const ATTRIB_POSITION: GLuint = 0;
+    const ATTRIB_COLOR: GLuint = 1;
+    // Position and color per vertex.
+    const VERTEX_FLOATS: usize = 7;
+    const CIRCLE_SEGMENTS: usize = 24;
+    const LABEL_SIZE: f32 = 16.0;
+
+    struct Label {
+        position: Vector3,
+        text: String,
+        color: Color,
+    }
+
+    struct Queue {
+        tested: Vec<f32>,
+        overlay: Vec<f32>,
+        labels: Vec<Label>,
+        // A DebugRenderer took the queue this frame.
+        drawn: bool,
+    }
+
+    static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+        tested: Vec::new(),
+        overlay: Vec::new(),
+        labels: Vec::new(),
+        drawn: false,
+    });
+
+    fn push_lines(lines: &[(Vector3, Vector3)], color: Color, depth: Depth) {
+        let mut queue = QUEUE.lock().unwrap();
+        let out = match depth {
+            Depth::Test => &mut queue.tested,
+            Depth::Overlay => &mut queue.overlay,
+        };
+        for (a, b) in lines {
+            out.extend_from_slice(&[a.x, a.y, a.z]);
+            out.extend_from_slice(&color);
+            out.extend_from_slice(&[b.x, b.y, b.z]);
+            out.extend_from_slice(&color);
+        }
+    }
+
+    // Any unit vector perpendicular to `v`.
+    fn perpendicular(v: Vector3) -> Vector3 {
+        let helper = if v.y.abs() < 0.99 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+        return v.cross(helper).normalize();
+    }
+
+    fn circle(center: Vector3, u: Vector3, v: Vector3, radius: f32, out: &mut Vec<(Vector3, Vector3)>) {
+        let point = |i: usize| {
+            let a = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+            return center + u * (a.cos() * radius) + v * (a.sin() * radius);
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            out.push((point(i), point(i + 1)));
+        }
+    }
+
+    // The 12 edges between 8 corners indexed by xyz bits.
+    fn box_edges(corners: &[Vector3; 8]) -> [(Vector3, Vector3); 12] {
+        let e = |a: usize, b: usize| (corners[a], corners[b]);
+        return [
+            e(0, 1), e(2, 3), e(4, 5), e(6, 7),
+            e(0, 2), e(1, 3), e(4, 6), e(5, 7),
+            e(0, 4), e(1, 5), e(2, 6), e(3, 7),
+        ];
+    }
+
+    pub fn line(a: Vector3, b: Vector3, color: Color, depth: Depth) {
+        push_lines(&[(a, b)], color, depth);
+    }
+
+    pub fn arrow(from: Vector3, to: Vector3, color: Color, depth: Depth) {
+        let dir = to - from;
+        let length = dir.length();
+        if length <= 0.0 {
+            return;
+        }
+        let d = dir * (1.0 / length);
+        let u = perpendicular(d);
+        let v = d.cross(u);
+        let head = length * 0.15;
+        let base = to - d * head;
+        push_lines(&[
+            (from, to),
+            (to, base + u * (head * 0.5)),
+            (to, base - u * (head * 0.5)),
+            (to, base + v * (head * 0.5)),
+            (to, base - v * (head * 0.5)),
+        ], color, depth);
+    }
+
+    // Axis-aligned box between two corners.
+    pub fn aabb(min: Vector3, max: Vector3, color: Color, depth: Depth) {
+        let mut corners = [min; 8];
+        for (i, c) in corners.iter_mut().enumerate() {
+            *c = Vector3::new(
+                if i & 1 != 0 { max.x } else { min.x },
+                if i & 2 != 0 { max.y } else { min.y },
+                if i & 4 != 0 { max.z } else { min.z });
+        }
+        push_lines(&box_edges(&corners), color, depth);
+    }
+
+    // Unit cube from -half_extents to +half_extents, transformed.
+    pub fn oriented_box(transform: &Matrix4, half_extents: Vector3, color: Color, depth: Depth) {
+        let mut corners = [half_extents; 8];
+        for (i, c) in corners.iter_mut().enumerate() {
+            let local = Vector3::new(
+                if i & 1 != 0 { half_extents.x } else { -half_extents.x },
+                if i & 2 != 0 { half_extents.y } else { -half_extents.y },
+                if i & 4 != 0 { half_extents.z } else { -half_extents.z });
+            *c = (*transform * Vector4::from_v3_1(local)).xyz();
+        }
+        push_lines(&box_edges(&corners), color, depth);
+    }
+
+    // Three great circles, one per axis plane.
+    pub fn sphere(center: Vector3, radius: f32, color: Color, depth: Depth) {
+        let (x, y, z) = (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut lines = Vec::with_capacity(CIRCLE_SEGMENTS * 3);
+        circle(center, x, y, radius, &mut lines);
+        circle(center, y, z, radius, &mut lines);
+        circle(center, z, x, radius, &mut lines);
+        push_lines(&lines, color, depth);
+    }
+
+    // Grid of columns x rows square cells on the XZ plane,
+    // centered on `center`.
+    pub fn grid(center: Vector3, cell_size: f32, columns: u32, rows: u32, color: Color, depth: Depth) {
+        let (w, d) = (columns as f32 * cell_size, rows as f32 * cell_size);
+        let origin = center - Vector3::new(w * 0.5, 0.0, d * 0.5);
+        let mut lines = Vec::with_capacity((columns + rows + 2) as usize);
+        for c in 0..=columns {
+            let x = origin + Vector3::new(c as f32 * cell_size, 0.0, 0.0);
+            lines.push((x, x + Vector3::new(0.0, 0.0, d)));
+        }
+        for r in 0..=rows {
+            let z = origin + Vector3::new(0.0, 0.0, r as f32 * cell_size);
+            lines.push((z, z + Vector3::new(w, 0.0, 0.0)));
+        }
+        push_lines(&lines, color, depth);
+    }
+
+    // Outline of the volume a camera's view-projection sees.
+    pub fn frustum(view_proj: &Matrix4, color: Color, depth: Depth) {
+        let inverse = match view_proj.inverse() {
+            Some(m) => m,
+            None => return,
+        };
+        let mut corners = [Vector3::new(0.0, 0.0, 0.0); 8];
+        for (i, c) in corners.iter_mut().enumerate() {
+            let ndc = Vector4 {
+                x: if i & 1 != 0 { 1.0 } else { -1.0 },
+                y: if i & 2 != 0 { 1.0 } else { -1.0 },
+                z: if i & 4 != 0 { 1.0 } else { -1.0 },
+                w: 1.0,
+            };
+            let p = inverse * ndc;
+            *c = p.xyz() * (1.0 / p.w);
+        }
+        push_lines(&box_edges(&corners), color, depth);
+    }
+
+    // Text centered over a world position, always on top.
+    pub fn text(position: Vector3, text: &str, color: Color) {
+        QUEUE.lock().unwrap().labels.push(Label { position: position, text: text.to_string(), color: color });
+    }
+
+    // Drops everything queued so far.
+    pub fn clear() {
+        let mut queue = QUEUE.lock().unwrap();
+        queue.tested.clear();
+        queue.overlay.clear();
+        queue.labels.clear();
+    }
+
+    // Called by the platform at the end of every frame.
+    // Without a renderer nothing would ever take the queue,
+    // so it's dropped instead. After a draw it's kept, since
+    // anything in it was queued for the next frame.
+    pub fn end_frame() {
+        let mut queue = QUEUE.lock().unwrap();
+        if !queue.drawn {
+            queue.tested.clear();
+            queue.overlay.clear();
+            queue.labels.clear();
+        }
+        queue.drawn = false;
+    }
+
+    // Floats of line vertices and labels waiting to be drawn.
+    #[cfg(test)]
+    pub(super) fn queued() -> (usize, usize) {
+        let queue = QUEUE.lock().unwrap();
+        return (queue.tested.len() + queue.overlay.len(), queue.labels.len());
+    }
+
+    pub struct DebugRenderer {
+        program: Program,
+        // Depth tested lines and the overlay, both blended
+        // and neither writing depth.
+        tested_pipeline: Pipeline,
+        overlay_pipeline: Pipeline,
+        vbo: GLuint,
+        text: Option<TextRenderer>,
+        tested: Vec<f32>,
+        overlay: Vec<f32>,
+        stamp: ContextStamp,
+    }
+
+    impl DebugRenderer {
+        // Labels are skipped without a text renderer.
+        pub unsafe fn new(activity: &Activity, text: Option<TextRenderer>) -> Result<DebugRenderer> {
+            let program = Program::load("debug_lines", &[(ATTRIB_POSITION, "position"), (ATTRIB_COLOR, "color")], activity)?;
+            let mut vbo: GLuint = 0;
+            gl_call!(GenBuffers(1, &mut vbo));
+            let overlay = PipelineDesc { blend: Some(BlendState::ALPHA), ..PipelineDesc::FULLSCREEN };
+            return Ok(DebugRenderer {
+                program: program,
+                tested_pipeline: Pipeline::new(PipelineDesc {
+                    depth: Some(DepthState { compare: Compare::LessEqual, write: false }),
+                    ..overlay
+                })?,
+                overlay_pipeline: Pipeline::new(overlay)?,
+                vbo: vbo,
+                text: text,
+                tested: Vec::new(),
+                overlay: Vec::new(),
+                stamp: ContextStamp::now(),
+            });
+        }
+
+        unsafe fn draw_lines(&self, vertices: &[f32]) {
+            if vertices.is_empty() {
+                return;
+            }
+            let stride = (VERTEX_FLOATS * 4) as GLsizei;
+            gl_call!(BufferData(::gl::ARRAY_BUFFER, (vertices.len() * 4) as GLsizeiptr, vertices.as_ptr() as *const c_void, ::gl::STREAM_DRAW));
+            gl_call!(VertexAttribPointer(ATTRIB_POSITION, 3, ::gl::FLOAT, ::gl::FALSE, stride, std::ptr::null()));
+            gl_call!(VertexAttribPointer(ATTRIB_COLOR, 4, ::gl::FLOAT, ::gl::FALSE, stride, 12 as *const c_void));
+            gl_call!(DrawArrays(::gl::LINES, 0, (vertices.len() / VERTEX_FLOATS) as GLsizei));
+        }
+
+        // Draws and empties the queue over the current framebuffer.
+        pub unsafe fn draw(&mut self, view_proj: &Matrix4, viewport_width: f32, viewport_height: f32) {
+            let labels = {
+                let mut queue = QUEUE.lock().unwrap();
+                std::mem::swap(&mut queue.tested, &mut self.tested);
+                std::mem::swap(&mut queue.overlay, &mut self.overlay);
+                queue.tested.clear();
+                queue.overlay.clear();
+                queue.drawn = true;
+                std::mem::take(&mut queue.labels)
+            };
+            self.program.bind();
+            gl_call!(UniformMatrix4fv(self.program.uniform_location("u_view_proj"), 1, ::gl::FALSE, view_proj.to_array().as_ptr()));
+            state::bind_buffer(::gl::ARRAY_BUFFER, self.vbo);
+            gl_call!(EnableVertexAttribArray(ATTRIB_POSITION));
+            gl_call!(EnableVertexAttribArray(ATTRIB_COLOR));
+            self.tested_pipeline.apply();
+            self.draw_lines(&self.tested);
+            self.overlay_pipeline.apply();
+            self.draw_lines(&self.overlay);
+            gl_call!(DisableVertexAttribArray(ATTRIB_POSITION));
+            gl_call!(DisableVertexAttribArray(ATTRIB_COLOR));
+            state::depth_mask(true);
+            state::set_enabled(::gl::BLEND, false);
+            if let Some(text) = self.text.as_mut() {
+                let options = LayoutOptions { size: LABEL_SIZE, ..LayoutOptions::default() };
+                for label in labels {
+                    let clip = *view_proj * Vector4::from_v3_1(label.position);
+                    // Behind the camera or off screen.
+                    if clip.w <= 0.0 || clip.x.abs() > clip.w || clip.y.abs() > clip.w {
+                        continue;
+                    }
+                    let x = (clip.x / clip.w * 0.5 + 0.5) * viewport_width;
+                    let y = (0.5 - clip.y / clip.w * 0.5) * viewport_height;
+                    let layout = text.layout(&label.text, &options);
+                    let style = TextStyle { color: label.color, outline_width: 2.0, ..TextStyle::default() };
+                    text.draw(&layout, x - layout.width * 0.5, y - layout.height * 0.5, &style, viewport_width, viewport_height);
+                }
+            }
+        }
+    }
+
+    impl Drop for DebugRenderer {
+        fn drop(&mut self) {
+            if !self.stamp.is_current() {
+                return;
+            }
+            state::deleted_buffers(&[self.vbo]);
+            unsafe { gl_call!(DeleteBuffers(1, &self.vbo)); }
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+mod disabled {
+    use super::{Color, Depth};
+    use crate::graphics::Result;
+    use crate::graphics::font::sdf::TextRenderer;
+    use crate::bridge::activity::Activity;
+    use crate::math::{Matrix4, Vector3};
+
+    #[inline(always)] pub fn line(_a: Vector3, _b: Vector3, _color: Color, _depth: Depth) {}
+    #[inline(always)] pub fn arrow(_from: Vector3, _to: Vector3, _color: Color, _depth: Depth) {}
+    #[inline(always)] pub fn aabb(_min: Vector3, _max: Vector3, _color: Color, _depth: Depth) {}
+    #[inline(always)] pub fn oriented_box(_transform: &Matrix4, _half_extents: Vector3, _color: Color, _depth: Depth) {}
+    #[inline(always)] pub fn sphere(_center: Vector3, _radius: f32, _color: Color, _depth: Depth) {}
+    #[inline(always)] pub fn grid(_center: Vector3, _cell_size: f32, _columns: u32, _rows: u32, _color: Color, _depth: Depth) {}
+    #[inline(always)] pub fn frustum(_view_proj: &Matrix4, _color: Color, _depth: Depth) {}
+    #[inline(always)] pub fn text(_position: Vector3, _text: &str, _color: Color) {}
+    #[inline(always)] pub fn clear() {}
+    #[inline(always)] pub fn end_frame() {}
+
+    pub struct DebugRenderer;
+
+    impl DebugRenderer {
+        #[inline(always)]
+        pub unsafe fn new(_activity: &Activity, _text: Option<TextRenderer>) -> Result<DebugRenderer> {
+            return Ok(DebugRenderer);
+        }
+
+        #[inline(always)]
+        pub unsafe fn draw(&mut self, _view_proj: &Matrix4, _viewport_width: f32, _viewport_height: f32) {}
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use crate::math::Vector3;
+
+    #[test]
+    fn undrawn_frames_are_dropped() {
+        let origin = Vector3::new(0.0, 0.0, 0.0);
+        debug_draw!(line(origin, Vector3::new(1.0, 0.0, 0.0), RED, Depth::Test));
+        debug_draw!(aabb(origin, Vector3::new(1.0, 1.0, 1.0), GREEN, Depth::Overlay));
+        debug_draw!(text(origin, &format!("{}", 1), WHITE));
+        // Two vertices of seven floats per line.
+        assert_eq!(queued(), ((1 + 12) * 14, 1));
+        end_frame();
+        assert_eq!(queued(), (0, 0));
+    }
+}
@@ -0,0 +1,53 @@
+use crate::graphics::{Result, Error};
+
+// The line-based text assets (materials, emitters,
+// skeletons, clips) share one shape: a directive and its
+// arguments per line, separated by whitespace, with `#`
+// starting a comment. Errors name the asset kind and the
+// line, like "emitter line 3: unknown directive".
+
+// Splits into (line number, words) with comments and blanks gone.
+pub(crate) fn lines(text: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    return text.lines().enumerate().filter_map(|(i, raw)| {
+        let words: Vec<&str> = raw.split('#').next().unwrap_or("").split_whitespace().collect();
+        return if words.is_empty() { None } else { Some((i + 1, words)) };
+    });
+}
+
+pub(crate) fn error(kind: &str, line: usize, msg: &str) -> Error {
+    return Error::ParseError(format!("{} line {}: {}", kind, line, msg));
+}
+
+pub(crate) fn floats(kind: &str, line: usize, words: &[&str]) -> Result<Vec<f32>> {
+    let mut v = Vec::with_capacity(words.len());
+    for w in words {
+        v.push(w.parse::<f32>().map_err(|_| error(kind, line, "values must be numbers"))?);
+    }
+    return Ok(v);
+}
+
+// For parser tests: the message of the ParseError expected.
+#[cfg(test)]
+pub(crate) fn error_message<T>(result: Result<T>) -> String {
+    return match result {
+        Err(Error::ParseError(msg)) => msg,
+        _ => panic!("expected a parse error"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_comments_and_blanks() {
+        let parsed: Vec<(usize, Vec<&str>)> = lines("# header\n\njoint a  -\n  size 1 # trailing\n#\n").collect();
+        assert_eq!(parsed, vec![(3, vec!["joint", "a", "-"]), (4, vec!["size", "1"])]);
+    }
+
+    #[test]
+    fn reads_floats() {
+        assert_eq!(floats("clip", 1, &["1", "-2.5", "3e2"]).unwrap(), vec![1.0, -2.5, 300.0]);
+        assert_eq!(error_message(floats("clip", 7, &["1", "x"])), "clip line 7: values must be numbers");
+    }
+}
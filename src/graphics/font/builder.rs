@@ -0,0 +1,213 @@
+use crate::graphics::{Result, Error};
+use crate::graphics::font::{FontAtlas, Glyph};
+use crate::graphics::font::ttf::Font;
+use crate::graphics::font::raster;
+use crate::graphics::font::pack::ShelfPacker;
+use std::collections::HashMap;
+
+pub struct AtlasOptions {
+    // Pixel size glyphs are rasterized at.
+    pub em_size: f32,
+    // How far, in pixels, the distance field extends
+    // past glyph edges. Bounds outline and shadow size.
+    pub distance_range: f32,
+    pub page_width: u32,
+    pub page_height: u32,
+}
+
+impl Default for AtlasOptions {
+    fn default() -> Self {
+        return AtlasOptions {
+            em_size: 48.0,
+            distance_range: 4.0,
+            page_width: 512,
+            page_height: 512,
+        };
+    }
+}
+
+// Gap the packer leaves right and below each glyph.
+const PADDING: u32 = 1;
+
+struct Bitmap {
+    c: char,
+    w: u32,
+    h: u32,
+    xoffset: f32,
+    yoffset: f32,
+    advance: f32,
+    pixels: Vec<u8>,
+}
+
+// Rasterizes the given characters into distance field pages.
+// This is cheap enough to run at load time for small sets like
+// ASCII, but bigger sets should be built once and saved with
+// FontAtlas::to_bytes as an .hbf asset.
+pub fn build_atlas<I: IntoIterator<Item = char>>(font: &Font, chars: I, options: &AtlasOptions) -> Result<FontAtlas> {
+    // Glyph positions and sizes are stored as u16.
+    if options.page_width > u16::MAX as u32 || options.page_height > u16::MAX as u32 {
+        return Err(Error::InvalidFormat("atlas pages can be at most 65535 pixels across"));
+    }
+    let scale = options.em_size / font.units_per_em as f32;
+    let pad = options.distance_range.ceil() + 1.0;
+    let mut wanted: Vec<char> = chars.into_iter().collect();
+    wanted.sort();
+    wanted.dedup();
+    let mut by_glyph: HashMap<u16, Vec<char>> = HashMap::new();
+    let mut bitmaps = Vec::with_capacity(wanted.len());
+    for c in wanted {
+        let gid = match font.glyph_index(c) {
+            Some(g) => g,
+            None => continue,
+        };
+        by_glyph.entry(gid).or_insert_with(Vec::new).push(c);
+        let advance = font.h_metrics(gid).advance as f32 * scale;
+        let outline = font.outline(gid).ok_or(Error::InvalidFormat("corrupt TrueType glyph"))?;
+        let points = outline.iter().flatten();
+        let (mut x_min, mut y_min, mut x_max, mut y_max) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for p in points {
+            x_min = x_min.min(p.x);
+            y_min = y_min.min(p.y);
+            x_max = x_max.max(p.x);
+            y_max = y_max.max(p.y);
+        }
+        if x_min > x_max {
+            // Nothing to draw, just keep the advance.
+            bitmaps.push(Bitmap { c: c, w: 0, h: 0, xoffset: 0.0, yoffset: 0.0, advance: advance, pixels: Vec::new() });
+            continue;
+        }
+        let left = (x_min * scale).floor() - pad;
+        let top = (y_max * scale).ceil() + pad;
+        let w = ((x_max * scale).ceil() + pad - left) as u32;
+        let h = (top - ((y_min * scale).floor() - pad)) as u32;
+        let segments = raster::flatten(&outline, scale, (left, top));
+        let pixels = raster::signed_distance_field(&segments, w as usize, h as usize, options.distance_range);
+        bitmaps.push(Bitmap { c: c, w: w, h: h, xoffset: left, yoffset: top, advance: advance, pixels: pixels });
+    }
+    bitmaps.sort_by(|a, b| b.h.cmp(&a.h));
+    let (pw, ph) = (options.page_width, options.page_height);
+    let mut pages: Vec<Vec<u8>> = Vec::new();
+    let mut packer = ShelfPacker::new(pw, ph, PADDING);
+    let mut glyphs = HashMap::with_capacity(bitmaps.len());
+    for b in &bitmaps {
+        // Blank glyphs aren't drawn, so they sit at the
+        // origin of page 0 whether or not it exists.
+        let mut pos = (0, 0);
+        let mut page_index = 0;
+        if b.w > 0 {
+            // The packer pads every rectangle, even the
+            // first one on a page.
+            if b.w + PADDING > pw || b.h + PADDING > ph {
+                return Err(Error::InvalidFormat("glyph doesn't fit in an atlas page"));
+            }
+            let mut placed = if pages.is_empty() { None } else { packer.insert(b.w, b.h) };
+            if placed.is_none() {
+                pages.push(vec![0u8; (pw * ph) as usize]);
+                packer = ShelfPacker::new(pw, ph, PADDING);
+                placed = packer.insert(b.w, b.h);
+            }
+            pos = placed.ok_or(Error::InvalidFormat("glyph doesn't fit in an atlas page"))?;
+            page_index = pages.len() - 1;
+            let page = pages.last_mut().unwrap();
+            for row in 0..b.h {
+                let dst = ((pos.1 + row) * pw + pos.0) as usize;
+                let src = (row * b.w) as usize;
+                page[dst..dst + b.w as usize].copy_from_slice(&b.pixels[src..src + b.w as usize]);
+            }
+        }
+        glyphs.insert(b.c, Glyph {
+            page: page_index as u32,
+            x: pos.0 as u16,
+            y: pos.1 as u16,
+            w: b.w as u16,
+            h: b.h as u16,
+            xoffset: b.xoffset,
+            yoffset: b.yoffset,
+            advance: b.advance,
+        });
+    }
+    let mut kerning = HashMap::new();
+    for (left, right, amount) in font.kerning_pairs() {
+        if let (Some(lc), Some(rc)) = (by_glyph.get(&left), by_glyph.get(&right)) {
+            for l in lc {
+                for r in rc {
+                    kerning.insert((*l, *r), amount as f32 * scale);
+                }
+            }
+        }
+    }
+    let ascender = font.ascender as f32 * scale;
+    let descender = font.descender as f32 * scale;
+    return Ok(FontAtlas {
+        em_size: options.em_size,
+        distance_range: options.distance_range,
+        line_height: ascender - descender + font.line_gap as f32 * scale,
+        ascender: ascender,
+        descender: descender,
+        page_width: pw,
+        page_height: ph,
+        glyphs: glyphs,
+        kerning: kerning,
+        pages: pages,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::font::ttf::tests::square_font;
+
+    fn options(page_width: u32, page_height: u32) -> AtlasOptions {
+        return AtlasOptions { em_size: 16.0, distance_range: 2.0, page_width: page_width, page_height: page_height };
+    }
+
+    #[test]
+    fn glyph_as_big_as_the_page_is_an_error() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        let roomy = build_atlas(&font, "A".chars(), &options(64, 64)).unwrap();
+        let g = *roomy.glyph('A').unwrap();
+        let (w, h) = (g.w as u32, g.h as u32);
+        // The packer pads by a pixel, so an exact fit doesn't fit.
+        assert!(build_atlas(&font, "A".chars(), &options(w, 64)).is_err());
+        assert!(build_atlas(&font, "A".chars(), &options(64, h)).is_err());
+        let snug = build_atlas(&font, "A".chars(), &options(w + 1, h + 1)).unwrap();
+        assert_eq!(snug.pages.len(), 1);
+    }
+
+    #[test]
+    fn pages_must_fit_in_u16() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        assert!(build_atlas(&font, "A".chars(), &options(65536, 64)).is_err());
+        assert!(build_atlas(&font, "A".chars(), &options(64, 70000)).is_err());
+    }
+
+    #[test]
+    fn overflowing_glyphs_start_new_pages() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        let roomy = build_atlas(&font, "AB".chars(), &options(64, 64)).unwrap();
+        assert_eq!(roomy.pages.len(), 1);
+        assert_eq!(roomy.kerning('A', 'B'), -50.0 * 16.0 / 1000.0);
+        // Room for the wider 'B' alone puts 'A' on its own page.
+        let b = *roomy.glyph('B').unwrap();
+        let tight = build_atlas(&font, "AB".chars(), &options(b.w as u32 + 1, b.h as u32 + 1)).unwrap();
+        assert_eq!(tight.pages.len(), 2);
+        assert_ne!(tight.glyph('A').unwrap().page, tight.glyph('B').unwrap().page);
+        let copy = FontAtlas::from_bytes(&tight.to_bytes()).unwrap();
+        assert_eq!(copy.pages, tight.pages);
+    }
+
+    #[test]
+    fn blank_only_atlas_round_trips() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        let atlas = build_atlas(&font, " ".chars(), &options(64, 64)).unwrap();
+        assert!(atlas.pages.is_empty());
+        assert_eq!(atlas.glyph(' ').unwrap().page, 0);
+        let copy = FontAtlas::from_bytes(&atlas.to_bytes()).unwrap();
+        assert_eq!(copy.glyph(' ').unwrap().advance, atlas.glyph(' ').unwrap().advance);
+        assert!(copy.pages.is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::graphics::font::{FontAtlas, Glyph};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+pub struct LayoutOptions {
+    // Font size in pixels, i.e. the rendered em height.
+    pub size: f32,
+    // Lines wrap at whitespace once they would pass this.
+    // Words longer than a whole line are broken anywhere.
+    pub max_width: Option<f32>,
+    pub align: Align,
+    // Multiplier on the atlas line height.
+    pub line_spacing: f32,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        return LayoutOptions {
+            size: 32.0,
+            max_width: None,
+            align: Align::Left,
+            line_spacing: 1.0,
+        };
+    }
+}
+
+// One glyph quad in layout space, which has its origin at
+// the top-left of the text box and y pointing down.
+#[derive(Copy, Clone, Debug)]
+pub struct PositionedGlyph {
+    pub page: u32,
+    pub x0: f32,
+    pub y0: f32,
+    pub x1: f32,
+    pub y1: f32,
+    pub u0: f32,
+    pub v0: f32,
+    pub u1: f32,
+    pub v1: f32,
+}
+
+pub struct TextLayout {
+    pub glyphs: Vec<PositionedGlyph>,
+    pub width: f32,
+    pub height: f32,
+    pub lines: usize,
+    // Pixel to atlas scale, needed by the SDF shader
+    // to pick its antialiasing width.
+    pub scale: f32,
+}
+
+// Missing glyphs render as '?' if the atlas has one.
+// Whitespace without a glyph only advances the pen.
+fn lookup(atlas: &FontAtlas, c: char) -> Option<&Glyph> {
+    return match atlas.glyph(c) {
+        Some(g) => Some(g),
+        None if c.is_whitespace() => None,
+        None => atlas.glyph('?'),
+    };
+}
+
+fn advance(atlas: &FontAtlas, c: char) -> f32 {
+    return match lookup(atlas, c) {
+        Some(g) => g.advance,
+        None => atlas.em_size * 0.25,
+    };
+}
+
+// Width of a run of characters in atlas units,
+// including the kerning from the character before it.
+fn run_width(atlas: &FontAtlas, prev: Option<char>, run: &[char]) -> f32 {
+    let mut width = 0.0;
+    let mut last = prev;
+    for c in run {
+        if let Some(p) = last {
+            width += atlas.kerning(p, *c);
+        }
+        width += advance(atlas, *c);
+        last = Some(*c);
+    }
+    return width;
+}
+
+struct Line {
+    chars: Vec<char>,
+    width: f32,
+}
+
+impl Line {
+    fn new() -> Line {
+        return Line { chars: Vec::new(), width: 0.0 };
+    }
+
+    fn push(&mut self, atlas: &FontAtlas, run: &[char]) {
+        self.width += run_width(atlas, self.chars.last().copied(), run);
+        self.chars.extend_from_slice(run);
+    }
+
+    // Trailing spaces don't count towards alignment.
+    fn trim_end(&mut self, atlas: &FontAtlas) {
+        while self.chars.last().map_or(false, |c| c.is_whitespace()) {
+            self.chars.pop();
+        }
+        self.width = run_width(atlas, None, &self.chars);
+    }
+}
+
+// Splits a paragraph into alternating runs of
+// whitespace and non-whitespace characters.
+fn tokens(paragraph: &str) -> Vec<Vec<char>> {
+    let mut out: Vec<Vec<char>> = Vec::new();
+    for c in paragraph.chars() {
+        let same = out.last()
+            .and_then(|t| t.last())
+            .map_or(false, |l| l.is_whitespace() == c.is_whitespace());
+        if same {
+            out.last_mut().unwrap().push(c);
+        } else {
+            out.push(vec![c]);
+        }
+    }
+    return out;
+}
+
+fn wrap(atlas: &FontAtlas, paragraph: &str, max_width: Option<f32>, out: &mut Vec<Line>) {
+    let mut line = Line::new();
+    for token in tokens(paragraph) {
+        let is_space = token[0].is_whitespace();
+        let limit = match max_width {
+            Some(w) => w,
+            None => {
+                line.push(atlas, &token);
+                continue;
+            },
+        };
+        // Spaces never start a wrapped line and are
+        // trimmed later if they end one.
+        if is_space {
+            if !line.chars.is_empty() {
+                line.push(atlas, &token);
+            }
+            continue;
+        }
+        let with_word = line.width + run_width(atlas, line.chars.last().copied(), &token);
+        if with_word <= limit {
+            line.push(atlas, &token);
+            continue;
+        }
+        if !line.chars.is_empty() {
+            line.trim_end(atlas);
+            out.push(std::mem::replace(&mut line, Line::new()));
+        }
+        if run_width(atlas, None, &token) <= limit {
+            line.push(atlas, &token);
+            continue;
+        }
+        // Break a word that's too long for any line.
+        for c in token {
+            let with_char = line.width + run_width(atlas, line.chars.last().copied(), &[c]);
+            if with_char > limit && !line.chars.is_empty() {
+                out.push(std::mem::replace(&mut line, Line::new()));
+            }
+            line.push(atlas, &[c]);
+        }
+    }
+    line.trim_end(atlas);
+    out.push(line);
+}
+
+pub fn layout(atlas: &FontAtlas, text: &str, options: &LayoutOptions) -> TextLayout {
+    let scale = options.size / atlas.em_size;
+    // Wrapping is done in atlas units.
+    let max_width = options.max_width.map(|w| w / scale);
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        wrap(atlas, paragraph.trim_end_matches('\r'), max_width, &mut lines);
+    }
+    let widest = lines.iter().fold(0.0f32, |w, l| w.max(l.width));
+    let box_width = max_width.unwrap_or(widest);
+    let line_advance = atlas.line_height * options.line_spacing;
+    let inv_w = 1.0 / atlas.page_width as f32;
+    let inv_h = 1.0 / atlas.page_height as f32;
+    let mut glyphs = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let mut pen = match options.align {
+            Align::Left => 0.0,
+            Align::Center => (box_width - line.width) * 0.5,
+            Align::Right => box_width - line.width,
+        };
+        let baseline = atlas.ascender + i as f32 * line_advance;
+        let mut prev: Option<char> = None;
+        for c in &line.chars {
+            if let Some(p) = prev {
+                pen += atlas.kerning(p, *c);
+            }
+            if let Some(g) = lookup(atlas, *c) {
+                if g.w > 0 && g.h > 0 {
+                    let x0 = pen + g.xoffset;
+                    let y0 = baseline - g.yoffset;
+                    glyphs.push(PositionedGlyph {
+                        page: g.page,
+                        x0: x0 * scale,
+                        y0: y0 * scale,
+                        x1: (x0 + g.w as f32) * scale,
+                        y1: (y0 + g.h as f32) * scale,
+                        u0: g.x as f32 * inv_w,
+                        v0: g.y as f32 * inv_h,
+                        u1: (g.x + g.w) as f32 * inv_w,
+                        v1: (g.y + g.h) as f32 * inv_h,
+                    });
+                }
+            }
+            pen += advance(atlas, *c);
+            prev = Some(*c);
+        }
+    }
+    let height = match lines.len() {
+        0 => 0.0,
+        n => atlas.ascender - atlas.descender + (n - 1) as f32 * line_advance,
+    };
+    return TextLayout {
+        glyphs: glyphs,
+        width: box_width * scale,
+        height: height * scale,
+        lines: lines.len(),
+        scale: scale,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Monospaced: every glyph is 8x8 on a 10 unit advance,
+    // at one pixel per atlas unit. Space has no glyph.
+    fn atlas() -> FontAtlas {
+        let mut glyphs = HashMap::new();
+        for (i, c) in "ABCDEV?é".chars().enumerate() {
+            glyphs.insert(c, Glyph { page: 0, x: i as u16 * 8, y: 0, w: 8, h: 8, xoffset: 1.0, yoffset: 8.0, advance: 10.0 });
+        }
+        let mut kerning = HashMap::new();
+        kerning.insert(('A', 'V'), -2.0);
+        return FontAtlas {
+            em_size: 10.0,
+            distance_range: 2.0,
+            line_height: 12.0,
+            ascender: 9.0,
+            descender: -3.0,
+            page_width: 64,
+            page_height: 8,
+            glyphs: glyphs,
+            kerning: kerning,
+            pages: vec![vec![0; 64 * 8]],
+        };
+    }
+
+    fn options(max_width: Option<f32>, align: Align) -> LayoutOptions {
+        return LayoutOptions { size: 10.0, max_width: max_width, align: align, line_spacing: 1.0 };
+    }
+
+    fn lefts(layout: &TextLayout) -> Vec<f32> {
+        return layout.glyphs.iter().map(|g| g.x0).collect();
+    }
+
+    #[test]
+    fn kerns_pairs() {
+        let text = layout(&atlas(), "AVA", &options(None, Align::Left));
+        assert_eq!(lefts(&text), vec![1.0, 9.0, 19.0]);
+        assert_eq!((text.width, text.lines), (28.0, 1));
+        // Scaling the size scales everything.
+        let big = layout(&atlas(), "AVA", &LayoutOptions { size: 20.0, ..options(None, Align::Left) });
+        assert_eq!((lefts(&big), big.width, big.scale), (vec![2.0, 18.0, 38.0], 56.0, 2.0));
+    }
+
+    #[test]
+    fn wraps_at_spaces_and_breaks_long_words() {
+        let text = layout(&atlas(), "AB  AB AB", &options(Some(25.0), Align::Left));
+        assert_eq!(text.lines, 3);
+        assert_eq!(lefts(&text), vec![1.0, 11.0, 1.0, 11.0, 1.0, 11.0]);
+        let tops: Vec<f32> = text.glyphs.iter().step_by(2).map(|g| g.y0).collect();
+        assert_eq!(tops, vec![1.0, 13.0, 25.0]);
+        assert_eq!(text.height, 9.0 + 3.0 + 2.0 * 12.0);
+        let text = layout(&atlas(), "ABCDE", &options(Some(25.0), Align::Left));
+        assert_eq!(text.lines, 3);
+        assert_eq!(lefts(&text), vec![1.0, 11.0, 1.0, 11.0, 1.0]);
+    }
+
+    #[test]
+    fn aligns_without_trailing_spaces() {
+        let center = layout(&atlas(), "A  ", &options(Some(30.0), Align::Center));
+        assert_eq!(lefts(&center), vec![11.0]);
+        let right = layout(&atlas(), "A  \nAB", &options(Some(30.0), Align::Right));
+        assert_eq!(lefts(&right), vec![21.0, 11.0, 21.0]);
+        // Without a width, the box is the widest line.
+        let right = layout(&atlas(), "A\nAB", &options(None, Align::Right));
+        assert_eq!((lefts(&right), right.width), (vec![11.0, 1.0, 11.0], 20.0));
+    }
+
+    #[test]
+    fn handles_multibyte_and_missing_characters() {
+        let text = layout(&atlas(), "é x\r\nB", &options(None, Align::Left));
+        assert_eq!(text.lines, 2);
+        // A glyphless space advances a quarter em; unknown
+        // characters draw as '?'.
+        assert_eq!(lefts(&text), vec![1.0, 13.5, 1.0]);
+        assert_eq!((text.glyphs[0].u0, text.glyphs[1].u0), (56.0 / 64.0, 48.0 / 64.0));
+        assert_eq!((text.glyphs[1].v0, text.glyphs[1].v1), (0.0, 1.0));
+    }
+}
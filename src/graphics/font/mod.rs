@@ -0,0 +1,240 @@
+pub mod layout;
+pub mod sdf;
+pub mod ttf;
+pub mod raster;
+pub mod pack;
+pub mod builder;
+
+use crate::graphics::{Result, Error};
+use crate::bridge::activity::{Activity, Asset};
+use crate::utils::ByteReader;
+use std::collections::HashMap;
+use std::path::Path;
+
+// HBF1 glyph atlas layout. Everything is little-endian
+// and metrics are in atlas pixels at em_size.
+//
+//   "HBF1", u32 flags (0)
+//   f32 em_size, f32 distance_range
+//   f32 line_height, f32 ascender, f32 descender
+//   u32 page_width, u32 page_height, u32 page_count
+//   u32 glyph_count, u32 kerning_count
+//   glyph_count * { u32 codepoint, u32 page,
+//                   u16 x, u16 y, u16 w, u16 h,
+//                   f32 xoffset, f32 yoffset, f32 advance }
+//   kerning_count * { u32 left, u32 right, f32 amount }
+//   page_count * page_width * page_height distance bytes
+//
+// Distances are stored so that 128 is the glyph edge and
+// 0/255 are distance_range pixels outside/inside it.
+const MAGIC: &[u8; 4] = b"HBF1";
+
+#[derive(Copy, Clone, Debug)]
+pub struct Glyph {
+    pub page: u32,
+    pub x: u16,
+    pub y: u16,
+    pub w: u16,
+    pub h: u16,
+    // Bitmap offset from the pen position, with
+    // yoffset measured up from the baseline.
+    pub xoffset: f32,
+    pub yoffset: f32,
+    pub advance: f32,
+}
+
+pub struct FontAtlas {
+    pub em_size: f32,
+    pub distance_range: f32,
+    pub line_height: f32,
+    pub ascender: f32,
+    pub descender: f32,
+    pub page_width: u32,
+    pub page_height: u32,
+    pub glyphs: HashMap<char, Glyph>,
+    pub kerning: HashMap<(char, char), f32>,
+    pub pages: Vec<Vec<u8>>,
+}
+
+fn bad_atlas() -> Error {
+    return Error::InvalidFormat("truncated or corrupt HBF1 atlas");
+}
+
+impl FontAtlas {
+    pub fn load<P: AsRef<Path>>(path: P, activity: &Activity) -> Result<FontAtlas> {
+        let mmap = Asset::map(path, activity)?;
+        return Self::from_bytes(&mmap);
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<FontAtlas> {
+        let mut r = ByteReader::new(data);
+        if r.bytes(4) != Some(&MAGIC[..]) {
+            return Err(Error::InvalidFormat("not an HBF1 atlas"));
+        }
+        let _flags = r.u32_le().ok_or_else(bad_atlas)?;
+        let mut header = [0f32; 5];
+        for h in header.iter_mut() {
+            *h = r.f32_le().ok_or_else(bad_atlas)?;
+        }
+        let mut counts = [0u32; 5];
+        for c in counts.iter_mut() {
+            *c = r.u32_le().ok_or_else(bad_atlas)?;
+        }
+        let [page_width, page_height, page_count, glyph_count, kerning_count] = counts;
+        let mut glyphs = HashMap::with_capacity(glyph_count as usize);
+        for _ in 0..glyph_count {
+            let code = r.u32_le().ok_or_else(bad_atlas)?;
+            let glyph = Glyph {
+                page: r.u32_le().ok_or_else(bad_atlas)?,
+                x: r.u16_le().ok_or_else(bad_atlas)?,
+                y: r.u16_le().ok_or_else(bad_atlas)?,
+                w: r.u16_le().ok_or_else(bad_atlas)?,
+                h: r.u16_le().ok_or_else(bad_atlas)?,
+                xoffset: r.f32_le().ok_or_else(bad_atlas)?,
+                yoffset: r.f32_le().ok_or_else(bad_atlas)?,
+                advance: r.f32_le().ok_or_else(bad_atlas)?,
+            };
+            // Blank glyphs (spaces) have no page to be in.
+            let blank = glyph.w == 0 || glyph.h == 0;
+            if !blank && (glyph.page >= page_count
+              || glyph.x as u32 + glyph.w as u32 > page_width
+              || glyph.y as u32 + glyph.h as u32 > page_height) {
+                return Err(Error::InvalidFormat("HBF1 glyph outside of its page"));
+            }
+            match char::from_u32(code) {
+                Some(c) => { glyphs.insert(c, glyph); },
+                None => return Err(Error::InvalidFormat("HBF1 glyph has invalid codepoint")),
+            };
+        }
+        let mut kerning = HashMap::with_capacity(kerning_count as usize);
+        for _ in 0..kerning_count {
+            let left = r.u32_le().and_then(char::from_u32).ok_or_else(bad_atlas)?;
+            let right = r.u32_le().and_then(char::from_u32).ok_or_else(bad_atlas)?;
+            let amount = r.f32_le().ok_or_else(bad_atlas)?;
+            kerning.insert((left, right), amount);
+        }
+        let page_len = (page_width as usize).checked_mul(page_height as usize).ok_or_else(bad_atlas)?;
+        let mut pages = Vec::with_capacity(page_count as usize);
+        for _ in 0..page_count {
+            pages.push(r.bytes(page_len).ok_or_else(bad_atlas)?.to_vec());
+        }
+        return Ok(FontAtlas {
+            em_size: header[0],
+            distance_range: header[1],
+            line_height: header[2],
+            ascender: header[3],
+            descender: header[4],
+            page_width: page_width,
+            page_height: page_height,
+            glyphs: glyphs,
+            kerning: kerning,
+            pages: pages,
+        });
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let page_len = (self.page_width * self.page_height) as usize;
+        let mut out = Vec::with_capacity(48 + self.glyphs.len() * 28
+            + self.kerning.len() * 12 + self.pages.len() * page_len);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&0u32.to_le_bytes());
+        for f in [self.em_size, self.distance_range, self.line_height, self.ascender, self.descender] {
+            out.extend_from_slice(&f.to_le_bytes());
+        }
+        for u in [self.page_width, self.page_height, self.pages.len() as u32,
+                  self.glyphs.len() as u32, self.kerning.len() as u32] {
+            out.extend_from_slice(&u.to_le_bytes());
+        }
+        // Sort so the same atlas always serializes the same way.
+        let mut glyphs: Vec<_> = self.glyphs.iter().collect();
+        glyphs.sort_by_key(|(c, _)| **c);
+        for (c, g) in glyphs {
+            out.extend_from_slice(&(*c as u32).to_le_bytes());
+            out.extend_from_slice(&g.page.to_le_bytes());
+            for u in [g.x, g.y, g.w, g.h] {
+                out.extend_from_slice(&u.to_le_bytes());
+            }
+            for f in [g.xoffset, g.yoffset, g.advance] {
+                out.extend_from_slice(&f.to_le_bytes());
+            }
+        }
+        let mut kerning: Vec<_> = self.kerning.iter().collect();
+        kerning.sort_by_key(|(pair, _)| **pair);
+        for ((l, r), amount) in kerning {
+            out.extend_from_slice(&(*l as u32).to_le_bytes());
+            out.extend_from_slice(&(*r as u32).to_le_bytes());
+            out.extend_from_slice(&amount.to_le_bytes());
+        }
+        for page in &self.pages {
+            out.extend_from_slice(&page[..page_len]);
+        }
+        return out;
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        return self.glyphs.get(&c);
+    }
+
+    pub fn kerning(&self, left: char, right: char) -> f32 {
+        return *self.kerning.get(&(left, right)).unwrap_or(&0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atlas() -> FontAtlas {
+        let glyph = |x: u16, w: u16, h: u16| Glyph { page: 0, x: x, y: 0, w: w, h: h, xoffset: -1.0, yoffset: 3.5, advance: 4.25 };
+        let mut glyphs = HashMap::new();
+        glyphs.insert('A', glyph(0, 2, 2));
+        glyphs.insert('é', glyph(2, 2, 1));
+        glyphs.insert(' ', glyph(0, 0, 0));
+        let mut kerning = HashMap::new();
+        kerning.insert(('A', 'é'), -0.5);
+        return FontAtlas {
+            em_size: 32.0,
+            distance_range: 4.0,
+            line_height: 38.0,
+            ascender: 30.0,
+            descender: -8.0,
+            page_width: 4,
+            page_height: 2,
+            glyphs: glyphs,
+            kerning: kerning,
+            pages: vec![(0..8).collect()],
+        };
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = atlas().to_bytes();
+        let read = FontAtlas::from_bytes(&bytes).unwrap();
+        assert_eq!((read.em_size, read.distance_range, read.line_height), (32.0, 4.0, 38.0));
+        assert_eq!((read.ascender, read.descender), (30.0, -8.0));
+        assert_eq!((read.page_width, read.page_height), (4, 2));
+        assert_eq!(read.pages, vec![(0..8).collect::<Vec<u8>>()]);
+        let e = read.glyph('é').unwrap();
+        assert_eq!((e.page, e.x, e.y, e.w, e.h), (0, 2, 0, 2, 1));
+        assert_eq!((e.xoffset, e.yoffset, e.advance), (-1.0, 3.5, 4.25));
+        assert!(read.glyph(' ').is_some());
+        assert_eq!(read.kerning('A', 'é'), -0.5);
+        assert_eq!(read.kerning('é', 'A'), 0.0);
+        // Serializing is deterministic despite the hash maps.
+        assert_eq!(read.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn rejects_corrupt_atlases() {
+        let bytes = atlas().to_bytes();
+        assert!(FontAtlas::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad = bytes.clone();
+        bad[3] = b'0';
+        assert!(FontAtlas::from_bytes(&bad).is_err());
+        // Widen 'A', the second glyph after ' ', past the page.
+        let mut bad = bytes.clone();
+        let w = 48 + 28 + 12;
+        bad[w..w + 2].copy_from_slice(&5u16.to_le_bytes());
+        assert!(matches!(FontAtlas::from_bytes(&bad), Err(Error::InvalidFormat("HBF1 glyph outside of its page"))));
+    }
+}
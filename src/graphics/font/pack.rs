@@ -0,0 +1,51 @@
+// Shelf packer for atlas pages. Rectangles go on the
+// shortest shelf they fit on, and a new shelf is opened
+// under the last one when none fits. Inserting them
+// tallest first keeps the wasted space small.
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+pub struct ShelfPacker {
+    width: u32,
+    height: u32,
+    padding: u32,
+    shelves: Vec<Shelf>,
+    bottom: u32,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32, padding: u32) -> ShelfPacker {
+        return ShelfPacker {
+            width: width,
+            height: height,
+            padding: padding,
+            shelves: Vec::new(),
+            bottom: 0,
+        };
+    }
+
+    // Returns the top-left corner of the placed
+    // rectangle, or None when the page is full.
+    pub fn insert(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        let (pw, ph) = (w + self.padding, h + self.padding);
+        let width = self.width;
+        let best = self.shelves.iter_mut()
+            .filter(|s| s.height >= ph && s.x + pw <= width)
+            .min_by_key(|s| s.height);
+        if let Some(shelf) = best {
+            let pos = (shelf.x, shelf.y);
+            shelf.x += pw;
+            return Some(pos);
+        }
+        if self.bottom + ph > self.height || pw > self.width {
+            return None;
+        }
+        self.shelves.push(Shelf { y: self.bottom, height: ph, x: pw });
+        let pos = (0, self.bottom);
+        self.bottom += ph;
+        return Some(pos);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::graphics::font::ttf::Outline;
+
+// Straight edge of a flattened outline in bitmap
+// pixels, with y pointing down.
+#[derive(Copy, Clone, Debug)]
+pub struct Segment {
+    pub x0: f32,
+    pub y0: f32,
+    pub x1: f32,
+    pub y1: f32,
+}
+
+struct Flattener<'a> {
+    out: &'a mut Vec<Segment>,
+    scale: f32,
+    origin: (f32, f32),
+}
+
+impl Flattener<'_> {
+    fn map(&self, p: (f32, f32)) -> (f32, f32) {
+        return (p.0 * self.scale - self.origin.0, self.origin.1 - p.1 * self.scale);
+    }
+
+    fn line(&mut self, a: (f32, f32), b: (f32, f32)) {
+        let (a, b) = (self.map(a), self.map(b));
+        self.out.push(Segment { x0: a.0, y0: a.1, x1: b.0, y1: b.1 });
+    }
+
+    fn quad(&mut self, a: (f32, f32), c: (f32, f32), b: (f32, f32)) {
+        // Roughly one step per two pixels of control polygon.
+        let (pa, pc, pb) = (self.map(a), self.map(c), self.map(b));
+        let len = (pc.0 - pa.0).hypot(pc.1 - pa.1) + (pb.0 - pc.0).hypot(pb.1 - pc.1);
+        let steps = (len * 0.5).ceil().max(1.0).min(16.0) as usize;
+        let mut prev = pa;
+        for i in 1..=steps {
+            let t = i as f32 / steps as f32;
+            let u = 1.0 - t;
+            let p = (
+                u * u * pa.0 + 2.0 * u * t * pc.0 + t * t * pb.0,
+                u * u * pa.1 + 2.0 * u * t * pc.1 + t * t * pb.1,
+            );
+            self.out.push(Segment { x0: prev.0, y0: prev.1, x1: p.0, y1: p.1 });
+            prev = p;
+        }
+    }
+}
+
+fn midpoint(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    return ((a.0 + b.0) * 0.5, (a.1 + b.1) * 0.5);
+}
+
+// Turns TrueType contours (quadratic splines with implied
+// on-curve midpoints) into line segments. Outline units are
+// multiplied by scale, then shifted so origin, given in
+// scaled units, becomes the top-left of the bitmap.
+pub fn flatten(outline: &Outline, scale: f32, origin: (f32, f32)) -> Vec<Segment> {
+    let mut out = Vec::new();
+    let mut f = Flattener { out: &mut out, scale: scale, origin: origin };
+    for contour in outline {
+        let n = contour.len();
+        if n < 2 {
+            continue;
+        }
+        let pt = |i: usize| (contour[i % n].x, contour[i % n].y);
+        // Start on an on-curve point, which the walk then ends
+        // on too, or between two off-curve ones if there is none.
+        let (start, walk) = match contour.iter().position(|p| p.on_curve) {
+            Some(i) => (pt(i), i + 1..i + n + 1),
+            None => (midpoint(pt(n - 1), pt(0)), 0..n),
+        };
+        let mut current = start;
+        let mut control: Option<(f32, f32)> = None;
+        for i in walk {
+            let p = pt(i);
+            match (contour[i % n].on_curve, control) {
+                (true, Some(c)) => { f.quad(current, c, p); current = p; control = None; },
+                (true, None) => { f.line(current, p); current = p; },
+                (false, Some(c)) => {
+                    let mid = midpoint(c, p);
+                    f.quad(current, c, mid);
+                    current = mid;
+                    control = Some(p);
+                },
+                (false, None) => control = Some(p),
+            };
+        }
+        if let Some(c) = control {
+            f.quad(current, c, start);
+        }
+    }
+    return out;
+}
+
+// Signed distance field of the segments sampled at pixel
+// centers. 128 is the edge and the value reaches 0 or 255
+// at `range` pixels outside or inside, using the nonzero
+// winding rule TrueType outlines are defined with.
+pub fn signed_distance_field(segments: &[Segment], width: usize, height: usize, range: f32) -> Vec<u8> {
+    let mut out = vec![0u8; width * height];
+    for y in 0..height {
+        let py = y as f32 + 0.5;
+        for x in 0..width {
+            let px = x as f32 + 0.5;
+            let mut best = f32::MAX;
+            let mut winding = 0;
+            for s in segments {
+                let (dx, dy) = (s.x1 - s.x0, s.y1 - s.y0);
+                let len2 = dx * dx + dy * dy;
+                let t = if len2 > 0.0 {
+                    (((px - s.x0) * dx + (py - s.y0) * dy) / len2).max(0.0).min(1.0)
+                } else {
+                    0.0
+                };
+                let (ex, ey) = (s.x0 + t * dx - px, s.y0 + t * dy - py);
+                best = best.min(ex * ex + ey * ey);
+                // Cast a ray towards +x and count signed crossings.
+                if (s.y0 <= py) != (s.y1 <= py) {
+                    let cross_x = s.x0 + (py - s.y0) / dy * dx;
+                    if cross_x > px {
+                        winding += if s.y1 > s.y0 { 1 } else { -1 };
+                    }
+                }
+            }
+            let dist = best.sqrt();
+            let signed = if winding != 0 { dist } else { -dist };
+            let value = 127.5 + signed / range * 127.5;
+            out[y * width + x] = value.round().max(0.0).min(255.0) as u8;
+        }
+    }
+    return out;
+}
@@ -0,0 +1,180 @@
+use ::gl::types::*;
+use crate::graphics::Result;
+use crate::graphics::font::FontAtlas;
+use crate::graphics::font::layout::{self, LayoutOptions, TextLayout};
+use crate::graphics::shader::Program;
+use crate::graphics::texture::{Texture, PixelFormat, Filter};
+use crate::graphics::pipeline::{Pipeline, PipelineDesc, BlendState};
+use crate::graphics::state;
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::gl_call;
+use crate::bridge::activity::Activity;
+use crate::math::Vector2;
+use std::ffi::c_void;
+
+const ATTRIB_POSITION: GLuint = 0;
+const ATTRIB_TEXCOORD: GLuint = 1;
+
+// Sizes are in screen pixels. Outlines and shadows
+// can't reach further than the atlas distance range
+// (scaled to the text size) past the glyph edge.
+#[derive(Copy, Clone)]
+pub struct TextStyle {
+    pub color: [f32; 4],
+    pub outline_color: [f32; 4],
+    pub outline_width: f32,
+    pub shadow_color: [f32; 4],
+    pub shadow_offset: Vector2,
+    pub shadow_softness: f32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        return TextStyle {
+            color: [1.0, 1.0, 1.0, 1.0],
+            outline_color: [0.0, 0.0, 0.0, 1.0],
+            outline_width: 0.0,
+            shadow_color: [0.0, 0.0, 0.0, 0.0],
+            shadow_offset: Vector2 { x: 0.0, y: 0.0 },
+            shadow_softness: 0.0,
+        };
+    }
+}
+
+struct Locations {
+    viewport: GLint,
+    atlas: GLint,
+    color: GLint,
+    outline_color: GLint,
+    outline_width: GLint,
+    shadow_color: GLint,
+    shadow_offset: GLint,
+    shadow_softness: GLint,
+    smoothing: GLint,
+}
+
+pub struct TextRenderer {
+    atlas: FontAtlas,
+    program: Program,
+    pipeline: Pipeline,
+    locations: Locations,
+    pages: Vec<Texture>,
+    vbo: GLuint,
+    vertices: Vec<f32>,
+    stamp: ContextStamp,
+}
+
+impl TextRenderer {
+    pub unsafe fn new(atlas: FontAtlas, activity: &Activity) -> Result<TextRenderer> {
+        let program = Program::load("sdf", &[
+            (ATTRIB_POSITION, "position"),
+            (ATTRIB_TEXCOORD, "texcoord"),
+        ], activity)?;
+        let locations = Locations {
+            viewport: program.uniform_location("u_viewport"),
+            atlas: program.uniform_location("u_atlas"),
+            color: program.uniform_location("u_color"),
+            outline_color: program.uniform_location("u_outline_color"),
+            outline_width: program.uniform_location("u_outline_width"),
+            shadow_color: program.uniform_location("u_shadow_color"),
+            shadow_offset: program.uniform_location("u_shadow_offset"),
+            shadow_softness: program.uniform_location("u_shadow_softness"),
+            smoothing: program.uniform_location("u_smoothing"),
+        };
+        let pages = atlas.pages.iter().map(|p| {
+            Texture::new(atlas.page_width as i32, atlas.page_height as i32,
+                PixelFormat::R8, Filter::Linear, Some(p))
+        }).collect::<Result<Vec<Texture>>>()?;
+        let mut vbo: GLuint = 0;
+        gl_call!(GenBuffers(1, &mut vbo));
+        return Ok(TextRenderer {
+            atlas: atlas,
+            program: program,
+            // The shader outputs premultiplied alpha.
+            pipeline: Pipeline::new(PipelineDesc { blend: Some(BlendState::PREMULTIPLIED), ..PipelineDesc::FULLSCREEN })?,
+            locations: locations,
+            pages: pages,
+            vbo: vbo,
+            vertices: Vec::new(),
+            stamp: ContextStamp::now(),
+        });
+    }
+
+    pub fn atlas(&self) -> &FontAtlas {
+        return &self.atlas;
+    }
+
+    pub fn layout(&self, text: &str, options: &LayoutOptions) -> TextLayout {
+        return layout::layout(&self.atlas, text, options);
+    }
+
+    // Draws a layout with its top-left corner at (x, y)
+    // in a viewport_width x viewport_height pixel viewport.
+    pub unsafe fn draw(&mut self, text: &TextLayout, x: f32, y: f32, style: &TextStyle,
+      viewport_width: f32, viewport_height: f32) {
+        if text.glyphs.is_empty() {
+            return;
+        }
+        // Batch the quads by page so each page is one draw.
+        let mut ranges = Vec::with_capacity(self.pages.len());
+        self.vertices.clear();
+        for page in 0..self.pages.len() as u32 {
+            let start = self.vertices.len() / 4;
+            for g in text.glyphs.iter().filter(|g| g.page == page) {
+                let (x0, y0, x1, y1) = (x + g.x0, y + g.y0, x + g.x1, y + g.y1);
+                self.vertices.extend_from_slice(&[
+                    x0, y0, g.u0, g.v0,
+                    x1, y0, g.u1, g.v0,
+                    x1, y1, g.u1, g.v1,
+                    x0, y0, g.u0, g.v0,
+                    x1, y1, g.u1, g.v1,
+                    x0, y1, g.u0, g.v1,
+                ]);
+            }
+            ranges.push((page as usize, start, self.vertices.len() / 4 - start));
+        }
+        // One normalized distance unit spans 2 * distance_range
+        // atlas pixels, and one screen pixel is 1 / scale of those.
+        let pixel = 1.0 / (2.0 * self.atlas.distance_range * text.scale);
+        let smoothing = 0.7 * pixel;
+        let outline = (style.outline_width * pixel).min(0.5 - smoothing);
+        self.program.bind();
+        gl_call!(Uniform2f(self.locations.viewport, viewport_width, viewport_height));
+        gl_call!(Uniform1i(self.locations.atlas, 0));
+        gl_call!(Uniform4fv(self.locations.color, 1, style.color.as_ptr()));
+        gl_call!(Uniform4fv(self.locations.outline_color, 1, style.outline_color.as_ptr()));
+        gl_call!(Uniform1f(self.locations.outline_width, outline));
+        gl_call!(Uniform4fv(self.locations.shadow_color, 1, style.shadow_color.as_ptr()));
+        gl_call!(Uniform2f(self.locations.shadow_offset,
+            style.shadow_offset.x / text.scale / self.atlas.page_width as f32,
+            style.shadow_offset.y / text.scale / self.atlas.page_height as f32));
+        gl_call!(Uniform1f(self.locations.shadow_softness, style.shadow_softness * pixel));
+        gl_call!(Uniform1f(self.locations.smoothing, smoothing));
+        self.pipeline.apply();
+        state::bind_buffer(::gl::ARRAY_BUFFER, self.vbo);
+        gl_call!(BufferData(::gl::ARRAY_BUFFER, (self.vertices.len() * 4) as GLsizeiptr, self.vertices.as_ptr() as *const c_void, ::gl::STREAM_DRAW));
+        gl_call!(EnableVertexAttribArray(ATTRIB_POSITION));
+        gl_call!(EnableVertexAttribArray(ATTRIB_TEXCOORD));
+        gl_call!(VertexAttribPointer(ATTRIB_POSITION, 2, ::gl::FLOAT, ::gl::FALSE, 16, std::ptr::null()));
+        gl_call!(VertexAttribPointer(ATTRIB_TEXCOORD, 2, ::gl::FLOAT, ::gl::FALSE, 16, 8 as *const c_void));
+        for (page, start, count) in ranges {
+            if count > 0 {
+                self.pages[page].bind(0);
+                gl_call!(DrawArrays(::gl::TRIANGLES, start as GLint, count as GLsizei));
+            }
+        }
+        gl_call!(DisableVertexAttribArray(ATTRIB_POSITION));
+        gl_call!(DisableVertexAttribArray(ATTRIB_TEXCOORD));
+        state::set_enabled(::gl::BLEND, false);
+    }
+}
+
+impl Drop for TextRenderer {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        state::deleted_buffers(&[self.vbo]);
+        unsafe { gl_call!(DeleteBuffers(1, &self.vbo)); }
+    }
+}
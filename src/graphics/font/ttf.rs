@@ -0,0 +1,619 @@
+use crate::graphics::{Result, Error};
+use crate::bridge::activity::{Activity, Asset};
+use crate::utils::ByteReader;
+use memmap2::Mmap;
+use std::path::Path;
+
+// Minimal TrueType reader. It only understands the tables
+// needed to lay out and rasterize glyphs (head, maxp, hhea,
+// hmtx, cmap, loca, glyf and kern) and borrows the font data
+// instead of copying it, so it can sit directly on an mmap.
+
+fn bad_font() -> Error {
+    return Error::InvalidFormat("truncated or corrupt TrueType font");
+}
+
+#[derive(Copy, Clone)]
+struct Table {
+    offset: usize,
+    len: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct OutlinePoint {
+    pub x: f32,
+    pub y: f32,
+    pub on_curve: bool,
+}
+
+// Points of each closed contour in font units, y up.
+pub type Outline = Vec<Vec<OutlinePoint>>;
+
+#[derive(Copy, Clone, Debug)]
+pub struct HMetrics {
+    pub advance: u16,
+    pub left_side_bearing: i16,
+}
+
+pub struct Font<'a> {
+    data: &'a [u8],
+    glyf: Table,
+    loca: Table,
+    hmtx: Table,
+    kern: Option<Table>,
+    // Offset of the chosen cmap subtable and its format.
+    cmap: (usize, u16),
+    long_loca: bool,
+    pub units_per_em: u16,
+    pub num_glyphs: u16,
+    num_hmetrics: u16,
+    pub ascender: i16,
+    pub descender: i16,
+    pub line_gap: i16,
+}
+
+// Composite glyphs can nest, but real fonts don't go deep.
+const MAX_COMPOSITE_DEPTH: u32 = 8;
+
+impl<'a> Font<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Font<'a>> {
+        let mut r = ByteReader::new(data);
+        let version = r.u32_be().ok_or_else(bad_font)?;
+        if version != 0x00010000 && version != u32::from_be_bytes(*b"true") {
+            return Err(Error::InvalidFormat("not a TrueType font"));
+        }
+        let num_tables = r.u16_be().ok_or_else(bad_font)?;
+        r.skip(6);
+        let (mut head, mut maxp, mut hhea, mut hmtx) = (None, None, None, None);
+        let (mut cmap, mut loca, mut glyf, mut kern) = (None, None, None, None);
+        for _ in 0..num_tables {
+            let tag = r.bytes(4).ok_or_else(bad_font)?;
+            let _checksum = r.u32_be().ok_or_else(bad_font)?;
+            let offset = r.u32_be().ok_or_else(bad_font)? as usize;
+            let len = r.u32_be().ok_or_else(bad_font)? as usize;
+            if offset.checked_add(len).map_or(true, |end| end > data.len()) {
+                return Err(bad_font());
+            }
+            let table = Some(Table { offset: offset, len: len });
+            match tag {
+                b"head" => head = table,
+                b"maxp" => maxp = table,
+                b"hhea" => hhea = table,
+                b"hmtx" => hmtx = table,
+                b"cmap" => cmap = table,
+                b"loca" => loca = table,
+                b"glyf" => glyf = table,
+                b"kern" => kern = table,
+                _ => {},
+            };
+        }
+        let missing = || Error::InvalidFormat("TrueType font is missing a required table");
+        let (head, maxp, hhea) = (head.ok_or_else(missing)?, maxp.ok_or_else(missing)?, hhea.ok_or_else(missing)?);
+        let units_per_em = ByteReader::at(data, head.offset + 18).u16_be().ok_or_else(bad_font)?;
+        let long_loca = ByteReader::at(data, head.offset + 50).i16_be().ok_or_else(bad_font)? != 0;
+        let num_glyphs = ByteReader::at(data, maxp.offset + 4).u16_be().ok_or_else(bad_font)?;
+        let mut r = ByteReader::at(data, hhea.offset + 4);
+        let ascender = r.i16_be().ok_or_else(bad_font)?;
+        let descender = r.i16_be().ok_or_else(bad_font)?;
+        let line_gap = r.i16_be().ok_or_else(bad_font)?;
+        let num_hmetrics = ByteReader::at(data, hhea.offset + 34).u16_be().ok_or_else(bad_font)?;
+        if units_per_em == 0 || num_hmetrics == 0 {
+            return Err(bad_font());
+        }
+        return Ok(Font {
+            data: data,
+            glyf: glyf.ok_or_else(missing)?,
+            loca: loca.ok_or_else(missing)?,
+            hmtx: hmtx.ok_or_else(missing)?,
+            kern: kern,
+            cmap: Self::find_cmap(data, cmap.ok_or_else(missing)?)?,
+            long_loca: long_loca,
+            units_per_em: units_per_em,
+            num_glyphs: num_glyphs,
+            num_hmetrics: num_hmetrics,
+            ascender: ascender,
+            descender: descender,
+            line_gap: line_gap,
+        });
+    }
+
+    // Picks a Unicode subtable, preferring the full
+    // repertoire (format 12) over the BMP (format 4).
+    fn find_cmap(data: &[u8], cmap: Table) -> Result<(usize, u16)> {
+        let mut r = ByteReader::at(data, cmap.offset + 2);
+        let count = r.u16_be().ok_or_else(bad_font)?;
+        let mut best: Option<(usize, u16)> = None;
+        for _ in 0..count {
+            let platform = r.u16_be().ok_or_else(bad_font)?;
+            let encoding = r.u16_be().ok_or_else(bad_font)?;
+            let offset = cmap.offset + r.u32_be().ok_or_else(bad_font)? as usize;
+            let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+            if !unicode {
+                continue;
+            }
+            let format = ByteReader::at(data, offset).u16_be().ok_or_else(bad_font)?;
+            match (format, best) {
+                (12, _) => best = Some((offset, 12)),
+                (4, None) => best = Some((offset, 4)),
+                _ => {},
+            };
+        }
+        return best.ok_or(Error::InvalidFormat("TrueType font has no Unicode cmap"));
+    }
+
+    pub fn glyph_index(&self, c: char) -> Option<u16> {
+        let code = c as u32;
+        let (offset, format) = self.cmap;
+        let gid = match format {
+            4 => self.cmap4_lookup(offset, code),
+            12 => self.cmap12_lookup(offset, code),
+            _ => None,
+        };
+        return gid.filter(|g| *g != 0 && *g < self.num_glyphs);
+    }
+
+    fn cmap4_lookup(&self, offset: usize, code: u32) -> Option<u16> {
+        if code > 0xFFFF {
+            return None;
+        }
+        let seg_count = (ByteReader::at(self.data, offset + 6).u16_be()? / 2) as usize;
+        let ends = offset + 14;
+        let starts = ends + seg_count * 2 + 2;
+        let deltas = starts + seg_count * 2;
+        let range_offsets = deltas + seg_count * 2;
+        // Segments are sorted by end code.
+        let (mut lo, mut hi) = (0, seg_count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if (ByteReader::at(self.data, ends + mid * 2).u16_be()? as u32) < code {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo >= seg_count {
+            return None;
+        }
+        let start = ByteReader::at(self.data, starts + lo * 2).u16_be()? as u32;
+        if start > code {
+            return None;
+        }
+        let delta = ByteReader::at(self.data, deltas + lo * 2).u16_be()?;
+        let range_pos = range_offsets + lo * 2;
+        let range_offset = ByteReader::at(self.data, range_pos).u16_be()? as usize;
+        if range_offset == 0 {
+            return Some((code as u16).wrapping_add(delta));
+        }
+        let addr = range_pos + range_offset + (code - start) as usize * 2;
+        return match ByteReader::at(self.data, addr).u16_be()? {
+            0 => None,
+            g => Some(g.wrapping_add(delta)),
+        };
+    }
+
+    fn cmap12_lookup(&self, offset: usize, code: u32) -> Option<u16> {
+        let groups = ByteReader::at(self.data, offset + 12).u32_be()? as usize;
+        let (mut lo, mut hi) = (0, groups);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let mut r = ByteReader::at(self.data, offset + 16 + mid * 12);
+            let (start, end, glyph) = (r.u32_be()?, r.u32_be()?, r.u32_be()?);
+            if code < start {
+                hi = mid;
+            } else if code > end {
+                lo = mid + 1;
+            } else {
+                // Malformed fonts can overflow this.
+                return u16::try_from(glyph.checked_add(code - start)?).ok();
+            }
+        }
+        return None;
+    }
+
+    pub fn h_metrics(&self, gid: u16) -> HMetrics {
+        let last = self.num_hmetrics as usize - 1;
+        let i = gid as usize;
+        let advance = ByteReader::at(self.data, self.hmtx.offset + std::cmp::min(i, last) * 4).u16_be();
+        let lsb = if i <= last {
+            ByteReader::at(self.data, self.hmtx.offset + i * 4 + 2).i16_be()
+        } else {
+            ByteReader::at(self.data, self.hmtx.offset + self.num_hmetrics as usize * 4 + (i - last - 1) * 2).i16_be()
+        };
+        return HMetrics {
+            advance: advance.unwrap_or(0),
+            left_side_bearing: lsb.unwrap_or(0),
+        };
+    }
+
+    fn glyph_range(&self, gid: u16) -> Option<(usize, usize)> {
+        if gid >= self.num_glyphs {
+            return None;
+        }
+        let i = gid as usize;
+        let (start, end) = if self.long_loca {
+            let mut r = ByteReader::at(self.data, self.loca.offset + i * 4);
+            (r.u32_be()? as usize, r.u32_be()? as usize)
+        } else {
+            let mut r = ByteReader::at(self.data, self.loca.offset + i * 2);
+            (r.u16_be()? as usize * 2, r.u16_be()? as usize * 2)
+        };
+        if start > end || end > self.glyf.len || self.loca.len == 0 {
+            return None;
+        }
+        return Some((self.glyf.offset + start, end - start));
+    }
+
+    // Returns the glyph's contours, or an empty outline for
+    // glyphs without any (like space). None means corrupt data.
+    pub fn outline(&self, gid: u16) -> Option<Outline> {
+        let mut out = Vec::new();
+        self.append_outline(gid, [1.0, 0.0, 0.0, 1.0, 0.0, 0.0], 0, &mut out)?;
+        return Some(out);
+    }
+
+    // Transform is [a, b, c, d, dx, dy] mapping (x, y) to
+    // (a*x + c*y + dx, b*x + d*y + dy).
+    fn append_outline(&self, gid: u16, xf: [f32; 6], depth: u32, out: &mut Outline) -> Option<()> {
+        let (offset, len) = self.glyph_range(gid)?;
+        if len == 0 {
+            return Some(());
+        }
+        let mut r = ByteReader::at(self.data, offset);
+        let contours = r.i16_be()?;
+        r.skip(8);
+        if contours >= 0 {
+            return self.append_simple(&mut r, contours as usize, xf, out);
+        }
+        if depth >= MAX_COMPOSITE_DEPTH {
+            return None;
+        }
+        const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+        const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+        const WE_HAVE_A_SCALE: u16 = 0x0008;
+        const MORE_COMPONENTS: u16 = 0x0020;
+        const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+        const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+        let f2dot14 = |r: &mut ByteReader| -> Option<f32> { Some(r.i16_be()? as f32 / 16384.0) };
+        loop {
+            let flags = r.u16_be()?;
+            let component = r.u16_be()?;
+            let (arg1, arg2) = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                (r.i16_be()? as f32, r.i16_be()? as f32)
+            } else {
+                (r.u8()? as i8 as f32, r.u8()? as i8 as f32)
+            };
+            let (a, b, c, d) = if flags & WE_HAVE_A_SCALE != 0 {
+                let s = f2dot14(&mut r)?;
+                (s, 0.0, 0.0, s)
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                (f2dot14(&mut r)?, 0.0, 0.0, f2dot14(&mut r)?)
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                (f2dot14(&mut r)?, f2dot14(&mut r)?, f2dot14(&mut r)?, f2dot14(&mut r)?)
+            } else {
+                (1.0, 0.0, 0.0, 1.0)
+            };
+            // Point-matched components are rare enough
+            // that placing them unshifted is acceptable.
+            let (dx, dy) = if flags & ARGS_ARE_XY_VALUES != 0 { (arg1, arg2) } else { (0.0, 0.0) };
+            // Compose the component transform with the parent's.
+            let combined = [
+                xf[0] * a + xf[2] * b, xf[1] * a + xf[3] * b,
+                xf[0] * c + xf[2] * d, xf[1] * c + xf[3] * d,
+                xf[0] * dx + xf[2] * dy + xf[4], xf[1] * dx + xf[3] * dy + xf[5],
+            ];
+            self.append_outline(component, combined, depth + 1, out)?;
+            if flags & MORE_COMPONENTS == 0 {
+                return Some(());
+            }
+        }
+    }
+
+    fn append_simple(&self, r: &mut ByteReader, contours: usize, xf: [f32; 6], out: &mut Outline) -> Option<()> {
+        const ON_CURVE: u8 = 0x01;
+        const X_SHORT: u8 = 0x02;
+        const Y_SHORT: u8 = 0x04;
+        const REPEAT: u8 = 0x08;
+        const X_SAME_OR_POSITIVE: u8 = 0x10;
+        const Y_SAME_OR_POSITIVE: u8 = 0x20;
+        let mut ends = Vec::with_capacity(contours);
+        for _ in 0..contours {
+            ends.push(r.u16_be()? as usize);
+        }
+        let num_points = match ends.last() {
+            Some(e) => e + 1,
+            None => return Some(()),
+        };
+        let instructions = r.u16_be()? as usize;
+        r.skip(instructions);
+        let mut flags = Vec::with_capacity(num_points);
+        while flags.len() < num_points {
+            let f = r.u8()?;
+            flags.push(f);
+            if f & REPEAT != 0 {
+                for _ in 0..r.u8()? {
+                    flags.push(f);
+                }
+            }
+        }
+        flags.truncate(num_points);
+        let mut read_coords = |short: u8, same_or_positive: u8| -> Option<Vec<i32>> {
+            let mut coords = Vec::with_capacity(num_points);
+            let mut v: i32 = 0;
+            for f in &flags {
+                if f & short != 0 {
+                    let delta = r.u8()? as i32;
+                    v += if f & same_or_positive != 0 { delta } else { -delta };
+                } else if f & same_or_positive == 0 {
+                    v += r.i16_be()? as i32;
+                }
+                coords.push(v);
+            }
+            return Some(coords);
+        };
+        let xs = read_coords(X_SHORT, X_SAME_OR_POSITIVE)?;
+        let ys = read_coords(Y_SHORT, Y_SAME_OR_POSITIVE)?;
+        let mut start = 0;
+        for end in ends {
+            if end < start || end >= num_points {
+                return None;
+            }
+            out.push((start..=end).map(|i| {
+                let (x, y) = (xs[i] as f32, ys[i] as f32);
+                OutlinePoint {
+                    x: xf[0] * x + xf[2] * y + xf[4],
+                    y: xf[1] * x + xf[3] * y + xf[5],
+                    on_curve: flags[i] & ON_CURVE != 0,
+                }
+            }).collect());
+            start = end + 1;
+        }
+        return Some(());
+    }
+
+    // Horizontal kerning from format 0 subtables, in font units.
+    pub fn kerning(&self, left: u16, right: u16) -> i16 {
+        let key = (left as u32) << 16 | right as u32;
+        let mut total: i16 = 0;
+        self.for_each_kern_table(|offset, pairs| {
+            let (mut lo, mut hi) = (0, pairs);
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                let mut r = ByteReader::at(self.data, offset + mid * 6);
+                let pair = match r.u32_be() { Some(p) => p, None => return };
+                if pair < key {
+                    lo = mid + 1;
+                } else if pair > key {
+                    hi = mid;
+                } else {
+                    total = total.saturating_add(r.i16_be().unwrap_or(0));
+                    return;
+                }
+            }
+        });
+        return total;
+    }
+
+    // Every (left, right, amount) pair in the kern table.
+    pub fn kerning_pairs(&self) -> Vec<(u16, u16, i16)> {
+        let mut out = Vec::new();
+        self.for_each_kern_table(|offset, pairs| {
+            let mut r = ByteReader::at(self.data, offset);
+            for _ in 0..pairs {
+                match (r.u16_be(), r.u16_be(), r.i16_be()) {
+                    (Some(left), Some(right), Some(amount)) => out.push((left, right, amount)),
+                    _ => return,
+                };
+            }
+        });
+        return out;
+    }
+
+    // Calls f with the offset of the pair array and the pair
+    // count of each horizontal format 0 subtable.
+    fn for_each_kern_table<F: FnMut(usize, usize)>(&self, mut f: F) {
+        let kern = match self.kern {
+            Some(k) => k,
+            None => return,
+        };
+        let mut r = ByteReader::at(self.data, kern.offset);
+        let (version, count) = match (r.u16_be(), r.u16_be()) {
+            (Some(v), Some(c)) => (v, c),
+            _ => return,
+        };
+        // Only the Microsoft version 0 table layout is supported.
+        if version != 0 {
+            return;
+        }
+        for _ in 0..count {
+            let start = r.position();
+            let (len, coverage) = match (r.u16_be(), r.u16_be(), r.u16_be()) {
+                (Some(_), Some(l), Some(c)) => (l as usize, c),
+                _ => return,
+            };
+            let horizontal = coverage & 0x1 != 0;
+            let format = coverage >> 8;
+            if horizontal && format == 0 {
+                if let Some(pairs) = r.u16_be() {
+                    f(r.position() + 6, pairs as usize);
+                }
+            }
+            r.seek(start + len);
+        }
+    }
+}
+
+// A .ttf mapped straight from the assets folder. Keep this
+// around for as long as any Font borrowed from it.
+pub struct FontFile {
+    data: Mmap,
+}
+
+impl FontFile {
+    pub fn open<P: AsRef<Path>>(path: P, activity: &Activity) -> Result<FontFile> {
+        return Ok(FontFile { data: Asset::map(path, activity)? });
+    }
+
+    pub fn font(&self) -> Result<Font<'_>> {
+        return Font::parse(&self.data);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn be16(out: &mut Vec<u8>, v: u16) {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn be32(out: &mut Vec<u8>, v: u32) {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+
+    // A tiny font with 1000 units per em: ' ' is blank, 'A' is
+    // a unit square, and 'B' is a composite of the square
+    // shifted right by half an em. 'A' then 'B' kerns by -50.
+    pub(crate) fn square_font() -> Vec<u8> {
+        let mut head = vec![0u8; 54];
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut maxp = Vec::new();
+        be32(&mut maxp, 0x00005000);
+        be16(&mut maxp, 4);
+        let mut hhea = vec![0u8; 36];
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&4u16.to_be_bytes());
+        let mut hmtx = Vec::new();
+        for advance in [500u16, 1000, 250, 1500] {
+            be16(&mut hmtx, advance);
+            be16(&mut hmtx, 0);
+        }
+        let mut glyf = Vec::new();
+        // Glyph 1: four on-curve points with word deltas.
+        for v in [1u16, 0, 0, 1000, 1000, 3, 0] {
+            be16(&mut glyf, v);
+        }
+        glyf.extend_from_slice(&[1, 1, 1, 1]);
+        for v in [0i16, 1000, 0, -1000, 0, 0, 1000, 0] {
+            be16(&mut glyf, v as u16);
+        }
+        let square_len = glyf.len();
+        // Glyph 3: glyph 1 at (500, 0).
+        for v in [-1i16, 500, 0, 1500, 1000, 0x0003, 1, 500, 0] {
+            be16(&mut glyf, v as u16);
+        }
+        let mut loca = Vec::new();
+        for offset in [0, 0, square_len, square_len, glyf.len()] {
+            be16(&mut loca, (offset / 2) as u16);
+        }
+        let mut cmap = Vec::new();
+        for v in [0u16, 1, 3, 1] {
+            be16(&mut cmap, v);
+        }
+        be32(&mut cmap, 12);
+        // Format 4: ' ' by delta, 'A'-'B' through the glyph
+        // id array, and the 0xFFFF terminator.
+        for v in [4u16, 48, 0, 6, 4, 1, 2] {
+            be16(&mut cmap, v);
+        }
+        for v in [0x20u16, 0x42, 0xFFFF, 0, 0x20, 0x41, 0xFFFF,
+                  2u16.wrapping_sub(0x20), 0, 1, 0, 4, 0, 1, 3] {
+            be16(&mut cmap, v);
+        }
+        let mut kern = Vec::new();
+        for v in [0u16, 1, 0, 20, 0x0001, 1, 6, 0, 0, 1, 3, (-50i16) as u16] {
+            be16(&mut kern, v);
+        }
+        let tables: [(&[u8; 4], Vec<u8>); 8] = [
+            (b"cmap", cmap), (b"glyf", glyf), (b"head", head), (b"hhea", hhea),
+            (b"hmtx", hmtx), (b"kern", kern), (b"loca", loca), (b"maxp", maxp),
+        ];
+        let mut out = Vec::new();
+        be32(&mut out, 0x00010000);
+        be16(&mut out, tables.len() as u16);
+        out.extend_from_slice(&[0; 6]);
+        let mut offset = 12 + tables.len() * 16;
+        for (tag, data) in &tables {
+            out.extend_from_slice(&tag[..]);
+            be32(&mut out, 0);
+            be32(&mut out, offset as u32);
+            be32(&mut out, data.len() as u32);
+            offset += (data.len() + 3) & !3;
+        }
+        for (_, data) in &tables {
+            out.extend_from_slice(data);
+            out.resize((out.len() + 3) & !3, 0);
+        }
+        return out;
+    }
+
+    #[test]
+    fn reads_metrics() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        assert_eq!(font.units_per_em, 1000);
+        assert_eq!(font.num_glyphs, 4);
+        assert_eq!((font.ascender, font.descender, font.line_gap), (800, -200, 0));
+        assert_eq!(font.h_metrics(3).advance, 1500);
+    }
+
+    #[test]
+    fn maps_characters_through_cmap4() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        assert_eq!(font.glyph_index(' '), Some(2));
+        assert_eq!(font.glyph_index('A'), Some(1));
+        assert_eq!(font.glyph_index('B'), Some(3));
+        assert_eq!(font.glyph_index('C'), None);
+        assert_eq!(font.glyph_index('\u{1F600}'), None);
+    }
+
+    #[test]
+    fn cmap12_survives_overflowing_groups() {
+        let data = square_font();
+        let mut font = Font::parse(&data).unwrap();
+        let mut cmap = Vec::new();
+        be16(&mut cmap, 12);
+        cmap.extend_from_slice(&[0; 10]);
+        be32(&mut cmap, 2);
+        for v in [0x41u32, 0x42, 1, 0x1F600, 0x1F601, u32::MAX] {
+            be32(&mut cmap, v);
+        }
+        font.data = &cmap;
+        font.cmap = (0, 12);
+        assert_eq!(font.glyph_index('B'), Some(2));
+        assert_eq!(font.glyph_index('\u{1F600}'), None);
+        assert_eq!(font.glyph_index('\u{1F601}'), None);
+        assert_eq!(font.glyph_index('C'), None);
+    }
+
+    #[test]
+    fn reads_simple_and_composite_outlines() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        let square = font.outline(1).unwrap();
+        assert_eq!(square.len(), 1);
+        let corners: Vec<(f32, f32)> = square[0].iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(corners, vec![(0.0, 0.0), (1000.0, 0.0), (1000.0, 1000.0), (0.0, 1000.0)]);
+        assert!(square[0].iter().all(|p| p.on_curve));
+        let shifted = font.outline(3).unwrap();
+        assert_eq!(shifted[0][1].x, 1500.0);
+        assert!(font.outline(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_kerning() {
+        let data = square_font();
+        let font = Font::parse(&data).unwrap();
+        assert_eq!(font.kerning(1, 3), -50);
+        assert_eq!(font.kerning(3, 1), 0);
+        assert_eq!(font.kerning_pairs(), vec![(1, 3, -50)]);
+    }
+
+    #[test]
+    fn rejects_truncated_fonts() {
+        let data = square_font();
+        assert!(Font::parse(&data[..40]).is_err());
+        assert!(Font::parse(b"OTTO\0\0\0\0\0\0\0\0").is_err());
+    }
+}
@@ -1,8 +1,83 @@
+use ::gl::types::{GLenum, GLint};
+use crate::graphics::debug::gl_call;
+use std::ffi::CStr;
+
+// Optional context behaviour, as requested from the
+// platform or as actually obtained.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct ContextFlags {
+    // KHR_debug output is guaranteed, not best-effort.
+    pub debug: bool,
+    // Out-of-bounds access is safe, and a GPU reset loses
+    // the context instead of taking the process with it.
+    pub robust: bool,
+}
 
 // OpenGL keeps its context in
 // thread-locals, so no data is
 // needed here.
+#[derive(Clone, Debug)]
 pub struct Context {
+    // The version the driver reports, which can be
+    // higher than the one asked for.
     pub major: u8,
     pub minor: u8,
+    pub flags: ContextFlags,
+    pub extensions: Vec<String>,
+    // Of the default framebuffer.
+    pub stencil_bits: u8,
 }
+
+impl Context {
+    // Must be called with the context current. `flags` are
+    // what the platform accepted; ES 3.2 can confirm them.
+    pub unsafe fn new(major: u8, minor: u8, flags: ContextFlags) -> Context {
+        let ext_ptr = gl_call!(GetString(::gl::EXTENSIONS));
+        let extensions = if ext_ptr.is_null() {
+            Vec::new()
+        } else {
+            CStr::from_ptr(ext_ptr as *const _).to_string_lossy()
+                .split_whitespace().map(|s| s.to_string()).collect()
+        };
+        let mut stencil_bits: GLint = 0;
+        gl_call!(GetIntegerv(STENCIL_BITS, &mut stencil_bits));
+        let mut flags = flags;
+        if (major, minor) >= (3, 2) {
+            let mut bits: GLint = 0;
+            gl_call!(GetIntegerv(::gl::CONTEXT_FLAGS, &mut bits));
+            flags.debug = bits as GLenum & ::gl::CONTEXT_FLAG_DEBUG_BIT != 0;
+            flags.robust = bits as GLenum & ::gl::CONTEXT_FLAG_ROBUST_ACCESS_BIT != 0;
+        }
+        return Context {
+            major: major,
+            minor: minor,
+            flags: flags,
+            extensions: extensions,
+            stencil_bits: stencil_bits as u8,
+        };
+    }
+
+    pub fn is_version_at_least(&self, major: u8, minor: u8) -> bool {
+        return (self.major, self.minor) >= (major, minor);
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        return self.extensions.iter().any(|e| e == name);
+    }
+
+    // Depth textures are core in ES 3.0 and
+    // an extension on ES 2.0.
+    pub fn supports_depth_texture(&self) -> bool {
+        return self.is_version_at_least(3, 0) || self.has_extension("GL_OES_depth_texture");
+    }
+
+    // MIN and MAX blend equations.
+    pub fn supports_blend_minmax(&self) -> bool {
+        return self.is_version_at_least(3, 0) || self.has_extension("GL_EXT_blend_minmax");
+    }
+}
+
+// The gl crate is generated from the desktop
+// core profile, so legacy ES enums are missing.
+pub const LUMINANCE: GLenum = 0x1909;
+pub const STENCIL_BITS: GLenum = 0x0D57;
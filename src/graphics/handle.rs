@@ -0,0 +1,357 @@
+use ::gl::types::*;
+use crate::graphics::state;
+use crate::graphics::debug::gl_call;
+use crate::bridge::globals::RENDERER_THREAD_ID;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+// Generational handles for GL objects. A handle is a slot
+// index plus the generation the slot had when the object
+// was registered; freeing the slot bumps its generation,
+// so a handle that outlived its object no longer resolves
+// instead of silently aliasing whatever reuses the name.
+//
+// Handles are plain Copy values that can be held and sent
+// anywhere. Creating and deleting the GL objects themselves
+// must happen on the render thread, since that's where the
+// EGL context is current: other threads call release(),
+// which only queues the handle, and the render thread
+// frees everything queued in drain() once per frame. To
+// get a new object they reserve() a handle and record a
+// create command for the render thread to fill it in.
+//
+// In debug builds, resolving a stale handle panics with the
+// kind, slot and both generations; releasing one is logged,
+// so the rest of the deletion queue still gets freed.
+//
+// When the context itself goes away, lose_all() keeps every
+// handle valid but marks its object lost, so something that
+// knows how to recreate it (see restore.rs) can attach the
+// new GL name with Handle::restore.
+
+pub trait Kind {
+    const INDEX: usize;
+    const NAME: &'static str;
+    unsafe fn delete(id: GLuint);
+}
+
+pub mod kind {
+    use super::*;
+
+    pub struct Buffer;
+    pub struct Texture;
+    pub struct Program;
+    pub struct Framebuffer;
+
+    impl Kind for Buffer {
+        const INDEX: usize = 0;
+        const NAME: &'static str = "buffer";
+        unsafe fn delete(id: GLuint) {
+            state::deleted_buffers(&[id]);
+            gl_call!(DeleteBuffers(1, &id));
+        }
+    }
+
+    impl Kind for Texture {
+        const INDEX: usize = 1;
+        const NAME: &'static str = "texture";
+        unsafe fn delete(id: GLuint) {
+            state::deleted_texture(id);
+            gl_call!(DeleteTextures(1, &id));
+        }
+    }
+
+    impl Kind for Program {
+        const INDEX: usize = 2;
+        const NAME: &'static str = "program";
+        unsafe fn delete(id: GLuint) {
+            state::deleted_program(id);
+            gl_call!(DeleteProgram(id));
+        }
+    }
+
+    impl Kind for Framebuffer {
+        const INDEX: usize = 3;
+        const NAME: &'static str = "framebuffer";
+        unsafe fn delete(id: GLuint) {
+            state::deleted_framebuffer(id);
+            gl_call!(DeleteFramebuffers(1, &id));
+        }
+    }
+}
+
+const KINDS: usize = 4;
+
+pub type BufferHandle = Handle<kind::Buffer>;
+pub type TextureHandle = Handle<kind::Texture>;
+pub type ProgramHandle = Handle<kind::Program>;
+pub type FramebufferHandle = Handle<kind::Framebuffer>;
+
+pub struct Handle<K: Kind> {
+    index: u32,
+    generation: u32,
+    _kind: PhantomData<fn() -> K>,
+}
+
+// Derives would put the bounds on K instead of the handle.
+// Clone is the canonical copy, so it's written the way
+// clippy expects instead of with an explicit return.
+impl<K: Kind> Clone for Handle<K> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<K: Kind> Copy for Handle<K> {}
+
+impl<K: Kind> PartialEq for Handle<K> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.index == other.index && self.generation == other.generation;
+    }
+}
+
+impl<K: Kind> Eq for Handle<K> {}
+
+impl<K: Kind> std::fmt::Debug for Handle<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}#{}v{}", K::NAME, self.index, self.generation);
+    }
+}
+
+impl<K: Kind> Handle<K> {
+    // Registers a GL object created on the render thread.
+    // The handle now owns it.
+    pub fn new(id: GLuint) -> Handle<K> {
+        let mut pools = POOLS.lock().unwrap();
+        let pool = &mut pools.pools[K::INDEX];
+        let index = match pool.free.pop() {
+            Some(i) => i,
+            None => {
+                pool.slots.push(Slot { id: 0, generation: 1, live: false, lost: false });
+                (pool.slots.len() - 1) as u32
+            },
+        };
+        let slot = &mut pool.slots[index as usize];
+        slot.id = id;
+        slot.live = true;
+        slot.lost = false;
+        return Handle { index: index, generation: slot.generation, _kind: PhantomData };
+    }
+
+    // A handle with no object behind it yet, for a thread
+    // without the context to name something the render
+    // thread creates later (see Command::CreateBuffer).
+    // Until then it resolves to None, like a lost handle,
+    // and restore() attaches the object.
+    pub fn reserve() -> Handle<K> {
+        let handle = Self::new(0);
+        let mut pools = POOLS.lock().unwrap();
+        pools.pools[K::INDEX].slots[handle.index as usize].lost = true;
+        return handle;
+    }
+
+    // The GL name, or None if the object has been freed
+    // or was lost with its context and not restored.
+    pub fn get(self) -> Option<GLuint> {
+        let message = {
+            let pools = POOLS.lock().unwrap();
+            let slot = pools.pools[K::INDEX].slots.get(self.index as usize);
+            match slot {
+                Some(s) if s.live && s.generation == self.generation && s.lost => return None,
+                Some(s) if s.live && s.generation == self.generation => return Some(s.id),
+                _ => stale(K::NAME, "get", self.index, self.generation, slot),
+            }
+        };
+        // Only with the pools unlocked: a panic holding them
+        // would poison every handle.
+        if cfg!(debug_assertions) {
+            panic!("{}", message);
+        }
+        log::error!("{}", message);
+        return None;
+    }
+
+    // True until released, even while lost.
+    pub fn is_alive(self) -> bool {
+        let pools = POOLS.lock().unwrap();
+        return pools.pools[K::INDEX].slots.get(self.index as usize)
+            .map_or(false, |s| s.live && s.generation == self.generation);
+    }
+
+    // Queues the object for deletion. Safe from any thread;
+    // the handle resolves until the render thread drains.
+    pub fn release(self) {
+        QUEUE.lock().unwrap().push(Pending { kind: K::INDEX, index: self.index, generation: self.generation });
+    }
+
+    // Attaches a recreated object to a lost handle. Returns
+    // false, leaving `id` unowned, if the handle is stale.
+    pub fn restore(self, id: GLuint) -> bool {
+        let mut pools = POOLS.lock().unwrap();
+        return match pools.pools[K::INDEX].slots.get_mut(self.index as usize) {
+            Some(s) if s.live && s.generation == self.generation => {
+                s.id = id;
+                s.lost = false;
+                true
+            },
+            _ => false,
+        };
+    }
+}
+
+struct Slot {
+    id: GLuint,
+    generation: u32,
+    live: bool,
+    // Live, but its object died with an old context.
+    lost: bool,
+}
+
+#[derive(Default)]
+struct Pool {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+struct Pools {
+    pools: [Pool; KINDS],
+}
+
+struct Pending {
+    kind: usize,
+    index: u32,
+    generation: u32,
+}
+
+static POOLS: Mutex<Pools> = Mutex::new(Pools {
+    pools: [
+        Pool { slots: Vec::new(), free: Vec::new() },
+        Pool { slots: Vec::new(), free: Vec::new() },
+        Pool { slots: Vec::new(), free: Vec::new() },
+        Pool { slots: Vec::new(), free: Vec::new() },
+    ],
+});
+
+static QUEUE: Mutex<Vec<Pending>> = Mutex::new(Vec::new());
+
+fn stale(kind: &str, what: &str, index: u32, generation: u32, slot: Option<&Slot>) -> String {
+    let current = slot.map(|s| (s.generation, s.live));
+    return format!("Stale {} handle in {}: slot {} generation {}, slot now {:?}",
+        kind, what, index, generation, current);
+}
+
+fn on_render_thread() -> bool {
+    return RENDERER_THREAD_ID.get().map_or(true, |id| *id == std::thread::current().id());
+}
+
+unsafe fn delete_kind(kind: usize, id: GLuint) {
+    match kind {
+        0 => kind::Buffer::delete(id),
+        1 => kind::Texture::delete(id),
+        2 => kind::Program::delete(id),
+        _ => kind::Framebuffer::delete(id),
+    }
+}
+
+fn kind_name(kind: usize) -> &'static str {
+    return match kind {
+        0 => kind::Buffer::NAME,
+        1 => kind::Texture::NAME,
+        2 => kind::Program::NAME,
+        _ => kind::Framebuffer::NAME,
+    };
+}
+
+// Frees everything released since the last call. Must run
+// on the render thread with the context current; call it
+// once per frame. Returns how many objects were deleted.
+pub unsafe fn drain() -> usize {
+    debug_assert!(on_render_thread(), "handle::drain called off the render thread");
+    let pending = std::mem::take(&mut *QUEUE.lock().unwrap());
+    if pending.is_empty() {
+        return 0;
+    }
+    let mut deleted = 0;
+    let mut stale_releases = Vec::new();
+    let mut pools = POOLS.lock().unwrap();
+    for p in pending {
+        let pool = &mut pools.pools[p.kind];
+        let slot = match pool.slots.get_mut(p.index as usize) {
+            Some(s) if s.live && s.generation == p.generation => s,
+            slot => {
+                stale_releases.push(stale(kind_name(p.kind), "release", p.index, p.generation, slot.map(|s| &*s)));
+                continue;
+            },
+        };
+        if !slot.lost {
+            delete_kind(p.kind, slot.id);
+        }
+        slot.live = false;
+        slot.lost = false;
+        slot.id = 0;
+        slot.generation = slot.generation.wrapping_add(1).max(1);
+        pool.free.push(p.index);
+        deleted += 1;
+    }
+    drop(pools);
+    for message in stale_releases.iter() {
+        log::error!("{}", message);
+    }
+    return deleted;
+}
+
+// Marks every live object lost after its context is gone.
+// Handles stay valid but resolve to None until restored;
+// releasing one just frees the slot.
+pub fn lose_all() {
+    let mut pools = POOLS.lock().unwrap();
+    for pool in pools.pools.iter_mut() {
+        for slot in pool.slots.iter_mut().filter(|s| s.live) {
+            slot.id = 0;
+            slot.lost = true;
+        }
+    }
+}
+
+// Forgets every live object without deleting it, for when
+// the context that owned them is already gone. Outstanding
+// handles all become stale.
+pub fn forget_all() {
+    QUEUE.lock().unwrap().clear();
+    let mut pools = POOLS.lock().unwrap();
+    for pool in pools.pools.iter_mut() {
+        for (i, slot) in pool.slots.iter_mut().enumerate() {
+            if slot.live {
+                slot.live = false;
+                slot.lost = false;
+                slot.id = 0;
+                slot.generation = slot.generation.wrapping_add(1).max(1);
+                pool.free.push(i as u32);
+            }
+        }
+    }
+}
+
+// Live objects per kind: buffers, textures, programs,
+// framebuffers. Handy for spotting leaks.
+pub fn live_counts() -> [usize; KINDS] {
+    let pools = POOLS.lock().unwrap();
+    let mut counts = [0; KINDS];
+    for (count, pool) in counts.iter_mut().zip(pools.pools.iter()) {
+        *count = pool.slots.len() - pool.free.len();
+    }
+    return counts;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handles_leave_the_pools_usable() {
+        let handle = Handle::<kind::Texture>::reserve();
+        let stale = Handle::<kind::Texture> { index: handle.index, generation: handle.generation + 1, _kind: PhantomData };
+        let result = std::panic::catch_unwind(|| stale.get());
+        assert_eq!(result.is_err(), cfg!(debug_assertions));
+        assert!(!POOLS.is_poisoned());
+        assert!(handle.is_alive());
+    }
+}
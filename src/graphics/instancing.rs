@@ -0,0 +1,263 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::gl::Context;
+use crate::graphics::state;
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::gl_call;
+use std::ffi::c_void;
+
+// Float vertex attribute: shader location and component count.
+#[derive(Copy, Clone)]
+pub struct Attribute {
+    pub location: GLuint,
+    pub components: GLint,
+}
+
+impl Attribute {
+    pub const fn new(location: GLuint, components: GLint) -> Attribute {
+        return Attribute { location: location, components: components };
+    }
+}
+
+fn float_count(attributes: &[Attribute]) -> usize {
+    return attributes.iter().map(|a| a.components as usize).sum();
+}
+
+// 16-bit indices reach this many vertices.
+const MAX_VERTICES: usize = 65536;
+
+// Checks a layout before anything is uploaded and returns
+// the vertex count. Empty layouts would leave nothing to
+// step through on the ES 2.0 path, and indices past the
+// mesh would land in the next replicated copy.
+fn validate(vertices: &[f32], vertex_attributes: &[Attribute], indices: &[u16],
+  instance_attributes: &[Attribute]) -> Result<usize> {
+    let vertex_floats = float_count(vertex_attributes);
+    if vertex_floats == 0 || float_count(instance_attributes) == 0 {
+        return Err(Error::InvalidFormat("instanced mesh needs vertex and instance attributes"));
+    }
+    if vertices.len() % vertex_floats != 0 {
+        return Err(Error::InvalidFormat("instanced mesh vertices don't match their attributes"));
+    }
+    let vertex_count = vertices.len() / vertex_floats;
+    if vertex_count > MAX_VERTICES {
+        return Err(Error::Unsupported(format!(
+            "instanced mesh has {} vertices, 16-bit indices reach {}", vertex_count, MAX_VERTICES)));
+    }
+    if indices.iter().any(|i| *i as usize >= vertex_count) {
+        return Err(Error::InvalidFormat("instanced mesh index past its last vertex"));
+    }
+    return Ok(vertex_count);
+}
+
+// The index list repeated `copies` times, each copy offset
+// to its own run of vertices.
+fn replicate_indices(indices: &[u16], vertex_count: usize, copies: usize) -> Vec<u16> {
+    let mut replicated: Vec<u16> = Vec::with_capacity(indices.len() * copies);
+    for copy in 0..copies {
+        let base = copy * vertex_count;
+        replicated.extend(indices.iter().map(|i| (base + *i as usize) as u16));
+    }
+    return replicated;
+}
+
+// Points each attribute into an interleaved float buffer
+// starting at byte offset `base`.
+unsafe fn attribute_pointers(attributes: &[Attribute], stride: usize, base: usize) {
+    let mut offset = base;
+    for a in attributes {
+        gl_call!(VertexAttribPointer(a.location, a.components, ::gl::FLOAT, ::gl::FALSE,
+            stride as GLsizei, offset as *const c_void));
+        offset += a.components as usize * 4;
+    }
+}
+
+// Small mesh drawn many times with per-instance attributes.
+//
+// ES 3.0 contexts upload the instance data as its own buffer
+// with attribute divisors and issue one glDrawElementsInstanced.
+// ES 2.0 has neither, so the mesh is replicated on the CPU with
+// each copy's vertices carrying that instance's attributes, and
+// drawn in batches that 16-bit indices can address. Shaders see
+// the same inputs either way, so they must not rely on
+// gl_InstanceID.
+pub struct InstancedMesh {
+    instanced: bool,
+    vertex_attributes: Vec<Attribute>,
+    instance_attributes: Vec<Attribute>,
+    vertex_floats: usize,
+    instance_floats: usize,
+    // Kept for replication on the fallback path.
+    vertices: Vec<f32>,
+    vertex_count: usize,
+    index_count: usize,
+    max_batch: usize,
+    vbo: GLuint,
+    ibo: GLuint,
+    instance_vbo: GLuint,
+    scratch: Vec<f32>,
+    stamp: ContextStamp,
+}
+
+impl InstancedMesh {
+    // `vertices` is interleaved in `vertex_attributes` order.
+    pub unsafe fn new(context: &Context, vertices: &[f32], vertex_attributes: &[Attribute],
+                      indices: &[u16], instance_attributes: &[Attribute]) -> Result<InstancedMesh> {
+        let vertex_count = validate(vertices, vertex_attributes, indices, instance_attributes)?;
+        let instanced = context.is_version_at_least(3, 0);
+        let vertex_floats = float_count(vertex_attributes);
+        let max_batch = if instanced { usize::MAX } else { MAX_VERTICES / vertex_count.max(1) };
+        let mut buffers: [GLuint; 3] = [0; 3];
+        gl_call!(GenBuffers(3, buffers.as_mut_ptr()));
+        if instanced {
+            state::bind_buffer(::gl::ARRAY_BUFFER, buffers[0]);
+            gl_call!(BufferData(::gl::ARRAY_BUFFER, (vertices.len() * 4) as GLsizeiptr, vertices.as_ptr() as *const c_void, ::gl::STATIC_DRAW));
+            state::bind_buffer(::gl::ELEMENT_ARRAY_BUFFER, buffers[1]);
+            gl_call!(BufferData(::gl::ELEMENT_ARRAY_BUFFER, (indices.len() * 2) as GLsizeiptr, indices.as_ptr() as *const c_void, ::gl::STATIC_DRAW));
+        } else {
+            // One index buffer covering the largest batch.
+            let replicated = replicate_indices(indices, vertex_count, max_batch);
+            state::bind_buffer(::gl::ELEMENT_ARRAY_BUFFER, buffers[1]);
+            gl_call!(BufferData(::gl::ELEMENT_ARRAY_BUFFER, (replicated.len() * 2) as GLsizeiptr, replicated.as_ptr() as *const c_void, ::gl::STATIC_DRAW));
+        }
+        return Ok(InstancedMesh {
+            instanced: instanced,
+            vertex_attributes: vertex_attributes.to_vec(),
+            instance_attributes: instance_attributes.to_vec(),
+            vertex_floats: vertex_floats,
+            instance_floats: float_count(instance_attributes),
+            vertices: if instanced { Vec::new() } else { vertices.to_vec() },
+            vertex_count: vertex_count,
+            index_count: indices.len(),
+            max_batch: max_batch,
+            vbo: buffers[0],
+            ibo: buffers[1],
+            instance_vbo: buffers[2],
+            scratch: Vec::new(),
+            stamp: ContextStamp::now(),
+        });
+    }
+
+    pub fn is_instanced(&self) -> bool {
+        return self.instanced;
+    }
+
+    // Floats each instance occupies in the data passed to draw.
+    pub fn instance_floats(&self) -> usize {
+        return self.instance_floats;
+    }
+
+    fn all_attributes(&self) -> impl Iterator<Item = &Attribute> {
+        return self.vertex_attributes.iter().chain(self.instance_attributes.iter());
+    }
+
+    // `instances` is interleaved in `instance_attributes` order.
+    // The program must already be bound.
+    pub unsafe fn draw(&mut self, instances: &[f32]) {
+        let count = instances.len() / self.instance_floats.max(1);
+        if count == 0 || self.index_count == 0 {
+            return;
+        }
+        for a in self.all_attributes() {
+            gl_call!(EnableVertexAttribArray(a.location));
+        }
+        state::bind_buffer(::gl::ELEMENT_ARRAY_BUFFER, self.ibo);
+        if self.instanced {
+            self.draw_instanced(instances, count);
+        } else {
+            self.draw_replicated(instances);
+        }
+        for a in self.all_attributes() {
+            gl_call!(DisableVertexAttribArray(a.location));
+        }
+    }
+
+    unsafe fn draw_instanced(&self, instances: &[f32], count: usize) {
+        state::bind_buffer(::gl::ARRAY_BUFFER, self.vbo);
+        attribute_pointers(&self.vertex_attributes, self.vertex_floats * 4, 0);
+        state::bind_buffer(::gl::ARRAY_BUFFER, self.instance_vbo);
+        gl_call!(BufferData(::gl::ARRAY_BUFFER, (instances.len() * 4) as GLsizeiptr, instances.as_ptr() as *const c_void, ::gl::STREAM_DRAW));
+        attribute_pointers(&self.instance_attributes, self.instance_floats * 4, 0);
+        for a in &self.instance_attributes {
+            gl_call!(VertexAttribDivisor(a.location, 1));
+        }
+        gl_call!(DrawElementsInstanced(::gl::TRIANGLES, self.index_count as GLsizei, ::gl::UNSIGNED_SHORT,
+            std::ptr::null(), count as GLsizei));
+        // Divisors are global state that would leak
+        // into every later draw using these locations.
+        for a in &self.instance_attributes {
+            gl_call!(VertexAttribDivisor(a.location, 0));
+        }
+    }
+
+    unsafe fn draw_replicated(&mut self, instances: &[f32]) {
+        let stride = self.vertex_floats + self.instance_floats;
+        state::bind_buffer(::gl::ARRAY_BUFFER, self.instance_vbo);
+        for batch in instances.chunks(self.max_batch * self.instance_floats) {
+            self.scratch.clear();
+            for instance in batch.chunks_exact(self.instance_floats) {
+                for vertex in self.vertices.chunks_exact(self.vertex_floats) {
+                    self.scratch.extend_from_slice(vertex);
+                    self.scratch.extend_from_slice(instance);
+                }
+            }
+            let count = batch.len() / self.instance_floats;
+            gl_call!(BufferData(::gl::ARRAY_BUFFER, (self.scratch.len() * 4) as GLsizeiptr, self.scratch.as_ptr() as *const c_void, ::gl::STREAM_DRAW));
+            attribute_pointers(&self.vertex_attributes, stride * 4, 0);
+            attribute_pointers(&self.instance_attributes, stride * 4, self.vertex_floats * 4);
+            gl_call!(DrawElements(::gl::TRIANGLES, (count * self.index_count) as GLsizei, ::gl::UNSIGNED_SHORT, std::ptr::null()));
+        }
+    }
+}
+
+impl Drop for InstancedMesh {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        let buffers = [self.vbo, self.ibo, self.instance_vbo];
+        state::deleted_buffers(&buffers);
+        unsafe { gl_call!(DeleteBuffers(3, buffers.as_ptr())); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: [f32; 8] = [0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+    const CORNER: [Attribute; 1] = [Attribute::new(0, 2)];
+    const CENTER: [Attribute; 1] = [Attribute::new(1, 3)];
+
+    #[test]
+    fn accepts_a_quad() {
+        assert_eq!(validate(&QUAD, &CORNER, &[0, 1, 2, 0, 2, 3], &CENTER).unwrap(), 4);
+    }
+
+    #[test]
+    fn rejects_empty_layouts() {
+        assert!(validate(&QUAD, &CORNER, &[0, 1, 2], &[]).is_err());
+        assert!(validate(&QUAD, &[], &[0, 1, 2], &CENTER).is_err());
+        assert!(validate(&QUAD, &[Attribute::new(0, 0)], &[0], &CENTER).is_err());
+    }
+
+    #[test]
+    fn rejects_what_16_bit_indices_cant_reach() {
+        let big = vec![0.0; (MAX_VERTICES + 1) * 2];
+        assert!(validate(&big, &CORNER, &[0, 1, 2], &CENTER).is_err());
+        let biggest = vec![0.0; MAX_VERTICES * 2];
+        assert_eq!(validate(&biggest, &CORNER, &[0, 1, 2], &CENTER).unwrap(), MAX_VERTICES);
+        assert!(validate(&QUAD, &CORNER, &[0, 1, 4], &CENTER).is_err());
+        assert!(validate(&QUAD[..7], &CORNER, &[0], &CENTER).is_err());
+    }
+
+    #[test]
+    fn replicated_indices_stay_in_range() {
+        let indices = [0, 1, 2, 0, 2, 3];
+        let copies = MAX_VERTICES / 4;
+        let replicated = replicate_indices(&indices, 4, copies);
+        assert_eq!(&replicated[6..12], &[4, 5, 6, 4, 6, 7]);
+        assert_eq!(*replicated.iter().max().unwrap() as usize, MAX_VERTICES - 1);
+        assert_eq!(replicated.len(), indices.len() * copies);
+    }
+}
@@ -0,0 +1,284 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::gl::Context;
+use crate::graphics::mesh::{Mesh, ATTRIB_POSITION};
+use crate::graphics::scene::{Scene, Attachment, Light};
+use crate::graphics::shader::{Program, ShaderLibrary};
+use crate::graphics::state;
+use crate::graphics::pipeline::{Pipeline, PipelineDesc, DepthState, Compare, Cull};
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::{self, gl_call};
+use crate::bridge::activity::Activity;
+use crate::math::{Matrix4, Vector3};
+use std::rc::Rc;
+
+// Must match MAX_POINT_LIGHTS in lit.frag.
+pub const MAX_POINT_LIGHTS: usize = 4;
+// Kept free of material textures for the shadow map.
+// ES 2.0 guarantees 8 fragment texture units.
+pub const SHADOW_TEXTURE_UNIT: u32 = 7;
+
+#[derive(Copy, Clone)]
+pub struct DirectionalLight {
+    // Direction the light travels in.
+    pub direction: Vector3,
+    // Color already multiplied by intensity.
+    pub color: Vector3,
+}
+
+#[derive(Copy, Clone)]
+pub struct PointLight {
+    pub position: Vector3,
+    pub color: Vector3,
+    pub range: f32,
+}
+
+pub struct LightSet {
+    pub ambient: Vector3,
+    pub directional: Option<DirectionalLight>,
+    pub points: Vec<PointLight>,
+}
+
+impl LightSet {
+    // Gathers the lights attached to the scene. World
+    // transforms must be up to date. The first directional
+    // light wins, since only one casts shadows.
+    pub fn from_scene(scene: &Scene, ambient: Vector3) -> LightSet {
+        let mut set = LightSet { ambient: ambient, directional: None, points: Vec::new() };
+        for (world, attachment) in scene.attachments() {
+            match attachment {
+                Attachment::Light(Light::Directional { color, intensity }) => {
+                    if set.directional.is_none() {
+                        set.directional = Some(DirectionalLight {
+                            direction: (-world.v3.xyz()).normalize(),
+                            color: color * intensity,
+                        });
+                    }
+                },
+                Attachment::Light(Light::Point { color, intensity, range }) => {
+                    set.points.push(PointLight {
+                        position: world.v4.xyz(),
+                        color: color * intensity,
+                        range: range,
+                    });
+                },
+                _ => {},
+            };
+        }
+        return set;
+    }
+
+    // Uploads the lights to a lit shader. Past the shader
+    // limit, the brightest point lights are kept.
+    pub unsafe fn upload(&self, program: &Program) {
+        let mut points: Vec<&PointLight> = self.points.iter().collect();
+        points.sort_by(|a, b| b.color.length().partial_cmp(&a.color.length()).unwrap_or(std::cmp::Ordering::Equal));
+        points.truncate(MAX_POINT_LIGHTS);
+        let mut positions = [0.0f32; MAX_POINT_LIGHTS * 3];
+        let mut colors = [0.0f32; MAX_POINT_LIGHTS * 3];
+        let mut ranges = [1.0f32; MAX_POINT_LIGHTS];
+        for (i, p) in points.iter().enumerate() {
+            positions[i * 3..i * 3 + 3].copy_from_slice(&[p.position.x, p.position.y, p.position.z]);
+            colors[i * 3..i * 3 + 3].copy_from_slice(&[p.color.x, p.color.y, p.color.z]);
+            ranges[i] = p.range;
+        }
+        let (direction, color) = match self.directional {
+            Some(d) => (d.direction, d.color),
+            None => (Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+        };
+        gl_call!(Uniform3f(program.uniform_location("u_ambient"), self.ambient.x, self.ambient.y, self.ambient.z));
+        gl_call!(Uniform3f(program.uniform_location("u_dir_direction"), direction.x, direction.y, direction.z));
+        gl_call!(Uniform3f(program.uniform_location("u_dir_color"), color.x, color.y, color.z));
+        gl_call!(Uniform1i(program.uniform_location("u_point_count"), points.len() as GLint));
+        gl_call!(Uniform3fv(program.uniform_location("u_point_position[0]"), MAX_POINT_LIGHTS as GLsizei, positions.as_ptr()));
+        gl_call!(Uniform3fv(program.uniform_location("u_point_color[0]"), MAX_POINT_LIGHTS as GLsizei, colors.as_ptr()));
+        gl_call!(Uniform1fv(program.uniform_location("u_point_range[0]"), MAX_POINT_LIGHTS as GLsizei, ranges.as_ptr()));
+    }
+}
+
+// Inverse transpose of the upper 3x3, so normals stay
+// perpendicular under non-uniform scale.
+pub fn normal_matrix(model: &Matrix4) -> [f32; 9] {
+    let m = model.inverse().unwrap_or(*model).transpose().to_array();
+    return [m[0], m[1], m[2], m[4], m[5], m[6], m[8], m[9], m[10]];
+}
+
+// Per-draw uniforms of the lit shader.
+pub unsafe fn upload_transforms(program: &Program, model: &Matrix4, view_proj: &Matrix4, camera_pos: Vector3) {
+    gl_call!(UniformMatrix4fv(program.uniform_location("u_model"), 1, ::gl::FALSE, model.to_array().as_ptr()));
+    gl_call!(UniformMatrix4fv(program.uniform_location("u_view_proj"), 1, ::gl::FALSE, view_proj.to_array().as_ptr()));
+    gl_call!(UniformMatrix3fv(program.uniform_location("u_normal_matrix"), 1, ::gl::FALSE, normal_matrix(model).as_ptr()));
+    gl_call!(Uniform3f(program.uniform_location("u_camera_pos"), camera_pos.x, camera_pos.y, camera_pos.z));
+}
+
+// Shadow map for the directional light. Uses a depth texture
+// when the context has them and otherwise renders depth
+// packed into an RGBA texture, which lit shaders then need
+// the PACKED_DEPTH feature to decode.
+pub struct ShadowMap {
+    fbo: GLuint,
+    texture: GLuint,
+    depth_rb: GLuint,
+    size: GLsizei,
+    packed: bool,
+    program: Rc<Program>,
+    pipeline: Pipeline,
+    light_view_proj: Matrix4,
+    previous_fbo: GLint,
+    previous_viewport: [GLint; 4],
+    // The pass clears to white, so the caller's clear
+    // values are put back in end().
+    previous_clear_color: [GLfloat; 4],
+    previous_clear_depth: GLfloat,
+    stamp: ContextStamp,
+}
+
+impl ShadowMap {
+    pub unsafe fn new(size: GLsizei, context: &Context, shaders: &mut ShaderLibrary, activity: &Activity) -> Result<ShadowMap> {
+        if context.supports_depth_texture() {
+            match Self::create(size, false, shaders, activity) {
+                Ok(map) => return Ok(map),
+                Err(e) => log::warn!("Depth texture shadow map failed, packing depth instead: {:?}", e),
+            };
+        }
+        return Self::create(size, true, shaders, activity);
+    }
+
+    unsafe fn create(size: GLsizei, packed: bool, shaders: &mut ShaderLibrary, activity: &Activity) -> Result<ShadowMap> {
+        let features = if packed { vec!["PACKED_DEPTH".to_string()] } else { Vec::new() };
+        let program = shaders.variant("shadow", &features, &[(ATTRIB_POSITION, "position")], activity)?;
+        let mut previous_fbo: GLint = 0;
+        gl_call!(GetIntegerv(::gl::FRAMEBUFFER_BINDING, &mut previous_fbo));
+        let mut map = ShadowMap {
+            fbo: 0,
+            texture: 0,
+            depth_rb: 0,
+            size: size,
+            packed: packed,
+            program: program,
+            // Culling front faces keeps acne off lit surfaces.
+            pipeline: Pipeline::new(PipelineDesc {
+                depth: Some(DepthState { compare: Compare::Less, write: true }),
+                cull: Cull::Front,
+                ..PipelineDesc::default()
+            })?,
+            light_view_proj: crate::math::M4_IDENTITY,
+            previous_fbo: 0,
+            previous_viewport: [0; 4],
+            previous_clear_color: [0.0; 4],
+            previous_clear_depth: 1.0,
+            stamp: ContextStamp::now(),
+        };
+        gl_call!(GenFramebuffers(1, &mut map.fbo));
+        gl_call!(GenTextures(1, &mut map.texture));
+        state::bind_texture(0, map.texture);
+        // Depth textures can't be filtered on ES 2.0, so
+        // the shader does its own PCF from nearest samples.
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_MIN_FILTER, ::gl::NEAREST as GLint));
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_MAG_FILTER, ::gl::NEAREST as GLint));
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_WRAP_S, ::gl::CLAMP_TO_EDGE as GLint));
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_WRAP_T, ::gl::CLAMP_TO_EDGE as GLint));
+        state::bind_framebuffer(map.fbo);
+        if packed {
+            gl_call!(TexImage2D(::gl::TEXTURE_2D, 0, ::gl::RGBA as GLint, size, size, 0, ::gl::RGBA, ::gl::UNSIGNED_BYTE, std::ptr::null()));
+            gl_call!(GenRenderbuffers(1, &mut map.depth_rb));
+            gl_call!(BindRenderbuffer(::gl::RENDERBUFFER, map.depth_rb));
+            gl_call!(RenderbufferStorage(::gl::RENDERBUFFER, ::gl::DEPTH_COMPONENT16, size, size));
+            gl_call!(FramebufferTexture2D(::gl::FRAMEBUFFER, ::gl::COLOR_ATTACHMENT0, ::gl::TEXTURE_2D, map.texture, 0));
+            gl_call!(FramebufferRenderbuffer(::gl::FRAMEBUFFER, ::gl::DEPTH_ATTACHMENT, ::gl::RENDERBUFFER, map.depth_rb));
+        } else {
+            gl_call!(TexImage2D(::gl::TEXTURE_2D, 0, ::gl::DEPTH_COMPONENT as GLint, size, size, 0, ::gl::DEPTH_COMPONENT, ::gl::UNSIGNED_INT, std::ptr::null()));
+            gl_call!(FramebufferTexture2D(::gl::FRAMEBUFFER, ::gl::DEPTH_ATTACHMENT, ::gl::TEXTURE_2D, map.texture, 0));
+        }
+        debug::label(debug::LABEL_FRAMEBUFFER, map.fbo, "shadow map");
+        debug::label(debug::LABEL_TEXTURE, map.texture, "shadow map");
+        let status = gl_call!(CheckFramebufferStatus(::gl::FRAMEBUFFER));
+        state::bind_framebuffer(previous_fbo as GLuint);
+        if status != ::gl::FRAMEBUFFER_COMPLETE {
+            return Err(Error::FramebufferIncomplete(status));
+        }
+        return Ok(map);
+    }
+
+    pub fn is_packed(&self) -> bool {
+        return self.packed;
+    }
+
+    // Features a lit material needs to sample this map.
+    pub fn features(&self) -> Vec<String> {
+        let mut features = vec!["SHADOWS".to_string()];
+        if self.packed {
+            features.push("PACKED_DEPTH".to_string());
+        }
+        return features;
+    }
+
+    pub fn light_view_proj(&self) -> &Matrix4 {
+        return &self.light_view_proj;
+    }
+
+    // Starts the shadow pass with an orthographic light
+    // frustum fitted around a bounding sphere of everything
+    // that should cast or receive shadows.
+    pub unsafe fn begin(&mut self, light: &DirectionalLight, center: Vector3, radius: f32) {
+        let dir = light.direction.normalize();
+        let up = if dir.y.abs() > 0.99 { Vector3::new(0.0, 0.0, 1.0) } else { Vector3::new(0.0, 1.0, 0.0) };
+        let view = Matrix4::look_at(center - dir * (radius * 2.0), center, up);
+        let proj = Matrix4::orthographic(-radius, radius, -radius, radius, radius, radius * 3.0);
+        self.light_view_proj = proj * view;
+        gl_call!(GetIntegerv(::gl::FRAMEBUFFER_BINDING, &mut self.previous_fbo));
+        gl_call!(GetIntegerv(::gl::VIEWPORT, self.previous_viewport.as_mut_ptr()));
+        gl_call!(GetFloatv(::gl::COLOR_CLEAR_VALUE, self.previous_clear_color.as_mut_ptr()));
+        gl_call!(GetFloatv(::gl::DEPTH_CLEAR_VALUE, &mut self.previous_clear_depth));
+        state::bind_framebuffer(self.fbo);
+        state::viewport(0, 0, self.size, self.size);
+        gl_call!(ClearColor(1.0, 1.0, 1.0, 1.0));
+        gl_call!(ClearDepthf(1.0));
+        self.pipeline.apply();
+        gl_call!(Clear(::gl::COLOR_BUFFER_BIT | ::gl::DEPTH_BUFFER_BIT));
+        self.program.bind();
+    }
+
+    pub unsafe fn draw(&self, model: &Matrix4, mesh: &Mesh) {
+        let mvp = self.light_view_proj * *model;
+        gl_call!(UniformMatrix4fv(self.program.uniform_location("u_mvp"), 1, ::gl::FALSE, mvp.to_array().as_ptr()));
+        mesh.draw();
+    }
+
+    pub unsafe fn end(&mut self) {
+        state::cull_face(::gl::BACK);
+        state::set_enabled(::gl::CULL_FACE, false);
+        state::bind_framebuffer(self.previous_fbo as GLuint);
+        let v = self.previous_viewport;
+        state::viewport(v[0], v[1], v[2], v[3]);
+        let c = self.previous_clear_color;
+        gl_call!(ClearColor(c[0], c[1], c[2], c[3]));
+        gl_call!(ClearDepthf(self.previous_clear_depth));
+    }
+
+    // Binds the map for a lit shader built with features().
+    pub unsafe fn bind(&self, program: &Program, bias: f32) {
+        state::bind_texture(SHADOW_TEXTURE_UNIT, self.texture);
+        gl_call!(Uniform1i(program.uniform_location("u_shadow_map"), SHADOW_TEXTURE_UNIT as GLint));
+        gl_call!(UniformMatrix4fv(program.uniform_location("u_light_view_proj"), 1, ::gl::FALSE, self.light_view_proj.to_array().as_ptr()));
+        gl_call!(Uniform1f(program.uniform_location("u_shadow_bias"), bias));
+        gl_call!(Uniform2f(program.uniform_location("u_shadow_texel"), 1.0 / self.size as f32, 1.0 / self.size as f32));
+    }
+}
+
+impl Drop for ShadowMap {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        unsafe {
+            state::deleted_framebuffer(self.fbo);
+            state::deleted_texture(self.texture);
+            gl_call!(DeleteFramebuffers(1, &self.fbo));
+            gl_call!(DeleteTextures(1, &self.texture));
+            if self.depth_rb != 0 {
+                gl_call!(DeleteRenderbuffers(1, &self.depth_rb));
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::directives;
+use crate::graphics::shader::{Program, ShaderLibrary, read_source};
+use crate::graphics::texture::{Texture, TextureLibrary};
+use crate::graphics::mesh::STANDARD_ATTRIBUTES;
+use crate::graphics::debug::gl_call;
+use crate::bridge::activity::Activity;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Materials are plain text assets, one directive per line.
+// Blank lines and anything after a '#' are ignored.
+//
+//   shader basic
+//   feature LIGHTING
+//   texture u_albedo shared/textures/navi.png
+//   uniform u_color 0.25 0.5 0.75 1.0
+//
+// `shader` names android/shaders/<name>/<name>.{vert,frag},
+// each `feature` is #defined when compiling the variant and
+// uniforms take one to four floats.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UniformValue {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl UniformValue {
+    pub unsafe fn upload(&self, location: GLint) {
+        match self {
+            UniformValue::Float(v) => gl_call!(Uniform1f(location, *v)),
+            UniformValue::Vec2(v) => gl_call!(Uniform2fv(location, 1, v.as_ptr())),
+            UniformValue::Vec3(v) => gl_call!(Uniform3fv(location, 1, v.as_ptr())),
+            UniformValue::Vec4(v) => gl_call!(Uniform4fv(location, 1, v.as_ptr())),
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MaterialDef {
+    pub shader: String,
+    pub features: Vec<String>,
+    // (sampler uniform, texture asset path)
+    pub textures: Vec<(String, String)>,
+    pub uniforms: Vec<(String, UniformValue)>,
+}
+
+impl MaterialDef {
+    pub fn load(path: &str, activity: &Activity) -> Result<MaterialDef> {
+        return Self::parse(&read_source(path, activity)?);
+    }
+
+    pub fn parse(text: &str) -> Result<MaterialDef> {
+        let mut shader = None;
+        let mut def = MaterialDef {
+            shader: String::new(),
+            features: Vec::new(),
+            textures: Vec::new(),
+            uniforms: Vec::new(),
+        };
+        for (line, words) in directives::lines(text) {
+            let (directive, args) = (words[0], &words[1..]);
+            match (directive, args.len()) {
+                ("shader", 1) => shader = Some(args[0].to_string()),
+                ("feature", 1) => def.features.push(args[0].to_string()),
+                ("texture", 2) => def.textures.push((args[0].to_string(), args[1].to_string())),
+                ("uniform", 2..=5) => {
+                    let mut v = [0.0f32; 4];
+                    for (j, a) in args[1..].iter().enumerate() {
+                        v[j] = a.parse().map_err(|_| directives::error("material", line, "uniform values must be numbers"))?;
+                    }
+                    let value = match args.len() - 1 {
+                        1 => UniformValue::Float(v[0]),
+                        2 => UniformValue::Vec2([v[0], v[1]]),
+                        3 => UniformValue::Vec3([v[0], v[1], v[2]]),
+                        _ => UniformValue::Vec4(v),
+                    };
+                    def.uniforms.push((args[0].to_string(), value));
+                },
+                ("shader", _) | ("feature", _) | ("texture", _) | ("uniform", _) =>
+                    return Err(directives::error("material", line, "wrong number of arguments")),
+                _ => return Err(directives::error("material", line, "unknown directive")),
+            };
+        }
+        def.shader = shader.ok_or_else(|| Error::ParseError("material: no shader given".to_string()))?;
+        return Ok(def);
+    }
+}
+
+static NEXT_MATERIAL_ID: AtomicU64 = AtomicU64::new(1);
+
+// A definition resolved against a compiled shader variant,
+// with uniform locations looked up once and textures
+// assigned to fixed units.
+pub struct Material {
+    id: u64,
+    program: Rc<Program>,
+    textures: Vec<(GLint, Rc<Texture>)>,
+    uniforms: Vec<(GLint, UniformValue)>,
+}
+
+impl Material {
+    pub unsafe fn new(def: &MaterialDef, shaders: &mut ShaderLibrary, textures: &TextureLibrary,
+      activity: &Activity) -> Result<Material> {
+        let program = shaders.variant(&def.shader, &def.features, STANDARD_ATTRIBUTES, activity)?;
+        let mut bound = Vec::with_capacity(def.textures.len());
+        for (sampler, path) in &def.textures {
+            let texture = textures.get(path).ok_or_else(|| Error::MissingResource(path.clone()))?;
+            bound.push((program.uniform_location(sampler), texture));
+        }
+        let uniforms = def.uniforms.iter()
+            .map(|(name, value)| (program.uniform_location(name), *value))
+            .collect();
+        return Ok(Material {
+            id: NEXT_MATERIAL_ID.fetch_add(1, Ordering::Relaxed),
+            program: program,
+            textures: bound,
+            uniforms: uniforms,
+        });
+    }
+
+    pub fn program(&self) -> &Program {
+        return &self.program;
+    }
+
+    // Draws sorted by this key change programs least often.
+    pub fn sort_key(&self) -> (GLuint, u64) {
+        return (self.program.id(), self.id);
+    }
+
+    // Binds the program and textures and uploads the
+    // uniforms. The state cache skips the binds when they're
+    // already current; uniforms always go up, since another
+    // material on the same program may have changed them.
+    pub unsafe fn bind(&self) {
+        self.program.bind();
+        for (unit, (location, texture)) in self.textures.iter().enumerate() {
+            texture.bind(unit as u32);
+            gl_call!(Uniform1i(*location, unit as GLint));
+        }
+        for (location, value) in &self.uniforms {
+            value.upload(*location);
+        }
+    }
+
+    pub unsafe fn set(&mut self, name: &str, value: UniformValue) {
+        let location = self.program.uniform_location(name);
+        match self.uniforms.iter_mut().find(|(l, _)| *l == location) {
+            Some(u) => u.1 = value,
+            None => self.uniforms.push((location, value)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_directives() {
+        let def = MaterialDef::parse("\
+# a comment
+shader basic
+feature LIGHTING   # trailing comment
+texture u_albedo shared/textures/navi.png
+
+uniform u_scale 2
+uniform u_color 0.25 0.5 0.75 1.0
+").unwrap();
+        assert_eq!(def.shader, "basic");
+        assert_eq!(def.features, vec!["LIGHTING".to_string()]);
+        assert_eq!(def.textures, vec![("u_albedo".to_string(), "shared/textures/navi.png".to_string())]);
+        assert_eq!(def.uniforms[0], ("u_scale".to_string(), UniformValue::Float(2.0)));
+        assert_eq!(def.uniforms[1], ("u_color".to_string(), UniformValue::Vec4([0.25, 0.5, 0.75, 1.0])));
+    }
+
+    fn error_message(text: &str) -> String {
+        return directives::error_message(MaterialDef::parse(text));
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(error_message("shader basic\nuniform u_color red"),
+            "material line 2: uniform values must be numbers");
+        assert_eq!(error_message("shader basic\ntexture u_albedo"),
+            "material line 2: wrong number of arguments");
+        assert_eq!(error_message("shade basic"), "material line 1: unknown directive");
+        assert_eq!(error_message("uniform u_color 1 2 3 4 5"), "material line 1: wrong number of arguments");
+    }
+
+    #[test]
+    fn missing_shader_has_no_line() {
+        assert_eq!(error_message("feature LIGHTING"), "material: no shader given");
+    }
+}
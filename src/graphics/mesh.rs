@@ -0,0 +1,96 @@
+use ::gl::types::*;
+use crate::graphics::model::MeshData;
+use crate::graphics::handle::BufferHandle;
+use crate::graphics::restore::{self, BufferSource};
+use crate::graphics::state;
+use crate::graphics::debug::gl_call;
+use crate::math::Vector3;
+use std::ffi::c_void;
+use std::sync::Arc;
+
+// Attribute locations shared by every mesh shader.
+pub const ATTRIB_POSITION: GLuint = 0;
+pub const ATTRIB_NORMAL: GLuint = 1;
+pub const ATTRIB_TEXCOORD: GLuint = 2;
+pub const ATTRIB_JOINTS: GLuint = 3;
+pub const ATTRIB_WEIGHTS: GLuint = 4;
+
+pub const STANDARD_ATTRIBUTES: &[(GLuint, &str)] = &[
+    (ATTRIB_POSITION, "position"),
+    (ATTRIB_NORMAL, "normal"),
+    (ATTRIB_TEXCOORD, "texcoord"),
+    (ATTRIB_JOINTS, "joints"),
+    (ATTRIB_WEIGHTS, "weights"),
+];
+
+// Registers a static buffer with restore, so it survives
+// the context going away.
+pub(crate) unsafe fn static_buffer(target: GLenum, data: Vec<u8>) -> BufferHandle {
+    return restore::buffer(BufferSource { target: target, usage: ::gl::STATIC_DRAW, data: Arc::new(data) });
+}
+
+// Index buffer for a mesh of `vertex_count` vertices and the
+// type to draw it with. Indices are narrowed to 16 bits
+// whenever possible since ES 2.0 only guarantees those
+// without OES_element_index_uint.
+pub(crate) unsafe fn index_buffer(indices: &[u32], vertex_count: usize) -> (BufferHandle, GLenum) {
+    if vertex_count <= u16::MAX as usize + 1 {
+        let bytes: Vec<u8> = indices.iter().flat_map(|i| (*i as u16).to_ne_bytes()).collect();
+        return (static_buffer(::gl::ELEMENT_ARRAY_BUFFER, bytes), ::gl::UNSIGNED_SHORT);
+    }
+    let bytes: Vec<u8> = indices.iter().flat_map(|i| i.to_ne_bytes()).collect();
+    return (static_buffer(::gl::ELEMENT_ARRAY_BUFFER, bytes), ::gl::UNSIGNED_INT);
+}
+
+// Mesh uploaded to GPU buffers. The buffers are restored
+// after a context loss, keeping a CPU copy to do it.
+pub struct Mesh {
+    vbo: BufferHandle,
+    ibo: BufferHandle,
+    index_count: GLsizei,
+    index_type: GLenum,
+}
+
+impl Mesh {
+    pub unsafe fn new(data: &MeshData) -> Mesh {
+        // Interleave as position, normal.
+        let mut vertices: Vec<u8> = Vec::with_capacity(data.positions.len() * 24);
+        for (i, p) in data.positions.iter().enumerate() {
+            let n = data.normals.get(i).copied().unwrap_or(Vector3::new(0.0, 0.0, 1.0));
+            for f in [p.x, p.y, p.z, n.x, n.y, n.z] {
+                vertices.extend_from_slice(&f.to_ne_bytes());
+            }
+        }
+        let (ibo, index_type) = index_buffer(&data.indices, data.positions.len());
+        return Mesh {
+            vbo: static_buffer(::gl::ARRAY_BUFFER, vertices),
+            ibo: ibo,
+            index_count: data.indices.len() as GLsizei,
+            index_type: index_type,
+        };
+    }
+
+    // Nothing is drawn while the buffers are lost.
+    pub unsafe fn draw(&self) {
+        let (vbo, ibo) = match (self.vbo.get(), self.ibo.get()) {
+            (Some(vbo), Some(ibo)) => (vbo, ibo),
+            _ => return,
+        };
+        state::bind_buffer(::gl::ARRAY_BUFFER, vbo);
+        state::bind_buffer(::gl::ELEMENT_ARRAY_BUFFER, ibo);
+        gl_call!(EnableVertexAttribArray(ATTRIB_POSITION));
+        gl_call!(EnableVertexAttribArray(ATTRIB_NORMAL));
+        gl_call!(VertexAttribPointer(ATTRIB_POSITION, 3, ::gl::FLOAT, ::gl::FALSE, 24, std::ptr::null()));
+        gl_call!(VertexAttribPointer(ATTRIB_NORMAL, 3, ::gl::FLOAT, ::gl::FALSE, 24, 12 as *const c_void));
+        gl_call!(DrawElements(::gl::TRIANGLES, self.index_count, self.index_type, std::ptr::null()));
+        gl_call!(DisableVertexAttribArray(ATTRIB_POSITION));
+        gl_call!(DisableVertexAttribArray(ATTRIB_NORMAL));
+    }
+}
+
+impl Drop for Mesh {
+    fn drop(&mut self) {
+        self.vbo.release();
+        self.ibo.release();
+    }
+}
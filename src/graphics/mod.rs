@@ -1,4 +1,34 @@
 pub mod gl;
+pub mod debug;
+pub mod debug_draw;
+pub mod profiler;
+pub mod pacing;
+pub mod state;
+pub mod pipeline;
+pub mod handle;
+pub mod restore;
+pub mod commands;
+pub mod shader;
+pub mod texture;
+pub mod model;
+pub mod mesh;
+pub mod instancing;
+pub mod scene;
+pub mod animation;
+pub mod skinning;
+pub mod material;
+pub mod lighting;
+pub mod target;
+pub mod postfx;
+pub mod resolution;
+pub mod particles;
+pub(crate) mod directives;
+
+#[path="font/mod.rs"]
+pub mod font;
+
+#[path="capture/mod.rs"]
+pub mod capture;
 
 // Graphics context for various frameworks
 // like OpenGL, Vulkan, etc. These are
@@ -15,3 +45,37 @@ pub enum Context {
     // Metal,
     // DX11,
 }
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    BridgeError(crate::bridge::Error),
+    IOError(std::io::Error),
+    ShaderCompileError(String),
+    ProgramLinkError(String),
+    InvalidFormat(&'static str),
+    ParseError(String),
+    MissingResource(String),
+    FramebufferIncomplete(u32),
+    Unsupported(String),
+    UTF8DecodeError,
+}
+
+impl From<crate::bridge::Error> for Error {
+    fn from(e: crate::bridge::Error) -> Self {
+        return Error::BridgeError(e);
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        return Error::IOError(e);
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(_e: std::str::Utf8Error) -> Self {
+        return Error::UTF8DecodeError;
+    }
+}
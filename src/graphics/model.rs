@@ -0,0 +1,202 @@
+use crate::graphics::{Result, Error};
+use crate::bridge::activity::{Activity, Asset};
+use crate::math::{Matrix3, Matrix4, Vector3};
+use crate::utils::ByteReader;
+use std::path::Path;
+
+// HBT1 model layout. Everything is little-endian and
+// offsets/sizes are counted in 4-byte words.
+//
+//   "HBT1", u32 flags (0)
+//   u32 mesh section offset, u32 reserved
+//   node tree, depth first. Each node is
+//     u32 size (including all of its descendants)
+//     u32 mesh (1-based index into the meshes, 0 for none)
+//     u32 child count
+//     Matrix3 basis, Vector3 translation
+//   followed directly by its children.
+//   mesh section, meshes back to back until the end:
+//     3 reserved words, u32 vertex count, u32 triangle count
+//     vertex count * Vector3 positions
+//     triangle count * 3 u32 indices
+const MAGIC: &[u8; 4] = b"HBT1";
+const NODE_HEADER_WORDS: u32 = 15;
+
+pub struct ModelNode {
+    // Index of the parent in Model::nodes, which always
+    // comes before its children.
+    pub parent: Option<usize>,
+    pub transform: Matrix4,
+    pub mesh: Option<usize>,
+}
+
+pub struct MeshData {
+    pub positions: Vec<Vector3>,
+    // HBT1 doesn't store normals, so they're
+    // generated from the triangles on load.
+    pub normals: Vec<Vector3>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    // Area weighted vertex normals. Hard edges need split
+    // vertices, which exporters already produce.
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); self.positions.len()];
+        for tri in self.indices.chunks_exact(3) {
+            let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+            let n = (self.positions[b] - self.positions[a]).cross(self.positions[c] - self.positions[a]);
+            for i in [a, b, c] {
+                normals[i] = normals[i] + n;
+            }
+        }
+        self.normals = normals.into_iter().map(|n| n.normalize()).collect();
+    }
+}
+
+pub struct Model {
+    pub nodes: Vec<ModelNode>,
+    pub meshes: Vec<MeshData>,
+}
+
+fn bad_model() -> Error {
+    return Error::InvalidFormat("truncated or corrupt HBT1 model");
+}
+
+fn read_vector3(r: &mut ByteReader) -> Option<Vector3> {
+    return Some(Vector3 { x: r.f32_le()?, y: r.f32_le()?, z: r.f32_le()? });
+}
+
+impl Model {
+    pub fn load<P: AsRef<Path>>(path: P, activity: &Activity) -> Result<Model> {
+        let mmap = Asset::map(path, activity)?;
+        return Self::from_bytes(&mmap);
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Model> {
+        let mut r = ByteReader::new(data);
+        if r.bytes(4) != Some(&MAGIC[..]) {
+            return Err(Error::InvalidFormat("not an HBT1 model"));
+        }
+        let _flags = r.u32_le().ok_or_else(bad_model)?;
+        let mesh_offset = r.u32_le().ok_or_else(bad_model)? as usize * 4;
+        let _reserved = r.u32_le().ok_or_else(bad_model)?;
+        if mesh_offset > data.len() {
+            return Err(bad_model());
+        }
+        let mut nodes = Vec::new();
+        // Root nodes fill everything up to the mesh section.
+        let mut tree = ByteReader::at(&data[..mesh_offset], r.position());
+        while tree.remaining() > 0 {
+            Self::read_node(&mut tree, None, &mut nodes)?;
+        }
+        let mut meshes = Vec::new();
+        let mut r = ByteReader::at(data, mesh_offset);
+        while r.remaining() > 0 {
+            r.skip(12);
+            let vertex_count = r.u32_le().ok_or_else(bad_model)? as usize;
+            let triangle_count = r.u32_le().ok_or_else(bad_model)? as usize;
+            // Check the counts against the file before allocating.
+            if vertex_count.saturating_mul(12).saturating_add(triangle_count.saturating_mul(12)) > r.remaining() {
+                return Err(bad_model());
+            }
+            let mut positions = Vec::with_capacity(vertex_count);
+            for _ in 0..vertex_count {
+                positions.push(read_vector3(&mut r).ok_or_else(bad_model)?);
+            }
+            let mut indices = Vec::with_capacity(triangle_count * 3);
+            for _ in 0..triangle_count * 3 {
+                let index = r.u32_le().ok_or_else(bad_model)?;
+                if index as usize >= vertex_count {
+                    return Err(Error::InvalidFormat("HBT1 index out of range"));
+                }
+                indices.push(index);
+            }
+            let mut mesh = MeshData { positions: positions, normals: Vec::new(), indices: indices };
+            mesh.compute_normals();
+            meshes.push(mesh);
+        }
+        for node in &nodes {
+            if node.mesh.map_or(false, |m| m >= meshes.len()) {
+                return Err(Error::InvalidFormat("HBT1 node references a missing mesh"));
+            }
+        }
+        return Ok(Model { nodes: nodes, meshes: meshes });
+    }
+
+    fn read_node(r: &mut ByteReader, parent: Option<usize>, nodes: &mut Vec<ModelNode>) -> Result<()> {
+        let start = r.position();
+        let size = r.u32_le().ok_or_else(bad_model)?;
+        let mesh = r.u32_le().ok_or_else(bad_model)?;
+        let children = r.u32_le().ok_or_else(bad_model)?;
+        let basis = Matrix3 {
+            v1: read_vector3(r).ok_or_else(bad_model)?,
+            v2: read_vector3(r).ok_or_else(bad_model)?,
+            v3: read_vector3(r).ok_or_else(bad_model)?,
+        };
+        let translation = read_vector3(r).ok_or_else(bad_model)?;
+        if size < NODE_HEADER_WORDS {
+            return Err(bad_model());
+        }
+        let index = nodes.len();
+        nodes.push(ModelNode {
+            parent: parent,
+            transform: Matrix4::from_m3_v3(basis, translation),
+            mesh: match mesh {
+                0 => None,
+                m => Some(m as usize - 1),
+            },
+        });
+        for _ in 0..children {
+            Self::read_node(r, Some(index), nodes)?;
+        }
+        // The size has to account for exactly the children read.
+        if r.position() != start + size as usize * 4 {
+            return Err(bad_model());
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIMPLE_BOX: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shared/models/SimpleBox.model"));
+
+    #[test]
+    fn loads_simple_box() {
+        let model = Model::from_bytes(SIMPLE_BOX).unwrap();
+        assert_eq!(model.nodes.len(), 2);
+        assert!(model.nodes[0].parent.is_none() && model.nodes[0].mesh.is_none());
+        assert_eq!((model.nodes[1].parent, model.nodes[1].mesh), (Some(0), Some(0)));
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        // Four vertices per face, so every face gets flat normals.
+        assert_eq!((mesh.positions.len(), mesh.indices.len()), (24, 36));
+        for p in &mesh.positions {
+            assert!([p.x, p.y, p.z].iter().all(|c| (c.abs() - 1.0).abs() < 1e-6));
+        }
+        assert_eq!(mesh.normals.len(), 24);
+        for n in &mesh.normals {
+            assert!((n.length() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_models() {
+        let mut data = SIMPLE_BOX.to_vec();
+        data[0] = b'X';
+        assert!(Model::from_bytes(&data).is_err());
+        assert!(Model::from_bytes(&SIMPLE_BOX[..SIMPLE_BOX.len() - 4]).is_err());
+        // Point the last index one past the vertices.
+        let mut data = SIMPLE_BOX.to_vec();
+        let end = data.len();
+        data[end - 4..].copy_from_slice(&24u32.to_le_bytes());
+        assert!(matches!(Model::from_bytes(&data), Err(Error::InvalidFormat("HBT1 index out of range"))));
+        // A root node claiming to be smaller than its header.
+        let mut data = SIMPLE_BOX.to_vec();
+        data[16..20].copy_from_slice(&14u32.to_le_bytes());
+        assert!(Model::from_bytes(&data).is_err());
+    }
+}
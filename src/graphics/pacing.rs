@@ -0,0 +1,167 @@
+use std::cell::RefCell;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Frame pacing. Left alone the render thread draws as fast
+// as the driver lets it, which mostly burns battery. The
+// settings here pick a swap interval for the platform to
+// pass to eglSwapInterval, and an optional frame-rate cap
+// that the render thread enforces by sleeping after each
+// frame. The sleep happens outside the profiled frame, so
+// frame times still measure real work.
+//
+// Where the platform can schedule presentation (Android's
+// EGL_ANDROID_presentation_time), next_present_time() is
+// the instant the current frame is due, so frames land on
+// an even cadence instead of whenever they finish.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FrameCap {
+    Unlimited,
+    // Paced by vsync alone, via a swap interval of 1.
+    DisplayRate,
+    Fps(u32),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PacingSettings {
+    // 0 swaps immediately, n waits for n vblanks.
+    pub swap_interval: i32,
+    pub cap: FrameCap,
+}
+
+impl Default for PacingSettings {
+    fn default() -> Self {
+        return PacingSettings { swap_interval: 1, cap: FrameCap::Fps(60) };
+    }
+}
+
+impl PacingSettings {
+    pub fn frame_interval(&self) -> Option<Duration> {
+        return match self.cap {
+            FrameCap::Fps(fps) if fps > 0 => Some(Duration::from_secs_f64(1.0 / fps as f64)),
+            _ => None,
+        };
+    }
+
+    // Vsync has to be on for DisplayRate to cap anything.
+    pub fn effective_swap_interval(&self) -> i32 {
+        if self.cap == FrameCap::DisplayRate {
+            return self.swap_interval.max(1);
+        }
+        return self.swap_interval.max(0);
+    }
+}
+
+// Bumped on every change so the render thread can tell
+// when to re-apply the swap interval.
+struct Shared {
+    settings: PacingSettings,
+    version: u64,
+}
+
+static SETTINGS: Mutex<Shared> = Mutex::new(Shared {
+    settings: PacingSettings { swap_interval: 1, cap: FrameCap::Fps(60) },
+    version: 1,
+});
+
+pub fn set_settings(settings: PacingSettings) {
+    let mut shared = SETTINGS.lock().unwrap();
+    if shared.settings != settings {
+        shared.settings = settings;
+        shared.version += 1;
+    }
+}
+
+pub fn settings() -> PacingSettings {
+    return SETTINGS.lock().unwrap().settings;
+}
+
+// The settings and their version, for platforms to compare
+// against the version they last applied.
+pub fn versioned_settings() -> (PacingSettings, u64) {
+    let shared = SETTINGS.lock().unwrap();
+    return (shared.settings, shared.version);
+}
+
+struct Pacer {
+    // When the frame being drawn is due.
+    deadline: Option<Instant>,
+}
+
+thread_local! {
+    static PACER: RefCell<Pacer> = RefCell::new(Pacer { deadline: None });
+}
+
+// When the frame now being drawn should be shown, if a
+// frame-rate cap is set. Render thread only.
+pub fn next_present_time() -> Option<Instant> {
+    let interval = settings().frame_interval()?;
+    return PACER.with(|p| {
+        let p = &mut *p.borrow_mut();
+        let now = Instant::now();
+        let deadline = match p.deadline {
+            Some(d) if d > now => d,
+            _ => now + interval,
+        };
+        p.deadline = Some(deadline);
+        return Some(deadline);
+    });
+}
+
+// Sleeps until the current frame's deadline and moves it
+// one interval on. A frame that ran past its deadline
+// doesn't sleep, and the cadence restarts from now rather
+// than rushing later frames to catch up. Call on the render
+// thread after swapping, without holding any locks other
+// threads wait on.
+pub fn wait() {
+    let interval = settings().frame_interval();
+    PACER.with(|p| {
+        let p = &mut *p.borrow_mut();
+        let interval = match interval {
+            Some(i) => i,
+            None => {
+                p.deadline = None;
+                return;
+            },
+        };
+        let now = Instant::now();
+        p.deadline = Some(match p.deadline {
+            Some(d) if d > now => {
+                std::thread::sleep(d - now);
+                d + interval
+            },
+            _ => now + interval,
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(swap_interval: i32, cap: FrameCap) -> PacingSettings {
+        return PacingSettings { swap_interval: swap_interval, cap: cap };
+    }
+
+    #[test]
+    fn only_fps_caps_set_an_interval() {
+        assert_eq!(with(1, FrameCap::Fps(50)).frame_interval(), Some(Duration::from_millis(20)));
+        assert_eq!(with(0, FrameCap::Fps(1)).frame_interval(), Some(Duration::from_secs(1)));
+        assert_eq!(with(1, FrameCap::Fps(0)).frame_interval(), None);
+        assert_eq!(with(1, FrameCap::Unlimited).frame_interval(), None);
+        assert_eq!(with(1, FrameCap::DisplayRate).frame_interval(), None);
+    }
+
+    #[test]
+    fn display_rate_forces_vsync() {
+        assert_eq!(with(0, FrameCap::DisplayRate).effective_swap_interval(), 1);
+        assert_eq!(with(2, FrameCap::DisplayRate).effective_swap_interval(), 2);
+        assert_eq!(with(0, FrameCap::Unlimited).effective_swap_interval(), 0);
+        assert_eq!(with(0, FrameCap::Fps(30)).effective_swap_interval(), 0);
+        // Negative intervals (adaptive vsync) aren't passed on.
+        assert_eq!(with(-1, FrameCap::Unlimited).effective_swap_interval(), 0);
+        assert_eq!(with(-1, FrameCap::DisplayRate).effective_swap_interval(), 1);
+    }
+}
@@ -0,0 +1,391 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::directives;
+use crate::graphics::gl::Context;
+use crate::graphics::shader::{Program, read_source};
+use crate::graphics::instancing::{Attribute, InstancedMesh};
+use crate::graphics::pipeline::{Pipeline, PipelineDesc, BlendState, DepthState, Compare, Cull};
+use crate::graphics::state;
+use crate::graphics::debug::gl_call;
+use crate::bridge::activity::Activity;
+use crate::math::{Matrix4, Vector3};
+
+// Emitters are text assets in the same style as materials.
+// `color` and `size` lines are curve keys over a particle's
+// normalized age, and ranges are "min max".
+//
+//   max_particles 128
+//   spawn_rate 60          particles per second
+//   burst 24               spawned at once on start
+//   lifetime 0.3 0.7       seconds
+//   speed 2.0 5.0
+//   direction 0 1 0        cone axis
+//   cone 60                half angle in degrees
+//   gravity 0 -9.8 0
+//   blend additive         or alpha
+//   color 0.0 1.0 0.9 0.5 1.0
+//   size 0.0 0.15
+
+// Simulation always advances in steps of this many seconds
+// so effects look the same at any frame rate.
+pub const FIXED_STEP: f32 = 1.0 / 60.0;
+// Stop catching up after a long hitch instead of spiralling.
+const MAX_STEPS_PER_UPDATE: u32 = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Blend {
+    Additive,
+    Alpha,
+}
+
+// Piecewise linear curve over [0, 1].
+#[derive(Clone)]
+pub struct Curve<const N: usize> {
+    keys: Vec<(f32, [f32; N])>,
+}
+
+impl<const N: usize> Curve<N> {
+    pub fn constant(v: [f32; N]) -> Curve<N> {
+        return Curve { keys: vec![(0.0, v)] };
+    }
+
+    pub fn add_key(&mut self, t: f32, v: [f32; N]) {
+        let i = self.keys.iter().position(|k| k.0 > t).unwrap_or(self.keys.len());
+        self.keys.insert(i, (t, v));
+    }
+
+    pub fn sample(&self, t: f32) -> [f32; N] {
+        let i = self.keys.iter().position(|k| k.0 > t).unwrap_or(self.keys.len());
+        if i == 0 {
+            return self.keys[0].1;
+        }
+        if i == self.keys.len() {
+            return self.keys[i - 1].1;
+        }
+        let (t0, a) = self.keys[i - 1];
+        let (t1, b) = self.keys[i];
+        let f = if t1 > t0 { (t - t0) / (t1 - t0) } else { 0.0 };
+        let mut out = [0.0; N];
+        for c in 0..N {
+            out[c] = a[c] + (b[c] - a[c]) * f;
+        }
+        return out;
+    }
+}
+
+#[derive(Clone)]
+pub struct EmitterDef {
+    pub max_particles: usize,
+    pub spawn_rate: f32,
+    pub burst: u32,
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    pub direction: Vector3,
+    pub cone_degrees: f32,
+    pub gravity: Vector3,
+    pub blend: Blend,
+    pub color: Curve<4>,
+    pub size: Curve<1>,
+}
+
+impl EmitterDef {
+    pub fn load(path: &str, activity: &Activity) -> Result<EmitterDef> {
+        return Self::parse(&read_source(path, activity)?);
+    }
+
+    pub fn parse(text: &str) -> Result<EmitterDef> {
+        let mut def = EmitterDef {
+            max_particles: 256,
+            spawn_rate: 10.0,
+            burst: 0,
+            lifetime: (1.0, 1.0),
+            speed: (1.0, 1.0),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+            cone_degrees: 0.0,
+            gravity: Vector3::new(0.0, 0.0, 0.0),
+            blend: Blend::Additive,
+            color: Curve { keys: Vec::new() },
+            size: Curve { keys: Vec::new() },
+        };
+        for (line, words) in directives::lines(text) {
+            let (directive, args) = (words[0], &words[1..]);
+            if directive == "blend" {
+                def.blend = match args {
+                    ["additive"] => Blend::Additive,
+                    ["alpha"] => Blend::Alpha,
+                    _ => return Err(directives::error("emitter", line, "blend must be additive or alpha")),
+                };
+                continue;
+            }
+            let v = directives::floats("emitter", line, args)?;
+            match (directive, v.len()) {
+                ("max_particles", 1) => def.max_particles = v[0].max(0.0) as usize,
+                ("spawn_rate", 1) => def.spawn_rate = v[0].max(0.0),
+                ("burst", 1) => def.burst = v[0].max(0.0) as u32,
+                ("lifetime", 2) => def.lifetime = (v[0].max(0.001), v[1].max(0.001)),
+                ("speed", 2) => def.speed = (v[0], v[1]),
+                ("direction", 3) => def.direction = Vector3::new(v[0], v[1], v[2]).normalize(),
+                ("cone", 1) => def.cone_degrees = v[0].max(0.0).min(180.0),
+                ("gravity", 3) => def.gravity = Vector3::new(v[0], v[1], v[2]),
+                ("color", 5) => def.color.add_key(v[0], [v[1], v[2], v[3], v[4]]),
+                ("size", 2) => def.size.add_key(v[0], [v[1]]),
+                ("max_particles", _) | ("spawn_rate", _) | ("burst", _) | ("lifetime", _) | ("speed", _)
+                | ("direction", _) | ("cone", _) | ("gravity", _) | ("color", _) | ("size", _) =>
+                    return Err(directives::error("emitter", line, "wrong number of arguments")),
+                _ => return Err(directives::error("emitter", line, "unknown directive")),
+            };
+        }
+        if def.color.keys.is_empty() {
+            def.color = Curve::constant([1.0, 1.0, 1.0, 1.0]);
+        }
+        if def.size.keys.is_empty() {
+            def.size = Curve::constant([0.1]);
+        }
+        return Ok(def);
+    }
+}
+
+// Small xorshift generator so effects don't need
+// a dependency or the global libc rand state.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        return (self.0 >> 8) as f32 / (1u32 << 24) as f32;
+    }
+
+    fn range(&mut self, r: (f32, f32)) -> f32 {
+        return r.0 + (r.1 - r.0) * self.next();
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Particle {
+    position: Vector3,
+    velocity: Vector3,
+    age: f32,
+    lifetime: f32,
+}
+
+pub struct Emitter {
+    pub def: EmitterDef,
+    pub position: Vector3,
+    // Stopped emitters let their particles die off.
+    pub emitting: bool,
+    particles: Vec<Particle>,
+    accumulator: f32,
+    spawn_debt: f32,
+    rng: Rng,
+}
+
+impl Emitter {
+    pub fn new(def: EmitterDef, position: Vector3, seed: u32) -> Emitter {
+        let burst = def.burst;
+        let mut emitter = Emitter {
+            particles: Vec::with_capacity(def.max_particles),
+            def: def,
+            position: position,
+            emitting: true,
+            accumulator: 0.0,
+            spawn_debt: 0.0,
+            rng: Rng(seed | 1),
+        };
+        for _ in 0..burst {
+            emitter.spawn();
+        }
+        return emitter;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return !self.emitting && self.particles.is_empty();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.particles.len();
+    }
+
+    // Random direction inside the cone around def.direction.
+    fn cone_direction(&mut self) -> Vector3 {
+        let axis = self.def.direction;
+        let cos_max = self.def.cone_degrees.to_radians().cos();
+        let cos_theta = 1.0 - self.rng.next() * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = self.rng.next() * std::f32::consts::PI * 2.0;
+        let helper = if axis.y.abs() < 0.99 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+        let u = axis.cross(helper).normalize();
+        let v = axis.cross(u);
+        return axis * cos_theta + u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin());
+    }
+
+    fn spawn(&mut self) {
+        if self.particles.len() >= self.def.max_particles {
+            return;
+        }
+        let direction = self.cone_direction();
+        let speed = self.rng.range(self.def.speed);
+        let lifetime = self.rng.range(self.def.lifetime);
+        self.particles.push(Particle {
+            position: self.position,
+            velocity: direction * speed,
+            age: 0.0,
+            lifetime: lifetime,
+        });
+    }
+
+    fn step(&mut self) {
+        let dt = FIXED_STEP;
+        let gravity = self.def.gravity * dt;
+        for p in self.particles.iter_mut() {
+            p.age += dt;
+            p.velocity = p.velocity + gravity;
+            p.position = p.position + p.velocity * dt;
+        }
+        self.particles.retain(|p| p.age < p.lifetime);
+        if self.emitting {
+            self.spawn_debt += self.def.spawn_rate * dt;
+            while self.spawn_debt >= 1.0 {
+                self.spawn();
+                self.spawn_debt -= 1.0;
+            }
+        }
+    }
+
+    // Advances by frame time in fixed steps. Leftover
+    // time carries over to the next update.
+    pub fn update(&mut self, dt: f32) {
+        self.accumulator += dt.max(0.0);
+        let mut steps = 0;
+        while self.accumulator >= FIXED_STEP && steps < MAX_STEPS_PER_UPDATE {
+            self.step();
+            self.accumulator -= FIXED_STEP;
+            steps += 1;
+        }
+        if steps == MAX_STEPS_PER_UPDATE {
+            self.accumulator = 0.0;
+        }
+    }
+
+    // Appends center.xyz, size, color.rgba for each particle.
+    pub fn write_instances(&self, out: &mut Vec<f32>) {
+        for p in &self.particles {
+            let t = (p.age / p.lifetime).min(1.0);
+            let c = self.def.color.sample(t);
+            let s = self.def.size.sample(t)[0];
+            out.extend_from_slice(&[p.position.x, p.position.y, p.position.z, s, c[0], c[1], c[2], c[3]]);
+        }
+    }
+}
+
+const ATTRIB_CORNER: GLuint = 0;
+const ATTRIB_CENTER: GLuint = 1;
+const ATTRIB_SIZE: GLuint = 2;
+const ATTRIB_COLOR: GLuint = 3;
+const QUAD_CORNERS: [f32; 8] = [-0.5, -0.5, 0.5, -0.5, 0.5, 0.5, -0.5, 0.5];
+const QUAD_INDICES: [u16; 6] = [0, 1, 2, 0, 2, 3];
+
+// Draws emitters as camera-facing quads, one instance per
+// particle. See InstancedMesh for how ES 2.0 is handled.
+pub struct ParticleRenderer {
+    program: Program,
+    quad: InstancedMesh,
+    additive: Pipeline,
+    alpha: Pipeline,
+    instances: Vec<f32>,
+}
+
+impl ParticleRenderer {
+    pub unsafe fn new(context: &Context, activity: &Activity) -> Result<ParticleRenderer> {
+        let program = Program::load("particle", &[
+            (ATTRIB_CORNER, "corner"),
+            (ATTRIB_CENTER, "center"),
+            (ATTRIB_SIZE, "size"),
+            (ATTRIB_COLOR, "color"),
+        ], activity)?;
+        // Instance layout matches Emitter::write_instances.
+        let quad = InstancedMesh::new(context, &QUAD_CORNERS, &[Attribute::new(ATTRIB_CORNER, 2)], &QUAD_INDICES, &[
+            Attribute::new(ATTRIB_CENTER, 3),
+            Attribute::new(ATTRIB_SIZE, 1),
+            Attribute::new(ATTRIB_COLOR, 4),
+        ])?;
+        // Depth tested against the scene but not written,
+        // so particles don't cut into each other.
+        let desc = PipelineDesc {
+            blend: Some(BlendState::ADDITIVE),
+            depth: Some(DepthState { compare: Compare::LessEqual, write: false }),
+            cull: Cull::None,
+            ..PipelineDesc::default()
+        };
+        return Ok(ParticleRenderer {
+            program: program,
+            quad: quad,
+            additive: Pipeline::new(desc)?,
+            alpha: Pipeline::new(PipelineDesc { blend: Some(BlendState::ALPHA), ..desc })?,
+            instances: Vec::new(),
+        });
+    }
+
+    pub unsafe fn draw(&mut self, emitters: &[&Emitter], view: &Matrix4, proj: &Matrix4) {
+        self.program.bind();
+        let view_proj = *proj * *view;
+        // Rows of the view rotation are the camera axes.
+        gl_call!(UniformMatrix4fv(self.program.uniform_location("u_view_proj"), 1, ::gl::FALSE, view_proj.to_array().as_ptr()));
+        gl_call!(Uniform3f(self.program.uniform_location("u_camera_right"), view.v1.x, view.v2.x, view.v3.x));
+        gl_call!(Uniform3f(self.program.uniform_location("u_camera_up"), view.v1.y, view.v2.y, view.v3.y));
+        for emitter in emitters {
+            match emitter.def.blend {
+                Blend::Additive => self.additive.apply(),
+                Blend::Alpha => self.alpha.apply(),
+            };
+            self.instances.clear();
+            emitter.write_instances(&mut self.instances);
+            self.quad.draw(&self.instances);
+        }
+        state::depth_mask(true);
+        state::set_enabled(::gl::BLEND, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPARK: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shared/particles/spark.emitter"));
+
+    #[test]
+    fn parses_spark() {
+        let def = EmitterDef::parse(SPARK).unwrap();
+        assert_eq!((def.max_particles, def.spawn_rate, def.burst), (128, 60.0, 24));
+        assert_eq!((def.lifetime, def.speed), ((0.3, 0.7), (2.0, 5.0)));
+        assert_eq!((def.direction.x, def.direction.y, def.direction.z), (0.0, 1.0, 0.0));
+        assert_eq!((def.cone_degrees, def.gravity.y), (60.0, -9.8));
+        assert_eq!(def.blend, Blend::Additive);
+        assert_eq!(def.color.sample(0.0), [1.0, 0.9, 0.5, 1.0]);
+        assert_eq!(def.color.sample(1.0), [0.6, 0.1, 0.0, 0.0]);
+        let size = def.size.sample(0.5)[0];
+        assert!((size - 0.085).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let def = EmitterDef::parse("blend alpha\ncone 500").unwrap();
+        assert_eq!(def.blend, Blend::Alpha);
+        assert_eq!(def.cone_degrees, 180.0);
+        assert_eq!(def.color.sample(0.3), [1.0; 4]);
+        assert_eq!(def.size.sample(0.3), [0.1]);
+    }
+
+    fn error_message(text: &str) -> String {
+        return directives::error_message(EmitterDef::parse(text));
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(error_message("burst 4\nspeed 1"), "emitter line 2: wrong number of arguments");
+        assert_eq!(error_message("blend multiply"), "emitter line 1: blend must be additive or alpha");
+        assert_eq!(error_message("\n\ngravity 0 down 0"), "emitter line 3: values must be numbers");
+        assert_eq!(error_message("sparkle 1"), "emitter line 1: unknown directive");
+    }
+}
@@ -0,0 +1,362 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::gl::Context;
+use crate::graphics::state;
+
+// Fixed-function state as one immutable value. A PipelineDesc
+// says how blending, depth, stencil, culling, color writes
+// and polygon offset should be set for a draw; Pipeline::new
+// checks that it makes sense at all, check() that the
+// context can do it, and apply() sets all of it at once
+// through the state cache, so nothing a previous draw left
+// behind leaks into the next one.
+//
+// new() needs no context, so game threads can build
+// pipelines for command lists; the executor runs check()
+// before applying them.
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Factor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+    SrcAlphaSaturate,
+}
+
+impl Factor {
+    fn gl(self) -> GLenum {
+        return match self {
+            Factor::Zero => ::gl::ZERO,
+            Factor::One => ::gl::ONE,
+            Factor::SrcColor => ::gl::SRC_COLOR,
+            Factor::OneMinusSrcColor => ::gl::ONE_MINUS_SRC_COLOR,
+            Factor::DstColor => ::gl::DST_COLOR,
+            Factor::OneMinusDstColor => ::gl::ONE_MINUS_DST_COLOR,
+            Factor::SrcAlpha => ::gl::SRC_ALPHA,
+            Factor::OneMinusSrcAlpha => ::gl::ONE_MINUS_SRC_ALPHA,
+            Factor::DstAlpha => ::gl::DST_ALPHA,
+            Factor::OneMinusDstAlpha => ::gl::ONE_MINUS_DST_ALPHA,
+            Factor::SrcAlphaSaturate => ::gl::SRC_ALPHA_SATURATE,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Equation {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    // ES 3.0 or GL_EXT_blend_minmax.
+    Min,
+    Max,
+}
+
+impl Equation {
+    fn gl(self) -> GLenum {
+        return match self {
+            Equation::Add => ::gl::FUNC_ADD,
+            Equation::Subtract => ::gl::FUNC_SUBTRACT,
+            Equation::ReverseSubtract => ::gl::FUNC_REVERSE_SUBTRACT,
+            Equation::Min => ::gl::MIN,
+            Equation::Max => ::gl::MAX,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BlendState {
+    pub color_equation: Equation,
+    pub color_src: Factor,
+    pub color_dst: Factor,
+    pub alpha_equation: Equation,
+    pub alpha_src: Factor,
+    pub alpha_dst: Factor,
+}
+
+impl BlendState {
+    pub const fn new(equation: Equation, src: Factor, dst: Factor) -> BlendState {
+        return BlendState {
+            color_equation: equation,
+            color_src: src,
+            color_dst: dst,
+            alpha_equation: equation,
+            alpha_src: src,
+            alpha_dst: dst,
+        };
+    }
+
+    pub const ALPHA: BlendState = BlendState::new(Equation::Add, Factor::SrcAlpha, Factor::OneMinusSrcAlpha);
+    pub const PREMULTIPLIED: BlendState = BlendState::new(Equation::Add, Factor::One, Factor::OneMinusSrcAlpha);
+    pub const ADDITIVE: BlendState = BlendState::new(Equation::Add, Factor::SrcAlpha, Factor::One);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Compare {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+impl Compare {
+    fn gl(self) -> GLenum {
+        return match self {
+            Compare::Never => ::gl::NEVER,
+            Compare::Less => ::gl::LESS,
+            Compare::Equal => ::gl::EQUAL,
+            Compare::LessEqual => ::gl::LEQUAL,
+            Compare::Greater => ::gl::GREATER,
+            Compare::NotEqual => ::gl::NOTEQUAL,
+            Compare::GreaterEqual => ::gl::GEQUAL,
+            Compare::Always => ::gl::ALWAYS,
+        };
+    }
+}
+
+// With depth testing off GL doesn't write depth either, so
+// a pass that only writes depth uses Compare::Always.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DepthState {
+    pub compare: Compare,
+    pub write: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    Increment,
+    IncrementWrap,
+    Decrement,
+    DecrementWrap,
+    Invert,
+}
+
+impl StencilOp {
+    fn gl(self) -> GLenum {
+        return match self {
+            StencilOp::Keep => ::gl::KEEP,
+            StencilOp::Zero => ::gl::ZERO,
+            StencilOp::Replace => ::gl::REPLACE,
+            StencilOp::Increment => ::gl::INCR,
+            StencilOp::IncrementWrap => ::gl::INCR_WRAP,
+            StencilOp::Decrement => ::gl::DECR,
+            StencilOp::DecrementWrap => ::gl::DECR_WRAP,
+            StencilOp::Invert => ::gl::INVERT,
+        };
+    }
+}
+
+// Applies to front and back faces alike.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct StencilState {
+    pub compare: Compare,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    pub fail: StencilOp,
+    pub depth_fail: StencilOp,
+    pub pass: StencilOp,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Cull {
+    None,
+    Front,
+    Back,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PolygonOffset {
+    pub factor: f32,
+    pub units: f32,
+}
+
+// None for blend, depth, stencil or polygon offset
+// disables that stage; a disabled depth or stencil stage
+// also restores its write mask to write everything.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PipelineDesc {
+    pub blend: Option<BlendState>,
+    pub depth: Option<DepthState>,
+    pub stencil: Option<StencilState>,
+    pub cull: Cull,
+    pub color_mask: [bool; 4],
+    pub polygon_offset: Option<PolygonOffset>,
+}
+
+impl PipelineDesc {
+    // Fullscreen passes and overlays: nothing depth tested,
+    // culled or blended.
+    pub const FULLSCREEN: PipelineDesc = PipelineDesc {
+        blend: None,
+        depth: None,
+        stencil: None,
+        cull: Cull::None,
+        color_mask: [true; 4],
+        polygon_offset: None,
+    };
+}
+
+impl Default for PipelineDesc {
+    // Opaque geometry: depth tested and written,
+    // back faces culled, no blending.
+    fn default() -> Self {
+        return PipelineDesc {
+            blend: None,
+            depth: Some(DepthState { compare: Compare::LessEqual, write: true }),
+            stencil: None,
+            cull: Cull::Back,
+            color_mask: [true; 4],
+            polygon_offset: None,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Pipeline {
+    desc: PipelineDesc,
+}
+
+impl Pipeline {
+    pub fn new(desc: PipelineDesc) -> Result<Pipeline> {
+        if let Some(offset) = desc.polygon_offset {
+            if !offset.factor.is_finite() || !offset.units.is_finite() {
+                return Err(Error::Unsupported(format!("polygon offset {:?}", offset)));
+            }
+        }
+        return Ok(Pipeline { desc: desc });
+    }
+
+    // Whether the context supports everything it sets.
+    pub fn check(&self, context: &Context) -> Result<()> {
+        if let Some(blend) = self.desc.blend {
+            let minmax = [blend.color_equation, blend.alpha_equation].iter()
+                .any(|e| *e == Equation::Min || *e == Equation::Max);
+            if minmax && !context.supports_blend_minmax() {
+                return Err(Error::Unsupported("MIN/MAX blend equations need ES 3.0 or GL_EXT_blend_minmax".to_string()));
+            }
+        }
+        if self.desc.stencil.is_some() && context.stencil_bits == 0 {
+            return Err(Error::Unsupported("stencil state without a stencil buffer".to_string()));
+        }
+        return Ok(());
+    }
+
+    pub fn desc(&self) -> &PipelineDesc {
+        return &self.desc;
+    }
+
+    // Sets every piece of state the description covers.
+    pub unsafe fn apply(&self) {
+        let d = &self.desc;
+        match d.blend {
+            Some(b) => {
+                state::set_enabled(::gl::BLEND, true);
+                state::blend_equation(b.color_equation.gl(), b.alpha_equation.gl());
+                state::blend_func_separate(b.color_src.gl(), b.color_dst.gl(), b.alpha_src.gl(), b.alpha_dst.gl());
+            },
+            None => state::set_enabled(::gl::BLEND, false),
+        }
+        match d.depth {
+            Some(depth) => {
+                state::set_enabled(::gl::DEPTH_TEST, true);
+                state::depth_func(depth.compare.gl());
+                state::depth_mask(depth.write);
+            },
+            // So a later clear doesn't depend on draw order.
+            None => {
+                state::set_enabled(::gl::DEPTH_TEST, false);
+                state::depth_mask(true);
+            },
+        }
+        match d.stencil {
+            Some(s) => {
+                state::set_enabled(::gl::STENCIL_TEST, true);
+                state::stencil_func(s.compare.gl(), s.reference as GLint, s.read_mask as GLuint);
+                state::stencil_op(s.fail.gl(), s.depth_fail.gl(), s.pass.gl());
+                state::stencil_mask(s.write_mask as GLuint);
+            },
+            None => {
+                state::set_enabled(::gl::STENCIL_TEST, false);
+                state::stencil_mask(!0);
+            },
+        }
+        match d.cull {
+            Cull::None => state::set_enabled(::gl::CULL_FACE, false),
+            Cull::Front => {
+                state::set_enabled(::gl::CULL_FACE, true);
+                state::cull_face(::gl::FRONT);
+            },
+            Cull::Back => {
+                state::set_enabled(::gl::CULL_FACE, true);
+                state::cull_face(::gl::BACK);
+            },
+        }
+        state::color_mask(d.color_mask);
+        match d.polygon_offset {
+            Some(o) => {
+                state::set_enabled(::gl::POLYGON_OFFSET_FILL, true);
+                state::polygon_offset(o.factor, o.units);
+            },
+            None => state::set_enabled(::gl::POLYGON_OFFSET_FILL, false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::gl::ContextFlags;
+
+    fn es2(stencil_bits: u8) -> Context {
+        return Context { major: 2, minor: 0, flags: ContextFlags::default(), extensions: Vec::new(), stencil_bits: stencil_bits };
+    }
+
+    #[test]
+    fn new_only_rejects_nonsense() {
+        let offset = PolygonOffset { factor: f32::NAN, units: 1.0 };
+        assert!(Pipeline::new(PipelineDesc { polygon_offset: Some(offset), ..PipelineDesc::default() }).is_err());
+        let max = BlendState::new(Equation::Max, Factor::One, Factor::One);
+        assert!(Pipeline::new(PipelineDesc { blend: Some(max), ..PipelineDesc::default() }).is_ok());
+    }
+
+    #[test]
+    fn check_tests_the_context() {
+        let max = Pipeline::new(PipelineDesc {
+            blend: Some(BlendState::new(Equation::Max, Factor::One, Factor::One)),
+            ..PipelineDesc::default()
+        }).unwrap();
+        assert!(max.check(&es2(8)).is_err());
+        let mut with_minmax = es2(8);
+        with_minmax.extensions.push("GL_EXT_blend_minmax".to_string());
+        assert!(max.check(&with_minmax).is_ok());
+        let stencil = Pipeline::new(PipelineDesc {
+            stencil: Some(StencilState {
+                compare: Compare::Always,
+                reference: 1,
+                read_mask: 0xff,
+                write_mask: 0xff,
+                fail: StencilOp::Keep,
+                depth_fail: StencilOp::Keep,
+                pass: StencilOp::Replace,
+            }),
+            ..PipelineDesc::default()
+        }).unwrap();
+        assert!(stencil.check(&es2(0)).is_err());
+        assert!(stencil.check(&es2(8)).is_ok());
+        assert!(Pipeline::new(PipelineDesc::FULLSCREEN).unwrap().check(&es2(0)).is_ok());
+    }
+}
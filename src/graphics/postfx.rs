@@ -0,0 +1,390 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::gl::Context;
+use crate::graphics::shader::{Program, ShaderLibrary, read_source};
+use crate::graphics::target::RenderTarget;
+use crate::graphics::texture::{Texture, PixelFormat, Filter};
+use crate::graphics::pipeline::{Pipeline, PipelineDesc, BlendState, Equation, Factor};
+use crate::graphics::state;
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::gl_call;
+use crate::bridge::activity::Activity;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+const ATTRIB_POSITION: GLuint = 0;
+
+// Every effect can be flipped at runtime. Disabled effects
+// cost nothing except the composite, which always runs.
+#[derive(Copy, Clone, Debug)]
+pub struct PostSettings {
+    pub bloom: bool,
+    // Brightness where bloom starts, and how wide
+    // the soft transition into it is.
+    pub bloom_threshold: f32,
+    pub bloom_knee: f32,
+    pub bloom_intensity: f32,
+    pub grading: bool,
+    // Blend between the ungraded and graded colors.
+    pub grading_amount: f32,
+    pub fxaa: bool,
+    pub vignette: bool,
+    pub vignette_strength: f32,
+    pub vignette_softness: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        return PostSettings {
+            bloom: true,
+            bloom_threshold: 0.8,
+            bloom_knee: 0.2,
+            bloom_intensity: 0.6,
+            grading: true,
+            grading_amount: 1.0,
+            fxaa: true,
+            vignette: true,
+            vignette_strength: 0.35,
+            vignette_softness: 0.45,
+        };
+    }
+}
+
+// A .cube 3D LUT, converted to 8-bit and laid out as a
+// strip of size slices of size x size texels each.
+pub struct ColorLut {
+    pub size: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl ColorLut {
+    pub fn load(path: &str, activity: &Activity) -> Result<ColorLut> {
+        return Self::parse_cube(&read_source(path, activity)?);
+    }
+
+    // Only LUT_3D_SIZE and the table are used. Domains
+    // other than the default [0, 1] aren't supported.
+    pub fn parse_cube(text: &str) -> Result<ColorLut> {
+        let bad = |msg: &str| Error::ParseError(format!("cube LUT: {}", msg));
+        let mut size: Option<u32> = None;
+        let mut table: Vec<[f32; 3]> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap();
+            if first.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                if first == "LUT_3D_SIZE" {
+                    size = words.next().and_then(|w| w.parse().ok());
+                    if size.map_or(true, |s| s < 2 || s > 256) {
+                        return Err(bad("invalid LUT_3D_SIZE"));
+                    }
+                } else if first == "LUT_1D_SIZE" {
+                    return Err(bad("1D LUTs aren't supported"));
+                }
+                continue;
+            }
+            let mut rgb = [0.0f32; 3];
+            rgb[0] = first.parse().map_err(|_| bad("bad number"))?;
+            for v in rgb[1..].iter_mut() {
+                *v = words.next().and_then(|w| w.parse().ok()).ok_or_else(|| bad("bad number"))?;
+            }
+            table.push(rgb);
+        }
+        let size = size.ok_or_else(|| bad("missing LUT_3D_SIZE"))?;
+        let n = size as usize;
+        if table.len() != n * n * n {
+            return Err(bad("table size doesn't match LUT_3D_SIZE"));
+        }
+        // Red changes fastest in .cube files, then green, then blue.
+        let width = n * n;
+        let mut pixels = vec![255u8; width * n * 4];
+        for (i, rgb) in table.iter().enumerate() {
+            let (r, g, b) = (i % n, (i / n) % n, i / (n * n));
+            let dst = (g * width + b * n + r) * 4;
+            for c in 0..3 {
+                pixels[dst + c] = (rgb[c].max(0.0).min(1.0) * 255.0).round() as u8;
+            }
+        }
+        return Ok(ColorLut { size: size, pixels: pixels });
+    }
+}
+
+struct Passes {
+    bright: Rc<Program>,
+    downsample: Rc<Program>,
+    upsample: Rc<Program>,
+    composite: Rc<Program>,
+    fxaa: Rc<Program>,
+    // Each pass overwrites its target, except the bloom
+    // upsamples, which add onto theirs.
+    overwrite: Pipeline,
+    accumulate: Pipeline,
+}
+
+// Runs after the scene pass. The scene is drawn into
+// scene_target() between begin() and finish(), then
+// bloom, grading, vignette and FXAA are applied on the
+// way out to whatever framebuffer was bound at begin().
+pub struct PostChain {
+    pub settings: PostSettings,
+    passes: Passes,
+    scene: RenderTarget,
+    // Result of the composite when FXAA still has to run.
+    resolve: RenderTarget,
+    bloom: Vec<RenderTarget>,
+    bloom_levels: usize,
+    lut: Option<(Texture, u32)>,
+    // Lets grading stay a no-op without a LUT loaded.
+    black: Texture,
+    triangle: GLuint,
+    output_fbo: GLint,
+    output_viewport: [GLint; 4],
+    stamp: ContextStamp,
+}
+
+impl PostChain {
+    pub unsafe fn new(width: i32, height: i32, context: &Context, shaders: &mut ShaderLibrary,
+      activity: &Activity) -> Result<PostChain> {
+        let attributes = [(ATTRIB_POSITION, "position")];
+        let mut pass = |feature: &str| shaders.variant("post", &[feature.to_string()], &attributes, activity);
+        let passes = Passes {
+            bright: pass("BRIGHT")?,
+            downsample: pass("DOWNSAMPLE")?,
+            upsample: pass("UPSAMPLE")?,
+            composite: pass("COMPOSITE")?,
+            fxaa: pass("FXAA")?,
+            overwrite: Pipeline::new(PipelineDesc::FULLSCREEN)?,
+            accumulate: Pipeline::new(PipelineDesc {
+                blend: Some(BlendState::new(Equation::Add, Factor::One, Factor::One)),
+                ..PipelineDesc::FULLSCREEN
+            })?,
+        };
+        // ES 2.0 parts get a shorter, coarser bloom chain.
+        let bloom_levels = if context.is_version_at_least(3, 0) { 5 } else { 3 };
+        let mut triangle: GLuint = 0;
+        let vertices: [f32; 6] = [-1.0, -1.0, 3.0, -1.0, -1.0, 3.0];
+        gl_call!(GenBuffers(1, &mut triangle));
+        state::bind_buffer(::gl::ARRAY_BUFFER, triangle);
+        gl_call!(BufferData(::gl::ARRAY_BUFFER, 24, vertices.as_ptr() as *const c_void, ::gl::STATIC_DRAW));
+        let mut chain = PostChain {
+            settings: PostSettings::default(),
+            passes: passes,
+            scene: RenderTarget::new(width, height, true, Filter::Linear)?,
+            resolve: RenderTarget::new(width, height, false, Filter::Linear)?,
+            bloom: Vec::new(),
+            bloom_levels: bloom_levels,
+            lut: None,
+            black: Texture::new(1, 1, PixelFormat::RGBA8, Filter::Nearest, Some(&[0, 0, 0, 255]))?,
+            triangle: triangle,
+            output_fbo: 0,
+            output_viewport: [0; 4],
+            stamp: ContextStamp::now(),
+        };
+        chain.create_bloom(width, height, context)?;
+        chain.label_targets();
+        return Ok(chain);
+    }
+
+    unsafe fn create_bloom(&mut self, width: i32, height: i32, context: &Context) -> Result<()> {
+        self.bloom.clear();
+        // Start at half resolution, or a quarter on ES 2.0.
+        let shift = if context.is_version_at_least(3, 0) { 1 } else { 2 };
+        for level in 0..self.bloom_levels {
+            let (w, h) = ((width >> (shift + level)).max(1), (height >> (shift + level)).max(1));
+            self.bloom.push(RenderTarget::new(w, h, false, Filter::Linear)?);
+        }
+        return Ok(());
+    }
+
+    unsafe fn label_targets(&self) {
+        self.scene.set_label("post scene");
+        self.resolve.set_label("post resolve");
+        for (i, level) in self.bloom.iter().enumerate() {
+            level.set_label(&format!("post bloom {}", i));
+        }
+    }
+
+    pub unsafe fn resize(&mut self, width: i32, height: i32, context: &Context) -> Result<()> {
+        if width == self.scene.width && height == self.scene.height {
+            return Ok(());
+        }
+        self.scene = RenderTarget::new(width, height, true, Filter::Linear)?;
+        self.resolve = RenderTarget::new(width, height, false, Filter::Linear)?;
+        self.create_bloom(width, height, context)?;
+        self.label_targets();
+        return Ok(());
+    }
+
+    pub unsafe fn set_lut(&mut self, lut: Option<&ColorLut>) -> Result<()> {
+        self.lut = match lut {
+            Some(l) => {
+                let width = (l.size * l.size) as i32;
+                let mut max_size: GLint = 0;
+                gl_call!(GetIntegerv(::gl::MAX_TEXTURE_SIZE, &mut max_size));
+                if width > max_size {
+                    return Err(Error::InvalidFormat("LUT is too big for this GPU"));
+                }
+                Some((Texture::new(width, l.size as i32, PixelFormat::RGBA8, Filter::Linear, Some(&l.pixels))?, l.size))
+            },
+            None => None,
+        };
+        return Ok(());
+    }
+
+    pub fn scene_target(&self) -> &RenderTarget {
+        return &self.scene;
+    }
+
+    // Redirects drawing into the scene target. Whatever
+    // was bound before is where finish() will output to.
+    pub unsafe fn begin(&mut self) {
+        gl_call!(GetIntegerv(::gl::FRAMEBUFFER_BINDING, &mut self.output_fbo));
+        gl_call!(GetIntegerv(::gl::VIEWPORT, self.output_viewport.as_mut_ptr()));
+        self.scene.bind();
+    }
+
+    unsafe fn draw_pass(&self, program: &Program, source: &Texture) {
+        program.bind();
+        source.bind(0);
+        gl_call!(Uniform1i(program.uniform_location("u_source"), 0));
+        gl_call!(Uniform2f(program.uniform_location("u_texel"), 1.0 / source.width as f32, 1.0 / source.height as f32));
+        gl_call!(DrawArrays(::gl::TRIANGLES, 0, 3));
+    }
+
+    pub unsafe fn finish(&mut self) {
+        let s = self.settings;
+        self.passes.overwrite.apply();
+        state::bind_buffer(::gl::ARRAY_BUFFER, self.triangle);
+        gl_call!(EnableVertexAttribArray(ATTRIB_POSITION));
+        gl_call!(VertexAttribPointer(ATTRIB_POSITION, 2, ::gl::FLOAT, ::gl::FALSE, 8, std::ptr::null()));
+
+        if s.bloom && !self.bloom.is_empty() {
+            self.bloom[0].bind();
+            let bright = &self.passes.bright;
+            bright.bind();
+            gl_call!(Uniform1f(bright.uniform_location("u_threshold"), s.bloom_threshold));
+            gl_call!(Uniform1f(bright.uniform_location("u_knee"), s.bloom_knee.max(0.0001)));
+            self.draw_pass(bright, &self.scene.color);
+            for i in 1..self.bloom.len() {
+                self.bloom[i].bind();
+                self.draw_pass(&self.passes.downsample, &self.bloom[i - 1].color);
+            }
+            // Walk back up, adding each level onto the one above.
+            self.passes.accumulate.apply();
+            let up = &self.passes.upsample;
+            up.bind();
+            gl_call!(Uniform1f(up.uniform_location("u_radius"), 1.0));
+            for i in (1..self.bloom.len()).rev() {
+                self.bloom[i - 1].bind();
+                self.draw_pass(up, &self.bloom[i].color);
+            }
+            self.passes.overwrite.apply();
+        }
+
+        if s.fxaa {
+            self.resolve.bind();
+        } else {
+            self.bind_output();
+        }
+        let composite = &self.passes.composite;
+        composite.bind();
+        let bloom_tex = if s.bloom && !self.bloom.is_empty() { &self.bloom[0].color } else { &self.black };
+        bloom_tex.bind(1);
+        gl_call!(Uniform1i(composite.uniform_location("u_bloom"), 1));
+        gl_call!(Uniform1f(composite.uniform_location("u_bloom_intensity"), if s.bloom { s.bloom_intensity } else { 0.0 }));
+        match (&self.lut, s.grading) {
+            (Some((lut, size)), true) => {
+                lut.bind(2);
+                gl_call!(Uniform1f(composite.uniform_location("u_lut_size"), *size as f32));
+                gl_call!(Uniform1f(composite.uniform_location("u_grading"), s.grading_amount));
+            },
+            _ => {
+                self.black.bind(2);
+                gl_call!(Uniform1f(composite.uniform_location("u_lut_size"), 2.0));
+                gl_call!(Uniform1f(composite.uniform_location("u_grading"), 0.0));
+            },
+        };
+        gl_call!(Uniform1i(composite.uniform_location("u_lut"), 2));
+        gl_call!(Uniform1f(composite.uniform_location("u_vignette"), if s.vignette { s.vignette_strength } else { 0.0 }));
+        gl_call!(Uniform1f(composite.uniform_location("u_vignette_softness"), s.vignette_softness));
+        self.draw_pass(composite, &self.scene.color);
+
+        if s.fxaa {
+            self.bind_output();
+            self.draw_pass(&self.passes.fxaa, &self.resolve.color);
+        }
+        gl_call!(DisableVertexAttribArray(ATTRIB_POSITION));
+    }
+
+    unsafe fn bind_output(&self) {
+        let v = self.output_viewport;
+        state::bind_framebuffer(self.output_fbo as GLuint);
+        state::viewport(v[0], v[1], v[2], v[3]);
+    }
+}
+
+impl Drop for PostChain {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        state::deleted_buffers(&[self.triangle]);
+        unsafe { gl_call!(DeleteBuffers(1, &self.triangle)); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Identity LUT of size 2: red fastest, then green, then blue.
+    const IDENTITY_2: &str = "\
+TITLE \"identity\"
+# comment
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    #[test]
+    fn lays_out_slices_side_by_side() {
+        let lut = ColorLut::parse_cube(IDENTITY_2).unwrap();
+        assert_eq!(lut.size, 2);
+        // A 4x2 strip: blue picks the slice, red the column
+        // within it and green the row.
+        assert_eq!(lut.pixels.len(), 4 * 2 * 4);
+        let texel = |x: usize, y: usize| &lut.pixels[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4];
+        assert_eq!(texel(0, 0), &[0, 0, 0, 255]);
+        assert_eq!(texel(1, 0), &[255, 0, 0, 255]);
+        assert_eq!(texel(0, 1), &[0, 255, 0, 255]);
+        assert_eq!(texel(2, 0), &[0, 0, 255, 255]);
+        assert_eq!(texel(3, 1), &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn clamps_out_of_range_values() {
+        let text = IDENTITY_2.replace("1 1 1\n", "1.5 -0.5 0.5\n");
+        let lut = ColorLut::parse_cube(&text).unwrap();
+        assert_eq!(&lut.pixels[(4 + 3) * 4..(4 + 3) * 4 + 3], &[255, 0, 128]);
+    }
+
+    #[test]
+    fn rejects_bad_tables() {
+        assert!(ColorLut::parse_cube("0 0 0").is_err());
+        assert!(ColorLut::parse_cube("LUT_3D_SIZE 1\n0 0 0").is_err());
+        assert!(ColorLut::parse_cube("LUT_1D_SIZE 2\n0 0 0\n1 1 1").is_err());
+        assert!(ColorLut::parse_cube(&IDENTITY_2.replace("1 1 1\n", "")).is_err());
+        assert!(ColorLut::parse_cube(&IDENTITY_2.replace("1 1 1\n", "1 x 1\n")).is_err());
+    }
+}
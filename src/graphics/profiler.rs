@@ -0,0 +1,462 @@
+use ::gl::types::*;
+use crate::graphics::gl::Context;
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Frame profiler. The render loop brackets each frame with
+// begin_frame/end_frame and draw code brackets passes with
+// begin_pass/end_pass (or the pass() guard). CPU time is
+// measured with Instant. GPU time comes from
+// EXT_disjoint_timer_query when the context has it; those
+// results arrive a few frames late and are dropped whenever
+// the GPU reports a disjoint event.
+//
+// GPU queries can't nest, so a frame is timed as a run of
+// back to back queries: one per pass and one for each gap
+// between passes. Their sum is the frame's GPU time.
+//
+// Timing lives on the render thread. Summaries are published
+// to a global every few frames so other threads can read
+// them with snapshot().
+
+// Samples kept per series for averages and percentiles.
+const HISTORY: usize = 120;
+// Frames between published snapshots.
+const PUBLISH_INTERVAL: u64 = 30;
+// Frames of GPU queries allowed in flight before we stop
+// issuing new ones rather than stall on results.
+const MAX_PENDING_FRAMES: usize = 4;
+
+const TIME_ELAPSED_EXT: GLenum = 0x88BF;
+const QUERY_RESULT_EXT: GLenum = 0x8866;
+const QUERY_RESULT_AVAILABLE_EXT: GLenum = 0x8867;
+const GPU_DISJOINT_EXT: GLenum = 0x8FBB;
+
+type GenQueries = extern "system" fn(GLsizei, *mut GLuint);
+type BeginQuery = extern "system" fn(GLenum, GLuint);
+type EndQuery = extern "system" fn(GLenum);
+type GetQueryObjectuiv = extern "system" fn(GLuint, GLenum, *mut GLuint);
+type GetQueryObjectui64v = extern "system" fn(GLuint, GLenum, *mut u64);
+
+// Entry points from EXT_disjoint_timer_query.
+struct TimerQueries {
+    gen: GenQueries,
+    begin: BeginQuery,
+    end: EndQuery,
+    get_uiv: GetQueryObjectuiv,
+    get_ui64v: GetQueryObjectui64v,
+}
+
+impl TimerQueries {
+    unsafe fn load<F: Fn(&str) -> *const c_void>(loader: F) -> Option<TimerQueries> {
+        let get = |name: &str| -> Option<*const c_void> {
+            let p = loader(name);
+            return if p.is_null() { None } else { Some(p) };
+        };
+        return Some(TimerQueries {
+            gen: std::mem::transmute::<*const c_void, GenQueries>(get("glGenQueriesEXT")?),
+            begin: std::mem::transmute::<*const c_void, BeginQuery>(get("glBeginQueryEXT")?),
+            end: std::mem::transmute::<*const c_void, EndQuery>(get("glEndQueryEXT")?),
+            get_uiv: std::mem::transmute::<*const c_void, GetQueryObjectuiv>(get("glGetQueryObjectuivEXT")?),
+            get_ui64v: std::mem::transmute::<*const c_void, GetQueryObjectui64v>(get("glGetQueryObjectui64vEXT")?),
+        });
+    }
+}
+
+// Summary of one series in milliseconds.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Summary {
+    pub average: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+    pub samples: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct PassStats {
+    pub name: &'static str,
+    pub cpu: Summary,
+    pub gpu: Option<Summary>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+    pub frames: u64,
+    // Time spent inside begin_frame..end_frame.
+    pub cpu: Summary,
+    // Time between consecutive begin_frame calls.
+    pub interval: Summary,
+    pub gpu: Option<Summary>,
+    pub passes: Vec<PassStats>,
+}
+
+// Rolling window of samples in milliseconds.
+#[derive(Clone)]
+pub struct Series {
+    samples: Vec<f32>,
+    next: usize,
+}
+
+impl Series {
+    pub fn new() -> Series {
+        return Series { samples: Vec::with_capacity(HISTORY), next: 0 };
+    }
+
+    pub fn push(&mut self, ms: f32) {
+        if self.samples.len() < HISTORY {
+            self.samples.push(ms);
+        } else {
+            self.samples[self.next] = ms;
+        }
+        self.next = (self.next + 1) % HISTORY;
+    }
+
+    // Most recently pushed sample.
+    pub fn last(&self) -> Option<f32> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        return Some(self.samples[(self.next + self.samples.len() - 1) % self.samples.len()]);
+    }
+
+    pub fn summary(&self) -> Summary {
+        if self.samples.is_empty() {
+            return Summary::default();
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        // Nearest-rank percentile.
+        let rank = |p: f32| sorted[(((p / 100.0) * sorted.len() as f32).ceil() as usize).clamp(1, sorted.len()) - 1];
+        return Summary {
+            average: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p50: rank(50.0),
+            p95: rank(95.0),
+            p99: rank(99.0),
+            max: *sorted.last().unwrap(),
+            samples: sorted.len(),
+        };
+    }
+}
+
+struct Pass {
+    name: &'static str,
+    cpu: Series,
+    gpu: Series,
+}
+
+// Queries issued during one frame, tagged with the pass
+// they timed or None for time outside any pass.
+struct PendingFrame {
+    queries: Vec<(Option<usize>, GLuint)>,
+}
+
+struct Profiler {
+    timers: Option<TimerQueries>,
+    free_queries: Vec<GLuint>,
+    pending: Vec<PendingFrame>,
+    current: Option<PendingFrame>,
+    frames: u64,
+    frame_start: Option<Instant>,
+    last_begin: Option<Instant>,
+    cpu: Series,
+    interval: Series,
+    gpu: Series,
+    passes: Vec<Pass>,
+    active_pass: Option<(usize, Instant)>,
+    log_every: Option<Duration>,
+    last_log: Instant,
+}
+
+thread_local! {
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler {
+        timers: None,
+        free_queries: Vec::new(),
+        pending: Vec::new(),
+        current: None,
+        frames: 0,
+        frame_start: None,
+        last_begin: None,
+        cpu: Series::new(),
+        interval: Series::new(),
+        gpu: Series::new(),
+        passes: Vec::new(),
+        active_pass: None,
+        log_every: None,
+        last_log: Instant::now(),
+    });
+}
+
+static SNAPSHOT: Mutex<Option<FrameStats>> = Mutex::new(None);
+
+fn millis(d: Duration) -> f32 {
+    return d.as_secs_f32() * 1000.0;
+}
+
+impl Profiler {
+    unsafe fn query(&mut self) -> Option<GLuint> {
+        let timers = self.timers.as_ref()?;
+        if let Some(q) = self.free_queries.pop() {
+            return Some(q);
+        }
+        let mut q: GLuint = 0;
+        (timers.gen)(1, &mut q);
+        return Some(q);
+    }
+
+    fn pass_index(&mut self, name: &'static str) -> usize {
+        if let Some(i) = self.passes.iter().position(|p| p.name == name) {
+            return i;
+        }
+        self.passes.push(Pass { name: name, cpu: Series::new(), gpu: Series::new() });
+        return self.passes.len() - 1;
+    }
+
+    // Ends the running query, if any, and starts the next
+    // one in the current frame.
+    unsafe fn next_query(&mut self, pass: Option<usize>) {
+        if self.current.is_none() {
+            return;
+        }
+        let q = match self.query() {
+            Some(q) => q,
+            None => return,
+        };
+        let timers = self.timers.as_ref().unwrap();
+        let current = self.current.as_mut().unwrap();
+        if !current.queries.is_empty() {
+            (timers.end)(TIME_ELAPSED_EXT);
+        }
+        (timers.begin)(TIME_ELAPSED_EXT, q);
+        current.queries.push((pass, q));
+    }
+
+    // Reads back the oldest frame's queries if they are all done.
+    unsafe fn collect(&mut self) {
+        let timers = match self.timers.as_ref() {
+            Some(t) => t,
+            None => return,
+        };
+        while let Some(oldest) = self.pending.first() {
+            let mut ready = true;
+            for (_, id) in &oldest.queries {
+                let mut available: GLuint = 0;
+                (timers.get_uiv)(*id, QUERY_RESULT_AVAILABLE_EXT, &mut available);
+                ready &= available != 0;
+            }
+            if !ready {
+                return;
+            }
+            let frame = self.pending.remove(0);
+            // Checking disjoint also clears it for the next batch.
+            let mut disjoint: GLint = 0;
+            ::gl::GetIntegerv(GPU_DISJOINT_EXT, &mut disjoint);
+            let result = |id: GLuint| -> f32 {
+                let mut ns: u64 = 0;
+                (timers.get_ui64v)(id, QUERY_RESULT_EXT, &mut ns);
+                return ns as f32 / 1.0e6;
+            };
+            if disjoint == 0 {
+                let mut total = 0.0;
+                for (pass, id) in &frame.queries {
+                    let ms = result(*id);
+                    total += ms;
+                    if let Some(pass) = pass {
+                        self.passes[*pass].gpu.push(ms);
+                    }
+                }
+                self.gpu.push(total);
+            }
+            self.free_queries.extend(frame.queries.iter().map(|q| q.1));
+        }
+    }
+
+    fn stats(&self) -> FrameStats {
+        let has_gpu = self.timers.is_some();
+        let gpu_summary = |s: &Series| if has_gpu && !s.samples.is_empty() { Some(s.summary()) } else { None };
+        return FrameStats {
+            frames: self.frames,
+            cpu: self.cpu.summary(),
+            interval: self.interval.summary(),
+            gpu: gpu_summary(&self.gpu),
+            passes: self.passes.iter().map(|p| PassStats {
+                name: p.name,
+                cpu: p.cpu.summary(),
+                gpu: gpu_summary(&p.gpu),
+            }).collect(),
+        };
+    }
+}
+
+// Sets up GPU timing for a freshly created context. Any
+// queries from a previous context died with it.
+pub unsafe fn install<F: Fn(&str) -> *const c_void>(context: &Context, loader: F) {
+    PROFILER.with(|p| {
+        let p = &mut *p.borrow_mut();
+        p.timers = if context.has_extension("GL_EXT_disjoint_timer_query") { TimerQueries::load(loader) } else { None };
+        p.free_queries.clear();
+        p.pending.clear();
+        p.current = None;
+        p.active_pass = None;
+        if p.timers.is_none() {
+            log::info!("EXT_disjoint_timer_query unavailable, profiling CPU time only");
+        }
+    });
+}
+
+// Most recent frame time in milliseconds for feedback like
+// dynamic resolution: GPU time when measured, since that's
+// what scaling resolution changes, otherwise CPU time.
+pub fn frame_time() -> Option<f32> {
+    return PROFILER.with(|p| {
+        let p = p.borrow();
+        return p.gpu.last().or(p.cpu.last());
+    });
+}
+
+pub fn has_gpu_timing() -> bool {
+    return PROFILER.with(|p| p.borrow().timers.is_some());
+}
+
+// Logs a summary at most this often, or never with None.
+pub fn set_logging(every: Option<Duration>) {
+    PROFILER.with(|p| p.borrow_mut().log_every = every);
+}
+
+pub unsafe fn begin_frame() {
+    PROFILER.with(|p| {
+        let p = &mut *p.borrow_mut();
+        let now = Instant::now();
+        if let Some(last) = p.last_begin {
+            p.interval.push(millis(now - last));
+        }
+        p.last_begin = Some(now);
+        p.frame_start = Some(now);
+        p.collect();
+        p.current = None;
+        if p.timers.is_some() && p.pending.len() < MAX_PENDING_FRAMES {
+            p.current = Some(PendingFrame { queries: Vec::new() });
+            p.next_query(None);
+        }
+    });
+}
+
+pub unsafe fn end_frame() {
+    end_pass();
+    let publish = PROFILER.with(|p| {
+        let p = &mut *p.borrow_mut();
+        if let Some(start) = p.frame_start.take() {
+            p.cpu.push(millis(start.elapsed()));
+        }
+        if let Some(current) = p.current.take() {
+            if !current.queries.is_empty() {
+                (p.timers.as_ref().unwrap().end)(TIME_ELAPSED_EXT);
+                p.pending.push(current);
+            }
+        }
+        p.frames += 1;
+        let log_due = p.log_every.map_or(false, |every| p.last_log.elapsed() >= every);
+        if log_due {
+            p.last_log = Instant::now();
+        }
+        if log_due || p.frames % PUBLISH_INTERVAL == 0 {
+            return Some((p.stats(), log_due));
+        }
+        return None;
+    });
+    if let Some((stats, log_due)) = publish {
+        if log_due {
+            log_stats(&stats);
+        }
+        *SNAPSHOT.lock().unwrap() = Some(stats);
+    }
+}
+
+// Passes don't nest: starting one ends the one before it.
+pub unsafe fn begin_pass(name: &'static str) {
+    end_pass();
+    PROFILER.with(|p| {
+        let p = &mut *p.borrow_mut();
+        let index = p.pass_index(name);
+        p.active_pass = Some((index, Instant::now()));
+        p.next_query(Some(index));
+    });
+}
+
+pub unsafe fn end_pass() {
+    PROFILER.with(|p| {
+        let p = &mut *p.borrow_mut();
+        if let Some((index, start)) = p.active_pass.take() {
+            p.passes[index].cpu.push(millis(start.elapsed()));
+            p.next_query(None);
+        }
+    });
+}
+
+pub struct PassGuard;
+
+impl Drop for PassGuard {
+    fn drop(&mut self) {
+        unsafe { end_pass(); }
+    }
+}
+
+// Times a pass until the guard is dropped.
+pub unsafe fn pass(name: &'static str) -> PassGuard {
+    begin_pass(name);
+    return PassGuard;
+}
+
+// Latest published summary, from any thread.
+pub fn snapshot() -> Option<FrameStats> {
+    return SNAPSHOT.lock().unwrap().clone();
+}
+
+pub fn log_stats(stats: &FrameStats) {
+    let line = |s: &Summary| format!("avg {:.2} p50 {:.2} p95 {:.2} p99 {:.2} max {:.2}", s.average, s.p50, s.p95, s.p99, s.max);
+    log::info!("frame {}: cpu {} | interval {}", stats.frames, line(&stats.cpu), line(&stats.interval));
+    if let Some(gpu) = &stats.gpu {
+        log::info!("  gpu {}", line(gpu));
+    }
+    for pass in &stats.passes {
+        match &pass.gpu {
+            Some(gpu) => log::info!("  {}: cpu {} | gpu {}", pass.name, line(&pass.cpu), line(gpu)),
+            None => log::info!("  {}: cpu {}", pass.name, line(&pass.cpu)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_with_nearest_rank_percentiles() {
+        assert_eq!(Series::new().summary().samples, 0);
+        let mut series = Series::new();
+        // Out of order, to show they're sorted first.
+        for ms in (1..=100).rev() {
+            series.push(ms as f32);
+        }
+        let s = series.summary();
+        assert_eq!((s.p50, s.p95, s.p99, s.max), (50.0, 95.0, 99.0, 100.0));
+        assert_eq!((s.average, s.samples), (50.5, 100));
+        assert_eq!(series.last(), Some(1.0));
+    }
+
+    #[test]
+    fn keeps_only_the_latest_history() {
+        let mut series = Series::new();
+        for ms in 1..=HISTORY + 80 {
+            series.push(ms as f32);
+        }
+        let s = series.summary();
+        assert_eq!(s.samples, HISTORY);
+        assert_eq!(series.last(), Some((HISTORY + 80) as f32));
+        // 81 to 200 are left, and rank 60 of those is 140.
+        assert_eq!((s.p50, s.max), (140.0, 200.0));
+    }
+}
@@ -0,0 +1,277 @@
+use ::gl::types::*;
+use crate::graphics::Result;
+use crate::graphics::shader::Program;
+use crate::graphics::target::RenderTarget;
+use crate::graphics::texture::Filter;
+use crate::graphics::pipeline::{Pipeline, PipelineDesc};
+use crate::graphics::state;
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::{self, gl_call};
+use crate::bridge::activity::Activity;
+use std::ffi::c_void;
+
+const ATTRIB_POSITION: GLuint = 0;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ResolutionSettings {
+    // Frame time to aim for in milliseconds.
+    pub target_ms: f32,
+    // Limits on the scale applied to each window axis.
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Only scale back up once frames are under this
+    // fraction of the budget, so we don't oscillate.
+    pub headroom: f32,
+    // Largest change to the scale in one adjustment.
+    pub max_step: f32,
+    // Frames to wait after a change for timings to catch up.
+    // GPU timer results arrive a few frames late.
+    pub cooldown_frames: u32,
+    // Unsharp mask strength for the upscale, 0 to disable.
+    pub sharpness: f32,
+}
+
+impl Default for ResolutionSettings {
+    fn default() -> Self {
+        return ResolutionSettings {
+            target_ms: 1000.0 / 60.0,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            headroom: 0.85,
+            max_step: 0.1,
+            cooldown_frames: 8,
+            sharpness: 0.2,
+        };
+    }
+}
+
+// Picks the scale from reported frame times.
+struct ScaleController {
+    scale: f32,
+    // Exponential moving average of reported frame times.
+    smoothed_ms: Option<f32>,
+    cooldown: u32,
+}
+
+impl ScaleController {
+    fn new(settings: &ResolutionSettings) -> ScaleController {
+        return ScaleController { scale: settings.max_scale, smoothed_ms: None, cooldown: 0 };
+    }
+
+    fn update(&mut self, s: &ResolutionSettings, frame_ms: f32) -> bool {
+        let smoothed = match self.smoothed_ms {
+            Some(prev) => prev + (frame_ms - prev) * 0.1,
+            None => frame_ms,
+        };
+        self.smoothed_ms = Some(smoothed);
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return false;
+        }
+        // Cost goes with pixel count, which is scale squared.
+        let ideal = self.scale * (s.target_ms / smoothed.max(0.001)).sqrt();
+        let wanted = if smoothed > s.target_ms {
+            ideal.max(self.scale - s.max_step)
+        } else if smoothed < s.target_ms * s.headroom {
+            // Step up gently, aiming just under budget.
+            (self.scale * (s.target_ms * s.headroom / smoothed.max(0.001)).sqrt()).min(self.scale + s.max_step * 0.5)
+        } else {
+            self.scale
+        };
+        let wanted = wanted.clamp(s.min_scale, s.max_scale);
+        // Ignore changes too small to be worth a visible jump.
+        if (wanted - self.scale).abs() < 0.01 {
+            return false;
+        }
+        self.scale = wanted;
+        self.cooldown = s.cooldown_frames;
+        // Measurements from before the change are stale.
+        self.smoothed_ms = None;
+        return true;
+    }
+}
+
+// Renders the scene below window resolution when frames run
+// over budget. The target is allocated once at max_scale and
+// the scene is drawn into a corner of it, so changing the
+// scale never reallocates. Draw between begin() and finish();
+// finish() upscales to whatever was bound at begin().
+pub struct DynamicResolution {
+    pub settings: ResolutionSettings,
+    controller: ScaleController,
+    program: Program,
+    pipeline: Pipeline,
+    target: RenderTarget,
+    window: (i32, i32),
+    triangle: GLuint,
+    output_fbo: GLint,
+    output_viewport: [GLint; 4],
+    stamp: ContextStamp,
+}
+
+impl DynamicResolution {
+    pub unsafe fn new(width: i32, height: i32, settings: ResolutionSettings, activity: &Activity) -> Result<DynamicResolution> {
+        let program = Program::load("upscale", &[(ATTRIB_POSITION, "position")], activity)?;
+        let mut triangle: GLuint = 0;
+        let vertices: [f32; 6] = [-1.0, -1.0, 3.0, -1.0, -1.0, 3.0];
+        gl_call!(GenBuffers(1, &mut triangle));
+        state::bind_buffer(::gl::ARRAY_BUFFER, triangle);
+        gl_call!(BufferData(::gl::ARRAY_BUFFER, 24, vertices.as_ptr() as *const c_void, ::gl::STATIC_DRAW));
+        debug::label(debug::LABEL_BUFFER, triangle, "upscale triangle");
+        let target = Self::create_target(width, height, settings.max_scale)?;
+        return Ok(DynamicResolution {
+            controller: ScaleController::new(&settings),
+            settings: settings,
+            program: program,
+            pipeline: Pipeline::new(PipelineDesc::FULLSCREEN)?,
+            target: target,
+            window: (width, height),
+            triangle: triangle,
+            output_fbo: 0,
+            output_viewport: [0; 4],
+            stamp: ContextStamp::now(),
+        });
+    }
+
+    unsafe fn create_target(width: i32, height: i32, max_scale: f32) -> Result<RenderTarget> {
+        let w = ((width as f32 * max_scale).ceil() as i32).max(1);
+        let h = ((height as f32 * max_scale).ceil() as i32).max(1);
+        let target = RenderTarget::new(w, h, true, Filter::Linear)?;
+        target.set_label("dynamic resolution");
+        return Ok(target);
+    }
+
+    pub unsafe fn resize(&mut self, width: i32, height: i32) -> Result<()> {
+        if (width, height) == self.window {
+            return Ok(());
+        }
+        self.target = Self::create_target(width, height, self.settings.max_scale)?;
+        self.window = (width, height);
+        return Ok(());
+    }
+
+    pub fn scale(&self) -> f32 {
+        return self.controller.scale;
+    }
+
+    // Size the scene is rendered at this frame.
+    pub fn render_size(&self) -> (i32, i32) {
+        let w = ((self.window.0 as f32 * self.controller.scale).round() as i32).clamp(1, self.target.width);
+        let h = ((self.window.1 as f32 * self.controller.scale).round() as i32).clamp(1, self.target.height);
+        return (w, h);
+    }
+
+    // Feeds one frame's time in milliseconds, normally
+    // profiler::frame_time(). Returns whether the scale changed.
+    pub fn update(&mut self, frame_ms: f32) -> bool {
+        return self.controller.update(&self.settings, frame_ms);
+    }
+
+    // Redirects drawing into the scaled target and sets
+    // the viewport to the area in use this frame.
+    pub unsafe fn begin(&mut self) -> (i32, i32) {
+        gl_call!(GetIntegerv(::gl::FRAMEBUFFER_BINDING, &mut self.output_fbo));
+        gl_call!(GetIntegerv(::gl::VIEWPORT, self.output_viewport.as_mut_ptr()));
+        let (w, h) = self.render_size();
+        state::bind_framebuffer(self.target.fbo());
+        state::viewport(0, 0, w, h);
+        return (w, h);
+    }
+
+    pub unsafe fn finish(&mut self) {
+        let (w, h) = self.render_size();
+        let v = self.output_viewport;
+        state::bind_framebuffer(self.output_fbo as GLuint);
+        state::viewport(v[0], v[1], v[2], v[3]);
+        self.pipeline.apply();
+        self.program.bind();
+        self.target.color.bind(0);
+        let (tw, th) = (self.target.width as f32, self.target.height as f32);
+        gl_call!(Uniform1i(self.program.uniform_location("u_source"), 0));
+        gl_call!(Uniform2f(self.program.uniform_location("u_texel"), 1.0 / tw, 1.0 / th));
+        gl_call!(Uniform2f(self.program.uniform_location("u_uv_scale"), w as f32 / tw, h as f32 / th));
+        gl_call!(Uniform1f(self.program.uniform_location("u_sharpness"), self.settings.sharpness));
+        state::bind_buffer(::gl::ARRAY_BUFFER, self.triangle);
+        gl_call!(EnableVertexAttribArray(ATTRIB_POSITION));
+        gl_call!(VertexAttribPointer(ATTRIB_POSITION, 2, ::gl::FLOAT, ::gl::FALSE, 8, std::ptr::null()));
+        gl_call!(DrawArrays(::gl::TRIANGLES, 0, 3));
+        gl_call!(DisableVertexAttribArray(ATTRIB_POSITION));
+    }
+}
+
+impl Drop for DynamicResolution {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        state::deleted_buffers(&[self.triangle]);
+        unsafe { gl_call!(DeleteBuffers(1, &self.triangle)); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 10ms budget, so 11ms frames ask for 10/11 of the pixels.
+    fn settings() -> ResolutionSettings {
+        return ResolutionSettings {
+            target_ms: 10.0,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            headroom: 0.85,
+            max_step: 0.1,
+            cooldown_frames: 2,
+            sharpness: 0.0,
+        };
+    }
+
+    #[test]
+    fn steps_down_over_budget_and_waits() {
+        let s = settings();
+        let mut c = ScaleController::new(&s);
+        assert!(c.update(&s, 11.0));
+        let first = (10.0f32 / 11.0).sqrt();
+        assert!((c.scale - first).abs() < 1e-5);
+        // Cooling down, however slow it gets.
+        assert!(!c.update(&s, 100.0));
+        assert!(!c.update(&s, 100.0));
+        // Then at most max_step at a time, down to min_scale.
+        assert!(c.update(&s, 100.0));
+        assert!((c.scale - (first - 0.1)).abs() < 1e-5);
+        for _ in 0..20 {
+            c.update(&s, 100.0);
+        }
+        assert_eq!(c.scale, 0.5);
+    }
+
+    #[test]
+    fn steps_up_only_with_headroom() {
+        let s = ResolutionSettings { cooldown_frames: 0, ..settings() };
+        let mut c = ScaleController { scale: 0.6, smoothed_ms: None, cooldown: 0 };
+        // Inside the band between headroom and budget.
+        assert!(!c.update(&s, 9.0));
+        assert_eq!(c.scale, 0.6);
+        // Half a step at most on the way up, up to max_scale.
+        c.smoothed_ms = None;
+        assert!(c.update(&s, 2.0));
+        assert!((c.scale - 0.65).abs() < 1e-5);
+        for _ in 0..20 {
+            c.update(&s, 2.0);
+        }
+        assert_eq!(c.scale, 1.0);
+        // Already at the limit, so nothing changes.
+        assert!(!c.update(&s, 2.0));
+    }
+
+    #[test]
+    fn ignores_single_spikes() {
+        let s = ResolutionSettings { cooldown_frames: 0, ..settings() };
+        let mut c = ScaleController::new(&s);
+        assert!(!c.update(&s, 9.0));
+        // One 20ms frame only moves the average to 10.1ms,
+        // too little change to be worth a jump.
+        assert!(!c.update(&s, 20.0));
+        assert_eq!(c.scale, 1.0);
+    }
+}
@@ -0,0 +1,223 @@
+use ::gl::types::*;
+use crate::graphics::Result;
+use crate::graphics::handle::{BufferHandle, TextureHandle, ProgramHandle, self};
+use crate::graphics::texture::{Texture, PixelFormat, Filter};
+use crate::graphics::shader::{self, Program};
+use crate::graphics::capture::Image;
+use crate::graphics::state;
+use crate::graphics::debug::{self, gl_call};
+use crate::bridge::activity::{Activity, Asset};
+use std::ffi::c_void;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+// Registry of how GPU resources were made, so they can be
+// made again. Android throws the EGL context away when the
+// app is backgrounded, and every GL object goes with it;
+// resources created through here keep their handles across
+// that, and context_created() rebuilds them from their
+// asset or the CPU copy kept alongside the handle.
+//
+// Framebuffers aren't covered: their contents are redrawn
+// every frame anyway, so owners just recreate them.
+//
+// Types that own raw GL names instead of handles (render
+// targets, the renderers, library caches) keep the
+// ContextStamp they were made under. Once it's stale their
+// names died with the old context and may already have been
+// handed out again by the new one, so they must neither be
+// used nor deleted. Whoever builds them takes a stamp of
+// its own at the same time and rebuilds once it's stale.
+
+pub enum TextureSource {
+    // Retained pixels, uploaded as-is.
+    Pixels {
+        width: i32,
+        height: i32,
+        format: PixelFormat,
+        filter: Filter,
+        pixels: Arc<Vec<u8>>,
+    },
+    // A PNG asset, decoded to RGBA.
+    Png {
+        path: String,
+        filter: Filter,
+    },
+}
+
+pub struct BufferSource {
+    pub target: GLenum,
+    pub usage: GLenum,
+    pub data: Arc<Vec<u8>>,
+}
+
+pub struct ProgramSource {
+    pub name: String,
+    pub features: Vec<String>,
+    pub attributes: Vec<(GLuint, String)>,
+}
+
+enum Recipe {
+    Texture(TextureSource, TextureHandle),
+    Buffer(BufferSource, BufferHandle),
+    Program(ProgramSource, ProgramHandle),
+}
+
+impl Recipe {
+    fn is_alive(&self) -> bool {
+        return match self {
+            Recipe::Texture(_, h) => h.is_alive(),
+            Recipe::Buffer(_, h) => h.is_alive(),
+            Recipe::Program(_, h) => h.is_alive(),
+        };
+    }
+}
+
+static RECIPES: Mutex<Vec<Recipe>> = Mutex::new(Vec::new());
+// Whether a context has come up before, i.e. whether a
+// new one means the old objects are gone.
+static HAD_CONTEXT: AtomicBool = AtomicBool::new(false);
+// Bumped for every context, including the first.
+static CONTEXT_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+// Which context raw GL names belong to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ContextStamp(u32);
+
+impl ContextStamp {
+    // The context current now.
+    pub fn now() -> ContextStamp {
+        return ContextStamp(CONTEXT_GENERATION.load(Ordering::Acquire));
+    }
+
+    // False once another context has been made since.
+    pub fn is_current(self) -> bool {
+        return self == Self::now();
+    }
+}
+
+unsafe fn create_texture(source: &TextureSource, activity: &Activity) -> Result<GLuint> {
+    let id = match source {
+        TextureSource::Pixels { width, height, format, filter, pixels } => {
+            Texture::new(*width, *height, *format, *filter, Some(pixels))?.into_raw()
+        },
+        TextureSource::Png { path, filter } => {
+            let mut data = Vec::new();
+            Asset::open(path, activity)?.read_to_end(&mut data)?;
+            let image = Image::from_png(&data)?;
+            let id = Texture::new(image.width as i32, image.height as i32, PixelFormat::RGBA8,
+                *filter, Some(&image.pixels))?.into_raw();
+            debug::label(debug::LABEL_TEXTURE, id, path);
+            id
+        },
+    };
+    return Ok(id);
+}
+
+unsafe fn create_buffer(source: &BufferSource) -> GLuint {
+    let mut id: GLuint = 0;
+    gl_call!(GenBuffers(1, &mut id));
+    state::bind_buffer(source.target, id);
+    gl_call!(BufferData(source.target, source.data.len() as GLsizeiptr,
+        source.data.as_ptr() as *const c_void, source.usage));
+    return id;
+}
+
+unsafe fn create_program(source: &ProgramSource, activity: &Activity) -> Result<GLuint> {
+    let vert_src = shader::read_source(&shader::shader_path(&source.name, "vert"), activity)?;
+    let frag_src = shader::read_source(&shader::shader_path(&source.name, "frag"), activity)?;
+    let attributes: Vec<(GLuint, &str)> = source.attributes.iter().map(|(i, n)| (*i, n.as_str())).collect();
+    let program = Program::new(&shader::with_features(&vert_src, &source.features),
+        &shader::with_features(&frag_src, &source.features), &attributes)?;
+    let id = program.into_raw();
+    debug::label(debug::LABEL_PROGRAM, id, &source.name);
+    return Ok(id);
+}
+
+pub unsafe fn texture(source: TextureSource, activity: &Activity) -> Result<TextureHandle> {
+    let handle = TextureHandle::new(create_texture(&source, activity)?);
+    RECIPES.lock().unwrap().push(Recipe::Texture(source, handle));
+    return Ok(handle);
+}
+
+pub unsafe fn buffer(source: BufferSource) -> BufferHandle {
+    let handle = BufferHandle::new(create_buffer(&source));
+    RECIPES.lock().unwrap().push(Recipe::Buffer(source, handle));
+    return handle;
+}
+
+pub unsafe fn program(source: ProgramSource, activity: &Activity) -> Result<ProgramHandle> {
+    let handle = ProgramHandle::new(create_program(&source, activity)?);
+    RECIPES.lock().unwrap().push(Recipe::Program(source, handle));
+    return Ok(handle);
+}
+
+// Replaces a registered buffer's contents, keeping the new
+// data as what gets restored.
+pub unsafe fn update_buffer(handle: BufferHandle, data: Arc<Vec<u8>>) {
+    let mut recipes = RECIPES.lock().unwrap();
+    for recipe in recipes.iter_mut() {
+        if let Recipe::Buffer(source, h) = recipe {
+            if *h == handle {
+                source.data = data;
+                if let Some(id) = handle.get() {
+                    state::bind_buffer(source.target, id);
+                    gl_call!(BufferData(source.target, source.data.len() as GLsizeiptr,
+                        source.data.as_ptr() as *const c_void, source.usage));
+                }
+                return;
+            }
+        }
+    }
+    log::warn!("update_buffer: {:?} isn't registered", handle);
+}
+
+// Call as soon as a new context is current, before any GL
+// object is made in it, whether or not there's an activity
+// to restore from yet. Stales every earlier ContextStamp.
+pub fn new_context() {
+    CONTEXT_GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+// Drops recipes whose handles were released.
+pub fn prune() {
+    RECIPES.lock().unwrap().retain(|r| r.is_alive());
+}
+
+// Call on the render thread right after a context is made
+// current. The first context has nothing to restore; after
+// that, every handle is marked lost and each registered
+// resource is rebuilt under its old handle. Without an
+// activity to load from they all stay lost. Failures are
+// logged and leave that handle lost. Returns how many
+// resources were restored.
+pub unsafe fn context_created(activity: Option<&Activity>) -> usize {
+    if !HAD_CONTEXT.swap(true, Ordering::AcqRel) {
+        return 0;
+    }
+    handle::lose_all();
+    prune();
+    let recipes = RECIPES.lock().unwrap();
+    let activity = match activity {
+        Some(a) => a,
+        None => {
+            log::warn!("No activity to restore {} GPU resources from", recipes.len());
+            return 0;
+        },
+    };
+    let mut restored = 0;
+    for recipe in recipes.iter() {
+        let result = match recipe {
+            Recipe::Texture(source, h) => create_texture(source, activity).map(|id| h.restore(id)),
+            Recipe::Buffer(source, h) => Ok(h.restore(create_buffer(source))),
+            Recipe::Program(source, h) => create_program(source, activity).map(|id| h.restore(id)),
+        };
+        match result {
+            Ok(_) => restored += 1,
+            Err(e) => log::error!("Failed to restore GPU resource: {:?}", e),
+        }
+    }
+    log::info!("Restored {} GPU resources", restored);
+    return restored;
+}
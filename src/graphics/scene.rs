@@ -0,0 +1,307 @@
+use crate::graphics::model::Model;
+use crate::math::{Matrix4, Vector3, M4_IDENTITY};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(usize);
+
+// Index into whatever mesh list the renderer keeps.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MeshId(pub usize);
+
+#[derive(Copy, Clone)]
+pub struct Camera {
+    // Vertical field of view in radians.
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Camera {
+    pub fn projection(&self, aspect: f32) -> Matrix4 {
+        return Matrix4::perspective(self.fov_y, aspect, self.near, self.far);
+    }
+}
+
+// Lights take their position and direction from the node
+// they're attached to. Directional lights shine down the
+// node's -z axis like cameras look down it.
+#[derive(Copy, Clone)]
+pub enum Light {
+    Directional { color: Vector3, intensity: f32 },
+    Point { color: Vector3, intensity: f32, range: f32 },
+}
+
+#[derive(Copy, Clone)]
+pub enum Attachment {
+    Mesh(MeshId),
+    Camera(Camera),
+    Light(Light),
+}
+
+pub struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: Matrix4,
+    world: Matrix4,
+    // If a node is dirty, so are all of its descendants.
+    dirty: bool,
+    pub attachments: Vec<Attachment>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        return self.parent;
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        return &self.children;
+    }
+
+    pub fn local(&self) -> &Matrix4 {
+        return &self.local;
+    }
+
+    // Only up to date after Scene::update_transforms.
+    pub fn world(&self) -> &Matrix4 {
+        return &self.world;
+    }
+}
+
+pub struct Scene {
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        return Scene { nodes: Vec::new(), free: Vec::new(), roots: Vec::new() };
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        return self.nodes.get(id.0).and_then(|n| n.as_ref());
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        return self.nodes.get_mut(id.0).and_then(|n| n.as_mut());
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        return &self.roots;
+    }
+
+    pub fn add(&mut self, parent: Option<NodeId>, local: Matrix4) -> NodeId {
+        let node = Node {
+            parent: parent,
+            children: Vec::new(),
+            local: local,
+            world: local,
+            dirty: true,
+            attachments: Vec::new(),
+        };
+        let id = match self.free.pop() {
+            Some(i) => { self.nodes[i] = Some(node); NodeId(i) },
+            None => { self.nodes.push(Some(node)); NodeId(self.nodes.len() - 1) },
+        };
+        match parent.and_then(|p| self.node_mut(p)) {
+            Some(p) => p.children.push(id),
+            None => {
+                self.node_mut(id).unwrap().parent = None;
+                self.roots.push(id);
+            },
+        };
+        return id;
+    }
+
+    // Removes the node along with everything under it.
+    pub fn remove(&mut self, id: NodeId) {
+        let node = match self.nodes.get_mut(id.0).and_then(|n| n.take()) {
+            Some(n) => n,
+            None => return,
+        };
+        self.free.push(id.0);
+        let siblings = match node.parent.and_then(|p| self.node_mut(p)) {
+            Some(p) => &mut p.children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|c| *c != id);
+        for child in node.children {
+            self.remove(child);
+        }
+    }
+
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        // Refuse to create cycles.
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == id {
+                return;
+            }
+            ancestor = self.node(a).and_then(|n| n.parent);
+        }
+        let old = match self.node_mut(id) {
+            Some(n) => std::mem::replace(&mut n.parent, parent),
+            None => return,
+        };
+        match old.and_then(|p| self.node_mut(p)) {
+            Some(p) => p.children.retain(|c| *c != id),
+            None => self.roots.retain(|c| *c != id),
+        };
+        match parent.and_then(|p| self.node_mut(p)) {
+            Some(p) => p.children.push(id),
+            None => {
+                self.node_mut(id).unwrap().parent = None;
+                self.roots.push(id);
+            },
+        };
+        self.mark_dirty(id);
+    }
+
+    pub fn set_local(&mut self, id: NodeId, local: Matrix4) {
+        if let Some(n) = self.node_mut(id) {
+            n.local = local;
+            self.mark_dirty(id);
+        }
+    }
+
+    fn mark_dirty(&mut self, id: NodeId) {
+        let mut stack = vec![id];
+        while let Some(i) = stack.pop() {
+            if let Some(n) = self.node_mut(i) {
+                // Already dirty means the subtree is too.
+                if i != id && n.dirty {
+                    continue;
+                }
+                n.dirty = true;
+                stack.extend_from_slice(&n.children);
+            }
+        }
+    }
+
+    // Recomputes world transforms, touching only the
+    // nodes that were moved or are under a moved node.
+    pub fn update_transforms(&mut self) {
+        let mut stack: Vec<(NodeId, Matrix4)> = self.roots.iter().map(|r| (*r, M4_IDENTITY)).collect();
+        while let Some((id, parent_world)) = stack.pop() {
+            let node = match self.node_mut(id) {
+                Some(n) => n,
+                None => continue,
+            };
+            if node.dirty {
+                node.world = parent_world * node.local;
+                node.dirty = false;
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().map(|c| (*c, world)));
+        }
+    }
+
+    // Depth-first walk over every node, parents first.
+    pub fn visit<F: FnMut(NodeId, &Node)>(&self, mut f: F) {
+        let mut stack: Vec<NodeId> = self.roots.iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            if let Some(n) = self.node(id) {
+                f(id, n);
+                stack.extend(n.children.iter().rev());
+            }
+        }
+    }
+
+    // Every attachment with the world transform of its node.
+    pub fn attachments(&self) -> Vec<(Matrix4, Attachment)> {
+        let mut out = Vec::new();
+        self.visit(|_, n| {
+            for a in &n.attachments {
+                out.push((n.world, *a));
+            }
+        });
+        return out;
+    }
+
+    // Adds the node tree of a model under parent. Model
+    // mesh i is attached as meshes[i], so upload the
+    // meshes first. Returns the ids of the model's nodes
+    // in the same order as Model::nodes.
+    pub fn add_model(&mut self, model: &Model, parent: Option<NodeId>, meshes: &[MeshId]) -> Vec<NodeId> {
+        let mut ids: Vec<NodeId> = Vec::with_capacity(model.nodes.len());
+        for node in &model.nodes {
+            let p = match node.parent {
+                Some(i) => Some(ids[i]),
+                None => parent,
+            };
+            let id = self.add(p, node.transform);
+            if let Some(mesh) = node.mesh.and_then(|m| meshes.get(m)) {
+                self.node_mut(id).unwrap().attachments.push(Attachment::Mesh(*mesh));
+            }
+            ids.push(id);
+        }
+        return ids;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn x_of(scene: &Scene, id: NodeId) -> f32 {
+        return scene.node(id).unwrap().world().v4.x;
+    }
+
+    fn shift(x: f32) -> Matrix4 {
+        return Matrix4::translation(Vector3::new(x, 0.0, 0.0));
+    }
+
+    #[test]
+    fn moving_a_parent_dirties_its_subtree() {
+        let mut scene = Scene::new();
+        let root = scene.add(None, shift(1.0));
+        let child = scene.add(Some(root), shift(2.0));
+        let grandchild = scene.add(Some(child), shift(4.0));
+        let other = scene.add(None, shift(8.0));
+        scene.update_transforms();
+        assert_eq!(x_of(&scene, grandchild), 7.0);
+        scene.set_local(child, shift(3.0));
+        let dirty: Vec<bool> = [root, child, grandchild, other].iter().map(|n| scene.node(*n).unwrap().dirty).collect();
+        assert_eq!(dirty, vec![false, true, true, false]);
+        scene.update_transforms();
+        assert_eq!((x_of(&scene, child), x_of(&scene, grandchild), x_of(&scene, other)), (4.0, 8.0, 8.0));
+        assert!(!scene.node(grandchild).unwrap().dirty);
+    }
+
+    #[test]
+    fn set_parent_refuses_cycles() {
+        let mut scene = Scene::new();
+        let root = scene.add(None, shift(1.0));
+        let child = scene.add(Some(root), shift(2.0));
+        let grandchild = scene.add(Some(child), shift(4.0));
+        scene.set_parent(root, Some(grandchild));
+        scene.set_parent(child, Some(child));
+        assert_eq!(scene.node(root).unwrap().parent(), None);
+        assert_eq!(scene.node(child).unwrap().parent(), Some(root));
+        assert_eq!(scene.roots(), &[root]);
+        // Reparented nodes keep their local transform.
+        scene.set_parent(grandchild, None);
+        scene.update_transforms();
+        assert_eq!(scene.roots(), &[root, grandchild]);
+        assert!(scene.node(child).unwrap().children().is_empty());
+        assert_eq!(x_of(&scene, grandchild), 4.0);
+    }
+
+    #[test]
+    fn remove_takes_the_subtree() {
+        let mut scene = Scene::new();
+        let root = scene.add(None, M4_IDENTITY);
+        let child = scene.add(Some(root), M4_IDENTITY);
+        let grandchild = scene.add(Some(child), M4_IDENTITY);
+        let sibling = scene.add(Some(root), M4_IDENTITY);
+        scene.remove(child);
+        assert!(scene.node(child).is_none() && scene.node(grandchild).is_none());
+        assert_eq!(scene.node(root).unwrap().children(), &[sibling]);
+        let mut visited = Vec::new();
+        scene.visit(|id, _| visited.push(id));
+        assert_eq!(visited, vec![root, sibling]);
+        // Freed slots get reused.
+        let reused = scene.add(None, M4_IDENTITY);
+        assert!(reused == child || reused == grandchild);
+    }
+}
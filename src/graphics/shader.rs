@@ -0,0 +1,215 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::state;
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::{self, gl_call};
+use crate::bridge::activity::{Activity, Asset};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io::Read;
+use std::ptr::null;
+use std::rc::Rc;
+
+// Shaders live in the assets folder as
+// android/shaders/<name>/<name>.{vert,frag}
+pub(crate) fn shader_path(name: &str, ext: &str) -> String {
+    return format!("android/shaders/{}/{}.{}", name, name, ext);
+}
+
+pub fn read_source(path: &str, activity: &Activity) -> Result<String> {
+    let mut asset = Asset::open(path, activity)?;
+    let mut src = String::new();
+    asset.read_to_string(&mut src)?;
+    return Ok(src);
+}
+
+unsafe fn info_log(id: GLuint, is_program: bool) -> String {
+    let mut len: GLint = 0;
+    if is_program {
+        gl_call!(GetProgramiv(id, ::gl::INFO_LOG_LENGTH, &mut len));
+    } else {
+        gl_call!(GetShaderiv(id, ::gl::INFO_LOG_LENGTH, &mut len));
+    }
+    let mut buf = vec![0u8; std::cmp::max(len, 1) as usize];
+    let mut written: GLsizei = 0;
+    if is_program {
+        gl_call!(GetProgramInfoLog(id, buf.len() as GLsizei, &mut written, buf.as_mut_ptr() as *mut GLchar));
+    } else {
+        gl_call!(GetShaderInfoLog(id, buf.len() as GLsizei, &mut written, buf.as_mut_ptr() as *mut GLchar));
+    }
+    buf.truncate(std::cmp::max(written, 0) as usize);
+    return String::from_utf8_lossy(&buf).into_owned();
+}
+
+// Wrapper around a shader object for impl Drop.
+// Only needed until the program is linked.
+struct Shader(GLuint);
+
+impl Shader {
+    unsafe fn compile(kind: GLenum, src: &str) -> Result<Shader> {
+        let csrc = match CString::new(src) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(Error::InvalidFormat("shader source contains NUL")),
+        }?;
+        let shader = Shader(gl_call!(CreateShader(kind)));
+        gl_call!(ShaderSource(shader.0, 1, &csrc.as_ptr(), null()));
+        gl_call!(CompileShader(shader.0));
+        let mut status: GLint = 0;
+        gl_call!(GetShaderiv(shader.0, ::gl::COMPILE_STATUS, &mut status));
+        if status == 0 {
+            return Err(Error::ShaderCompileError(info_log(shader.0, false)));
+        }
+        return Ok(shader);
+    }
+}
+
+impl Drop for Shader {
+    // Shaders only live while their program links, so
+    // they can't outlast the context.
+    fn drop(&mut self) {
+        unsafe { gl_call!(DeleteShader(self.0)); }
+    }
+}
+
+pub struct Program {
+    id: GLuint,
+    locations: RefCell<HashMap<String, GLint>>,
+    stamp: ContextStamp,
+}
+
+impl Program {
+    // Attributes are bound to fixed locations before
+    // linking so vertex layouts don't need to query them.
+    pub unsafe fn new(vert_src: &str, frag_src: &str, attributes: &[(GLuint, &str)]) -> Result<Program> {
+        let vert = Shader::compile(::gl::VERTEX_SHADER, vert_src)?;
+        let frag = Shader::compile(::gl::FRAGMENT_SHADER, frag_src)?;
+        let program = Program { id: gl_call!(CreateProgram()), locations: RefCell::new(HashMap::new()), stamp: ContextStamp::now() };
+        gl_call!(AttachShader(program.id, vert.0));
+        gl_call!(AttachShader(program.id, frag.0));
+        for (index, name) in attributes {
+            let cname = CString::new(*name).unwrap();
+            gl_call!(BindAttribLocation(program.id, *index, cname.as_ptr()));
+        }
+        gl_call!(LinkProgram(program.id));
+        gl_call!(DetachShader(program.id, vert.0));
+        gl_call!(DetachShader(program.id, frag.0));
+        let mut status: GLint = 0;
+        gl_call!(GetProgramiv(program.id, ::gl::LINK_STATUS, &mut status));
+        if status == 0 {
+            return Err(Error::ProgramLinkError(info_log(program.id, true)));
+        }
+        return Ok(program);
+    }
+
+    pub unsafe fn load(name: &str, attributes: &[(GLuint, &str)], activity: &Activity) -> Result<Program> {
+        let vert_src = read_source(&shader_path(name, "vert"), activity)?;
+        let frag_src = read_source(&shader_path(name, "frag"), activity)?;
+        let program = Self::new(&vert_src, &frag_src, attributes)?;
+        debug::label(debug::LABEL_PROGRAM, program.id, name);
+        return Ok(program);
+    }
+
+    pub fn id(&self) -> GLuint {
+        return self.id;
+    }
+
+    // False after the context it was made in went away.
+    pub fn is_current(&self) -> bool {
+        return self.stamp.is_current();
+    }
+
+    // Hands the GL program over to the caller, who
+    // becomes responsible for deleting it.
+    pub fn into_raw(self) -> GLuint {
+        let id = self.id;
+        std::mem::forget(self);
+        return id;
+    }
+
+    // Locations are cached, so this is cheap to call per draw.
+    // Missing uniforms come back as -1, which GL ignores.
+    pub unsafe fn uniform_location(&self, name: &str) -> GLint {
+        if let Some(loc) = self.locations.borrow().get(name) {
+            return *loc;
+        }
+        let loc = match CString::new(name) {
+            Ok(cname) => gl_call!(GetUniformLocation(self.id, cname.as_ptr())),
+            Err(_) => -1,
+        };
+        self.locations.borrow_mut().insert(name.to_string(), loc);
+        return loc;
+    }
+
+    pub unsafe fn bind(&self) {
+        state::use_program(self.id);
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        state::deleted_program(self.id);
+        unsafe { gl_call!(DeleteProgram(self.id)); }
+    }
+}
+
+// Prepends a #define for each feature, keeping any
+// #version line first where GLSL requires it.
+pub fn with_features(src: &str, features: &[String]) -> String {
+    // "NAME=VALUE" defines a value, e.g. an array size.
+    let defines: String = features.iter().map(|f| match f.split_once('=') {
+        Some((name, value)) => format!("#define {} {}\n", name, value),
+        None => format!("#define {} 1\n", f),
+    }).collect();
+    let trimmed = src.trim_start();
+    if trimmed.starts_with("#version") {
+        let end = trimmed.find('\n').map_or(trimmed.len(), |i| i + 1);
+        let (version, rest) = trimmed.split_at(end);
+        return format!("{}{}{}", version, defines, rest);
+    }
+    return format!("{}{}", defines, src);
+}
+
+// Compiled permutations of the shader assets, keyed by
+// shader name and the sorted set of enabled features.
+// A new context empties it, so variants get recompiled
+// rather than handed out dead.
+pub struct ShaderLibrary {
+    variants: HashMap<(String, Vec<String>), Rc<Program>>,
+    stamp: ContextStamp,
+}
+
+impl ShaderLibrary {
+    pub fn new() -> ShaderLibrary {
+        return ShaderLibrary { variants: HashMap::new(), stamp: ContextStamp::now() };
+    }
+
+    pub unsafe fn variant(&mut self, name: &str, features: &[String], attributes: &[(GLuint, &str)],
+      activity: &Activity) -> Result<Rc<Program>> {
+        let mut sorted = features.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        let key = (name.to_string(), sorted);
+        if !self.stamp.is_current() {
+            self.clear();
+        }
+        if let Some(p) = self.variants.get(&key) {
+            return Ok(p.clone());
+        }
+        let vert_src = with_features(&read_source(&shader_path(name, "vert"), activity)?, &key.1);
+        let frag_src = with_features(&read_source(&shader_path(name, "frag"), activity)?, &key.1);
+        let program = Rc::new(Program::new(&vert_src, &frag_src, attributes)?);
+        debug::label(debug::LABEL_PROGRAM, program.id, &format!("{}[{}]", name, key.1.join(",")));
+        self.variants.insert(key, program.clone());
+        return Ok(program);
+    }
+
+    // Drops every variant, e.g. when the context went away.
+    pub fn clear(&mut self) {
+        self.variants.clear();
+        self.stamp = ContextStamp::now();
+    }
+}
@@ -0,0 +1,186 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::model::MeshData;
+use crate::graphics::mesh::{self, ATTRIB_POSITION, ATTRIB_NORMAL, ATTRIB_JOINTS, ATTRIB_WEIGHTS};
+use crate::graphics::handle::BufferHandle;
+use crate::graphics::shader::Program;
+use crate::graphics::state;
+use crate::graphics::debug::gl_call;
+use crate::math::{Matrix4, Vector3};
+use std::ffi::c_void;
+
+// Linear blend skinning on the GPU. Skinned meshes carry up
+// to four joint influences per vertex and lit shaders built
+// with features(max_joints) blend the joint matrices uploaded
+// by upload_joints().
+
+pub const MAX_INFLUENCES: usize = 4;
+// Vertex uniform vectors left for everything besides joints.
+const RESERVED_UNIFORM_VECTORS: i32 = 16;
+// ES 2.0 only promises 128 vectors, which leaves room for 28.
+pub const DEFAULT_MAX_JOINTS: usize = 28;
+
+// Largest joint limit this context can hold in uniforms.
+// Every joint is a mat4, so four vectors.
+pub unsafe fn max_joints_supported() -> usize {
+    let mut vectors: GLint = 0;
+    gl_call!(GetIntegerv(::gl::MAX_VERTEX_UNIFORM_VECTORS, &mut vectors));
+    return ((vectors - RESERVED_UNIFORM_VECTORS).max(4) / 4) as usize;
+}
+
+// Shader features for a skinned variant with room for
+// `max_joints` joints. Variants are cached per limit.
+pub fn features(max_joints: usize) -> Vec<String> {
+    return vec!["SKINNING".to_string(), format!("MAX_JOINTS={}", max_joints)];
+}
+
+// Per-vertex joint indices and weights.
+pub struct SkinData {
+    pub joints: Vec<[u8; MAX_INFLUENCES]>,
+    pub weights: Vec<[f32; MAX_INFLUENCES]>,
+}
+
+impl SkinData {
+    // Keeps the strongest four influences of each vertex and
+    // renormalizes them. Vertices without any follow joint 0.
+    pub fn from_influences(influences: &[Vec<(usize, f32)>]) -> Result<SkinData> {
+        let mut skin = SkinData { joints: Vec::with_capacity(influences.len()), weights: Vec::with_capacity(influences.len()) };
+        for vertex in influences {
+            let mut sorted: Vec<(usize, f32)> = vertex.iter().copied().filter(|i| i.1 > 0.0).collect();
+            sorted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+            sorted.truncate(MAX_INFLUENCES);
+            let total: f32 = sorted.iter().map(|i| i.1).sum();
+            let mut joints = [0u8; MAX_INFLUENCES];
+            let mut weights = [0.0f32; MAX_INFLUENCES];
+            if total <= 0.0 {
+                weights[0] = 1.0;
+            }
+            for (k, (joint, weight)) in sorted.iter().enumerate() {
+                if *joint > u8::MAX as usize {
+                    return Err(Error::InvalidFormat("skinned meshes support at most 256 joints"));
+                }
+                joints[k] = *joint as u8;
+                weights[k] = weight / total;
+            }
+            skin.joints.push(joints);
+            skin.weights.push(weights);
+        }
+        return Ok(skin);
+    }
+}
+
+// Mesh with skinning attributes, interleaved as position,
+// normal, 4 joint bytes and 4 weight floats (44 bytes).
+// Like Mesh, its buffers are restored after a context loss.
+pub struct SkinnedMesh {
+    vbo: BufferHandle,
+    ibo: BufferHandle,
+    index_count: GLsizei,
+    index_type: GLenum,
+    // Highest joint index used, to check against the limit.
+    max_joint: usize,
+}
+
+const STRIDE: usize = 44;
+
+impl SkinnedMesh {
+    pub unsafe fn new(data: &MeshData, skin: &SkinData) -> Result<SkinnedMesh> {
+        if skin.joints.len() != data.positions.len() || skin.weights.len() != data.positions.len() {
+            return Err(Error::InvalidFormat("skin data doesn't match the mesh's vertex count"));
+        }
+        let mut vertices: Vec<u8> = Vec::with_capacity(data.positions.len() * STRIDE);
+        for (i, p) in data.positions.iter().enumerate() {
+            let n = data.normals.get(i).copied().unwrap_or(Vector3::new(0.0, 0.0, 1.0));
+            for f in [p.x, p.y, p.z, n.x, n.y, n.z] {
+                vertices.extend_from_slice(&f.to_ne_bytes());
+            }
+            vertices.extend_from_slice(&skin.joints[i]);
+            for w in skin.weights[i] {
+                vertices.extend_from_slice(&w.to_ne_bytes());
+            }
+        }
+        let (ibo, index_type) = mesh::index_buffer(&data.indices, data.positions.len());
+        let max_joint = skin.joints.iter().flat_map(|j| j.iter()).map(|j| *j as usize).max().unwrap_or(0);
+        return Ok(SkinnedMesh {
+            vbo: mesh::static_buffer(::gl::ARRAY_BUFFER, vertices),
+            ibo: ibo,
+            index_count: data.indices.len() as GLsizei,
+            index_type: index_type,
+            max_joint: max_joint,
+        });
+    }
+
+    pub fn max_joint(&self) -> usize {
+        return self.max_joint;
+    }
+
+    pub unsafe fn draw(&self) {
+        let (vbo, ibo) = match (self.vbo.get(), self.ibo.get()) {
+            (Some(vbo), Some(ibo)) => (vbo, ibo),
+            _ => return,
+        };
+        state::bind_buffer(::gl::ARRAY_BUFFER, vbo);
+        state::bind_buffer(::gl::ELEMENT_ARRAY_BUFFER, ibo);
+        let stride = STRIDE as GLsizei;
+        for attrib in [ATTRIB_POSITION, ATTRIB_NORMAL, ATTRIB_JOINTS, ATTRIB_WEIGHTS] {
+            gl_call!(EnableVertexAttribArray(attrib));
+        }
+        gl_call!(VertexAttribPointer(ATTRIB_POSITION, 3, ::gl::FLOAT, ::gl::FALSE, stride, std::ptr::null()));
+        gl_call!(VertexAttribPointer(ATTRIB_NORMAL, 3, ::gl::FLOAT, ::gl::FALSE, stride, 12 as *const c_void));
+        // Joint indices arrive in the shader as whole floats.
+        gl_call!(VertexAttribPointer(ATTRIB_JOINTS, 4, ::gl::UNSIGNED_BYTE, ::gl::FALSE, stride, 24 as *const c_void));
+        gl_call!(VertexAttribPointer(ATTRIB_WEIGHTS, 4, ::gl::FLOAT, ::gl::FALSE, stride, 28 as *const c_void));
+        gl_call!(DrawElements(::gl::TRIANGLES, self.index_count, self.index_type, std::ptr::null()));
+        for attrib in [ATTRIB_POSITION, ATTRIB_NORMAL, ATTRIB_JOINTS, ATTRIB_WEIGHTS] {
+            gl_call!(DisableVertexAttribArray(attrib));
+        }
+    }
+}
+
+impl Drop for SkinnedMesh {
+    fn drop(&mut self) {
+        self.vbo.release();
+        self.ibo.release();
+    }
+}
+
+// Uploads skinning matrices from Pose::skin_matrices to a
+// program built with features(max_joints).
+pub unsafe fn upload_joints(program: &Program, matrices: &[Matrix4], max_joints: usize) -> Result<()> {
+    if matrices.len() > max_joints {
+        return Err(Error::InvalidFormat("skeleton has more joints than the shader's joint limit"));
+    }
+    let mut flat: Vec<f32> = Vec::with_capacity(matrices.len() * 16);
+    for m in matrices {
+        flat.extend_from_slice(&m.to_array());
+    }
+    gl_call!(UniformMatrix4fv(program.uniform_location("u_joints[0]"), matrices.len() as GLsizei, ::gl::FALSE, flat.as_ptr()));
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_strongest_four_and_renormalizes() {
+        let skin = SkinData::from_influences(&[
+            vec![(1, 0.1), (2, 0.4), (3, 0.2), (4, 0.2), (5, 0.1)],
+            vec![(7, 2.0), (8, 0.0), (9, -1.0)],
+            vec![],
+        ]).unwrap();
+        assert_eq!(skin.joints[0][0], 2);
+        assert_eq!(&skin.joints[0][1..3], &[3, 4]);
+        let total: f32 = skin.weights[0].iter().sum();
+        assert!((total - 1.0).abs() < 1e-6);
+        assert!((skin.weights[0][0] - 0.4 / 0.9).abs() < 1e-6);
+        // Zero and negative weights aren't influences.
+        assert_eq!((skin.joints[1], skin.weights[1]), ([7, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]));
+        assert_eq!((skin.joints[2], skin.weights[2]), ([0; 4], [1.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn rejects_joints_past_a_byte() {
+        assert!(SkinData::from_influences(&[vec![(256, 1.0)]]).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use ::gl::types::*;
+use crate::graphics::debug::gl_call;
+use std::cell::RefCell;
+
+// Shadow copy of the GL state the renderer touches, so
+// redundant calls can be skipped before they reach the
+// driver. Every field starts out unknown (None) and the
+// first call always goes through. Anything that changes
+// this state behind the cache's back, like a new context
+// or foreign code, must be followed by invalidate().
+//
+// GL state belongs to the thread the context is current
+// on, so the cache is thread-local too.
+
+pub const MAX_TEXTURE_UNITS: usize = 16;
+
+// Capabilities tracked by set_enabled. Others pass through.
+const CAPABILITIES: [GLenum; 6] = [
+    ::gl::BLEND,
+    ::gl::DEPTH_TEST,
+    ::gl::CULL_FACE,
+    ::gl::SCISSOR_TEST,
+    ::gl::POLYGON_OFFSET_FILL,
+    ::gl::STENCIL_TEST,
+];
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Stats {
+    pub issued: u64,
+    pub skipped: u64,
+}
+
+#[derive(Default)]
+struct Shadow {
+    program: Option<GLuint>,
+    array_buffer: Option<GLuint>,
+    element_buffer: Option<GLuint>,
+    framebuffer: Option<GLuint>,
+    active_unit: Option<GLuint>,
+    textures: [Option<GLuint>; MAX_TEXTURE_UNITS],
+    capabilities: [Option<bool>; CAPABILITIES.len()],
+    // RGB source and destination, then alpha.
+    blend_func: Option<(GLenum, GLenum, GLenum, GLenum)>,
+    blend_equation: Option<(GLenum, GLenum)>,
+    color_mask: Option<[bool; 4]>,
+    stencil_func: Option<(GLenum, GLint, GLuint)>,
+    stencil_op: Option<(GLenum, GLenum, GLenum)>,
+    stencil_mask: Option<GLuint>,
+    polygon_offset: Option<(f32, f32)>,
+    depth_mask: Option<bool>,
+    depth_func: Option<GLenum>,
+    cull_face: Option<GLenum>,
+    scissor: Option<[GLint; 4]>,
+    viewport: Option<[GLint; 4]>,
+    stats: Stats,
+}
+
+thread_local! {
+    static SHADOW: RefCell<Shadow> = RefCell::new(Shadow::default());
+}
+
+// Updates a cached value, returning whether the
+// real GL call needs to be made.
+fn changed<T: PartialEq + Copy>(stats: &mut Stats, slot: &mut Option<T>, value: T) -> bool {
+    if *slot == Some(value) {
+        stats.skipped += 1;
+        return false;
+    }
+    *slot = Some(value);
+    stats.issued += 1;
+    return true;
+}
+
+// Forgets everything. Call after creating or making a
+// context current, since its state is unknown to us.
+pub fn invalidate() {
+    SHADOW.with(|s| {
+        let stats = s.borrow().stats;
+        *s.borrow_mut() = Shadow { stats: stats, ..Shadow::default() };
+    });
+}
+
+// Counters since the last call.
+pub fn take_stats() -> Stats {
+    return SHADOW.with(|s| std::mem::take(&mut s.borrow_mut().stats));
+}
+
+pub unsafe fn use_program(id: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.program, id) {
+            gl_call!(UseProgram(id));
+        }
+    });
+}
+
+// Only ARRAY_BUFFER and ELEMENT_ARRAY_BUFFER are tracked.
+pub unsafe fn bind_buffer(target: GLenum, id: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        let slot = match target {
+            ::gl::ARRAY_BUFFER => &mut s.array_buffer,
+            ::gl::ELEMENT_ARRAY_BUFFER => &mut s.element_buffer,
+            _ => {
+                gl_call!(BindBuffer(target, id));
+                return;
+            },
+        };
+        if changed(&mut s.stats, slot, id) {
+            gl_call!(BindBuffer(target, id));
+        }
+    });
+}
+
+pub unsafe fn bind_framebuffer(id: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.framebuffer, id) {
+            gl_call!(BindFramebuffer(::gl::FRAMEBUFFER, id));
+        }
+    });
+}
+
+// Binds a 2D texture to a unit, switching the
+// active unit only when the binding changes.
+pub unsafe fn bind_texture(unit: u32, id: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        let index = unit as usize;
+        if index >= MAX_TEXTURE_UNITS {
+            gl_call!(ActiveTexture(::gl::TEXTURE0 + unit));
+            gl_call!(BindTexture(::gl::TEXTURE_2D, id));
+            s.active_unit = Some(unit);
+            return;
+        }
+        if !changed(&mut s.stats, &mut s.textures[index], id) {
+            return;
+        }
+        if changed(&mut s.stats, &mut s.active_unit, unit) {
+            gl_call!(ActiveTexture(::gl::TEXTURE0 + unit));
+        }
+        gl_call!(BindTexture(::gl::TEXTURE_2D, id));
+    });
+}
+
+pub unsafe fn set_enabled(capability: GLenum, enabled: bool) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        let index = CAPABILITIES.iter().position(|c| *c == capability);
+        let issue = match index {
+            Some(i) => changed(&mut s.stats, &mut s.capabilities[i], enabled),
+            None => true,
+        };
+        if issue {
+            if enabled {
+                gl_call!(Enable(capability));
+            } else {
+                gl_call!(Disable(capability));
+            }
+        }
+    });
+}
+
+pub unsafe fn blend_func(src: GLenum, dst: GLenum) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.blend_func, (src, dst, src, dst)) {
+            gl_call!(BlendFunc(src, dst));
+        }
+    });
+}
+
+pub unsafe fn blend_func_separate(src_rgb: GLenum, dst_rgb: GLenum, src_alpha: GLenum, dst_alpha: GLenum) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.blend_func, (src_rgb, dst_rgb, src_alpha, dst_alpha)) {
+            gl_call!(BlendFuncSeparate(src_rgb, dst_rgb, src_alpha, dst_alpha));
+        }
+    });
+}
+
+pub unsafe fn blend_equation(rgb: GLenum, alpha: GLenum) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.blend_equation, (rgb, alpha)) {
+            gl_call!(BlendEquationSeparate(rgb, alpha));
+        }
+    });
+}
+
+pub unsafe fn color_mask(mask: [bool; 4]) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.color_mask, mask) {
+            let b = |v: bool| if v { ::gl::TRUE } else { ::gl::FALSE };
+            gl_call!(ColorMask(b(mask[0]), b(mask[1]), b(mask[2]), b(mask[3])));
+        }
+    });
+}
+
+// Front and back faces share stencil state.
+pub unsafe fn stencil_func(func: GLenum, reference: GLint, mask: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.stencil_func, (func, reference, mask)) {
+            gl_call!(StencilFunc(func, reference, mask));
+        }
+    });
+}
+
+pub unsafe fn stencil_op(fail: GLenum, depth_fail: GLenum, pass: GLenum) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.stencil_op, (fail, depth_fail, pass)) {
+            gl_call!(StencilOp(fail, depth_fail, pass));
+        }
+    });
+}
+
+pub unsafe fn stencil_mask(mask: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.stencil_mask, mask) {
+            gl_call!(StencilMask(mask));
+        }
+    });
+}
+
+pub unsafe fn polygon_offset(factor: f32, units: f32) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.polygon_offset, (factor, units)) {
+            gl_call!(PolygonOffset(factor, units));
+        }
+    });
+}
+
+pub unsafe fn depth_mask(write: bool) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.depth_mask, write) {
+            gl_call!(DepthMask(if write { ::gl::TRUE } else { ::gl::FALSE }));
+        }
+    });
+}
+
+pub unsafe fn depth_func(func: GLenum) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.depth_func, func) {
+            gl_call!(DepthFunc(func));
+        }
+    });
+}
+
+pub unsafe fn cull_face(mode: GLenum) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.cull_face, mode) {
+            gl_call!(CullFace(mode));
+        }
+    });
+}
+
+pub unsafe fn scissor(x: GLint, y: GLint, width: GLint, height: GLint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.scissor, [x, y, width, height]) {
+            gl_call!(Scissor(x, y, width, height));
+        }
+    });
+}
+
+pub unsafe fn viewport(x: GLint, y: GLint, width: GLint, height: GLint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if changed(&mut s.stats, &mut s.viewport, [x, y, width, height]) {
+            gl_call!(Viewport(x, y, width, height));
+        }
+    });
+}
+
+// GL reverts bindings of deleted objects to 0, and ids get
+// reused, so owners must report deletions or a new object
+// with a recycled id would be thought already bound.
+
+// Except programs: deleting the current one leaves it in
+// use until something else is, so what's bound is unknown.
+pub fn deleted_program(id: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.program == Some(id) {
+            s.program = None;
+        }
+    });
+}
+
+pub fn deleted_buffers(ids: &[GLuint]) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        for slot in [&mut s.array_buffer, &mut s.element_buffer] {
+            if slot.map_or(false, |b| ids.contains(&b)) {
+                *slot = Some(0);
+            }
+        }
+    });
+}
+
+pub fn deleted_texture(id: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        for slot in s.textures.iter_mut() {
+            if *slot == Some(id) {
+                *slot = Some(0);
+            }
+        }
+    });
+}
+
+pub fn deleted_framebuffer(id: GLuint) {
+    SHADOW.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.framebuffer == Some(id) {
+            s.framebuffer = Some(0);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deleting_the_current_program_forgets_it() {
+        SHADOW.with(|s| s.borrow_mut().program = Some(5));
+        deleted_program(4);
+        assert_eq!(SHADOW.with(|s| s.borrow().program), Some(5));
+        deleted_program(5);
+        assert_eq!(SHADOW.with(|s| s.borrow().program), None);
+    }
+}
@@ -0,0 +1,76 @@
+use ::gl::types::*;
+use crate::graphics::{Result, Error};
+use crate::graphics::texture::{Texture, PixelFormat, Filter};
+use crate::graphics::state;
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::{self, gl_call};
+
+// Offscreen color target with an optional depth buffer.
+// The color texture can be sampled once rendering is done.
+pub struct RenderTarget {
+    fbo: GLuint,
+    depth_rb: GLuint,
+    pub color: Texture,
+    pub width: i32,
+    pub height: i32,
+    stamp: ContextStamp,
+}
+
+impl RenderTarget {
+    pub unsafe fn new(width: i32, height: i32, depth: bool, filter: Filter) -> Result<RenderTarget> {
+        let mut previous: GLint = 0;
+        gl_call!(GetIntegerv(::gl::FRAMEBUFFER_BINDING, &mut previous));
+        let color = Texture::new(width, height, PixelFormat::RGBA8, filter, None)?;
+        let mut target = RenderTarget { fbo: 0, depth_rb: 0, color: color, width: width, height: height,
+            stamp: ContextStamp::now() };
+        gl_call!(GenFramebuffers(1, &mut target.fbo));
+        state::bind_framebuffer(target.fbo);
+        gl_call!(FramebufferTexture2D(::gl::FRAMEBUFFER, ::gl::COLOR_ATTACHMENT0, ::gl::TEXTURE_2D, target.color.id(), 0));
+        if depth {
+            gl_call!(GenRenderbuffers(1, &mut target.depth_rb));
+            gl_call!(BindRenderbuffer(::gl::RENDERBUFFER, target.depth_rb));
+            gl_call!(RenderbufferStorage(::gl::RENDERBUFFER, ::gl::DEPTH_COMPONENT16, width, height));
+            gl_call!(FramebufferRenderbuffer(::gl::FRAMEBUFFER, ::gl::DEPTH_ATTACHMENT, ::gl::RENDERBUFFER, target.depth_rb));
+        }
+        let status = gl_call!(CheckFramebufferStatus(::gl::FRAMEBUFFER));
+        state::bind_framebuffer(previous as GLuint);
+        if status != ::gl::FRAMEBUFFER_COMPLETE {
+            return Err(Error::FramebufferIncomplete(status));
+        }
+        return Ok(target);
+    }
+
+    // Names the framebuffer and its textures for debug output.
+    pub unsafe fn set_label(&self, name: &str) {
+        debug::label(debug::LABEL_FRAMEBUFFER, self.fbo, name);
+        debug::label(debug::LABEL_TEXTURE, self.color.id(), name);
+        if self.depth_rb != 0 {
+            debug::label(debug::LABEL_RENDERBUFFER, self.depth_rb, name);
+        }
+    }
+
+    pub fn fbo(&self) -> GLuint {
+        return self.fbo;
+    }
+
+    // Binds for drawing and sets the viewport to cover it.
+    pub unsafe fn bind(&self) {
+        state::bind_framebuffer(self.fbo);
+        state::viewport(0, 0, self.width, self.height);
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        unsafe {
+            state::deleted_framebuffer(self.fbo);
+            gl_call!(DeleteFramebuffers(1, &self.fbo));
+            if self.depth_rb != 0 {
+                gl_call!(DeleteRenderbuffers(1, &self.depth_rb));
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use ::gl::types::*;
+use crate::graphics::gl::LUMINANCE;
+use crate::graphics::state;
+use crate::graphics::restore::ContextStamp;
+use crate::graphics::debug::gl_call;
+use crate::graphics::{Result, Error};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    // Single channel, read back as .r in shaders.
+    R8,
+    RGB8,
+    RGBA8,
+}
+
+impl PixelFormat {
+    // Unsized formats so ES 2.0 accepts them too.
+    pub(crate) fn gl_format(self) -> GLenum {
+        return match self {
+            PixelFormat::R8 => LUMINANCE,
+            PixelFormat::RGB8 => ::gl::RGB,
+            PixelFormat::RGBA8 => ::gl::RGBA,
+        };
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        return match self {
+            PixelFormat::R8 => 1,
+            PixelFormat::RGB8 => 3,
+            PixelFormat::RGBA8 => 4,
+        };
+    }
+}
+
+// Bytes a width x height upload of `format` reads, or None
+// for negative or overflowing sizes.
+pub(crate) fn upload_size(width: i32, height: i32, format: PixelFormat) -> Option<usize> {
+    if width < 0 || height < 0 {
+        return None;
+    }
+    return (width as usize).checked_mul(height as usize)?.checked_mul(format.bytes_per_pixel());
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+pub struct Texture {
+    id: GLuint,
+    pub width: i32,
+    pub height: i32,
+    pub format: PixelFormat,
+    stamp: ContextStamp,
+}
+
+impl Texture {
+    // Creates a clamped 2D texture. Passing None for
+    // the pixels leaves the contents undefined, which
+    // is what render targets want.
+    pub unsafe fn new(width: i32, height: i32, format: PixelFormat, filter: Filter, pixels: Option<&[u8]>) -> Result<Texture> {
+        let size = match upload_size(width, height, format) {
+            Some(s) => s,
+            None => return Err(Error::InvalidFormat("bad texture size")),
+        };
+        if pixels.map_or(false, |p| p.len() < size) {
+            return Err(Error::InvalidFormat("too few pixels for the texture size"));
+        }
+        let mut id: GLuint = 0;
+        gl_call!(GenTextures(1, &mut id));
+        state::bind_texture(0, id);
+        let gl_filter = match filter {
+            Filter::Nearest => ::gl::NEAREST,
+            Filter::Linear => ::gl::LINEAR,
+        } as GLint;
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_MIN_FILTER, gl_filter));
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_MAG_FILTER, gl_filter));
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_WRAP_S, ::gl::CLAMP_TO_EDGE as GLint));
+        gl_call!(TexParameteri(::gl::TEXTURE_2D, ::gl::TEXTURE_WRAP_T, ::gl::CLAMP_TO_EDGE as GLint));
+        // Rows of single channel textures aren't 4-byte aligned.
+        gl_call!(PixelStorei(::gl::UNPACK_ALIGNMENT, 1));
+        let data = match pixels {
+            Some(p) => p.as_ptr() as *const c_void,
+            None => std::ptr::null(),
+        };
+        gl_call!(TexImage2D(::gl::TEXTURE_2D, 0, format.gl_format() as GLint, width, height, 0,
+            format.gl_format(), ::gl::UNSIGNED_BYTE, data));
+        return Ok(Texture { id: id, width: width, height: height, format: format, stamp: ContextStamp::now() });
+    }
+
+    pub fn id(&self) -> GLuint {
+        return self.id;
+    }
+
+    // False after the context it was made in went away.
+    pub fn is_current(&self) -> bool {
+        return self.stamp.is_current();
+    }
+
+    // Hands the GL texture over to the caller, who
+    // becomes responsible for deleting it.
+    pub fn into_raw(self) -> GLuint {
+        let id = self.id;
+        std::mem::forget(self);
+        return id;
+    }
+
+    pub unsafe fn bind(&self, unit: u32) {
+        state::bind_texture(unit, self.id);
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        if !self.stamp.is_current() {
+            return;
+        }
+        state::deleted_texture(self.id);
+        unsafe { gl_call!(DeleteTextures(1, &self.id)); }
+    }
+}
+
+// Loaded textures by asset path so materials
+// referencing the same image share one texture. Textures
+// from an earlier context are never handed out.
+pub struct TextureLibrary {
+    textures: HashMap<String, Rc<Texture>>,
+    stamp: ContextStamp,
+}
+
+impl TextureLibrary {
+    pub fn new() -> TextureLibrary {
+        return TextureLibrary { textures: HashMap::new(), stamp: ContextStamp::now() };
+    }
+
+    pub fn insert(&mut self, path: &str, texture: Texture) -> Rc<Texture> {
+        if !self.stamp.is_current() {
+            self.clear();
+        }
+        let rc = Rc::new(texture);
+        self.textures.insert(path.to_string(), rc.clone());
+        return rc;
+    }
+
+    pub fn get(&self, path: &str) -> Option<Rc<Texture>> {
+        if !self.stamp.is_current() {
+            return None;
+        }
+        return self.textures.get(path).cloned();
+    }
+
+    pub fn clear(&mut self) {
+        self.textures.clear();
+        self.stamp = ContextStamp::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Both are refused before any GL call is made.
+    #[test]
+    fn new_rejects_bad_uploads() {
+        unsafe {
+            assert!(Texture::new(4, 4, PixelFormat::RGBA8, Filter::Nearest, Some(&[0; 63])).is_err());
+            assert!(Texture::new(i32::MAX, i32::MAX, PixelFormat::RGB8, Filter::Nearest, Some(&[0; 4])).is_err());
+            assert!(Texture::new(-2, 4, PixelFormat::R8, Filter::Linear, None).is_err());
+        }
+    }
+}
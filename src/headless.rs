@@ -0,0 +1,113 @@
+use crate::bridge::bindings::{self, Settings};
+use crate::graphics::Result;
+use crate::graphics::pacing::{self, FrameCap, PacingSettings};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// Runs the game loop offscreen on the Linux bridge for a
+// bounded number of frames or amount of time, optionally
+// saving every frame as a PNG. This is what the headless
+// binary drives; it's here so the bridge can stay private.
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub width: i32,
+    pub height: i32,
+    pub asset_dir: PathBuf,
+    pub data_dir: PathBuf,
+    // Frames are written as frame_00000.png and so on.
+    pub output_dir: Option<PathBuf>,
+    // Save one frame in this many.
+    pub capture_every: u64,
+    // Stops at whichever limit is hit first. With neither,
+    // runs until the process is killed.
+    pub frames: Option<u64>,
+    pub time_limit: Option<Duration>,
+    pub software: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let settings = Settings::default();
+        return Options {
+            width: settings.width,
+            height: settings.height,
+            asset_dir: settings.asset_dir,
+            data_dir: settings.data_dir,
+            output_dir: None,
+            capture_every: 1,
+            frames: None,
+            time_limit: None,
+            software: settings.software,
+        };
+    }
+}
+
+// Returns the number of frames rendered. Call on the thread
+// that should render; it stays the render thread.
+pub fn run(options: &Options) -> Result<u64> {
+    bindings::init(&Settings {
+        width: options.width,
+        height: options.height,
+        asset_dir: options.asset_dir.clone(),
+        data_dir: options.data_dir.clone(),
+        software: options.software,
+    })?;
+    // Nobody's watching, so there's no reason to wait.
+    pacing::set_settings(PacingSettings { swap_interval: 0, cap: FrameCap::Unlimited });
+    if let Some(dir) = options.output_dir.as_ref() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let start = Instant::now();
+    let mut frame: u64 = 0;
+    loop {
+        if options.frames.map_or(false, |f| frame >= f) {
+            break;
+        }
+        if options.time_limit.map_or(false, |t| start.elapsed() >= t) {
+            break;
+        }
+        let capture = options.output_dir.is_some() && frame % options.capture_every.max(1) == 0;
+        let image = bindings::render_frame(capture)?;
+        if let (Some(dir), Some(image)) = (options.output_dir.as_ref(), image) {
+            image.save(&dir.join(format!("frame_{:05}.png", frame)))?;
+        }
+        frame += 1;
+    }
+    log::info!("Rendered {} frames in {:.2}s", frame, start.elapsed().as_secs_f64());
+    return Ok(frame);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::capture::{self, Image};
+
+    // The only test that renders: the first thread to call
+    // init stays the render thread for the whole process.
+    #[test]
+    fn matches_the_golden_frame() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let scratch = std::env::temp_dir().join("hbat-golden");
+        bindings::init(&Settings {
+            width: 64,
+            height: 36,
+            asset_dir: root.join("assets"),
+            data_dir: scratch.clone(),
+            // The same rasterizer on every machine.
+            software: true,
+        }).unwrap();
+        pacing::set_settings(PacingSettings { swap_interval: 0, cap: FrameCap::Unlimited });
+        let mut frame = None;
+        for _ in 0..3 {
+            frame = bindings::render_frame(true).unwrap();
+        }
+        let expected = Image::load(&root.join("tests").join("golden").join("clear.png")).unwrap();
+        let diff = capture::diff(&expected, &frame.unwrap(), 2).unwrap();
+        if diff.mismatched > 0 {
+            let path = scratch.join("clear_diff.png");
+            diff.image.save(&path).unwrap();
+            panic!("{} pixels differ by up to {}, see {}", diff.mismatched, diff.max_delta, path.display());
+        }
+    }
+}
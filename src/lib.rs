@@ -1,10 +1,15 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 mod math;
+mod utils;
 mod mainloop;
 
 #[cfg_attr(target_os="android", path="bridge/android/mod.rs")]
+#[cfg_attr(target_os="linux", path="bridge/linux/mod.rs")]
 mod bridge;
 
 #[path="graphics/mod.rs"]
 mod graphics;
+
+#[cfg(target_os="linux")]
+pub mod headless;
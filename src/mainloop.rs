@@ -9,10 +9,16 @@ pub fn render(graphics: &mut Graphics) {
         Ok(_) => {
             info!("Got the context!");
             unsafe {
-                gl::Viewport(0, 0, graphics.width, graphics.height);
+                let _clear = crate::graphics::profiler::pass("clear");
+                crate::graphics::state::viewport(0, 0, graphics.width, graphics.height);
                 gl::ClearColor(1.0, 0.0, 1.0, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT);
             }
+            unsafe {
+                // Whatever game code recorded for this frame.
+                let _commands = crate::graphics::profiler::pass("commands");
+                crate::graphics::commands::execute_pending();
+            }
         }
         Err(e) => {
             error!("Did not get the context! {:?}", e);
@@ -1,4 +1,4 @@
-use std::ops::{Mul, Add};
+use std::ops::{Mul, Add, Sub, Neg};
 
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -23,6 +23,62 @@ pub struct Vector3 {
     pub z: f32,  
 }
 
+impl Vector3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Vector3 {
+        return Vector3 { x: x, y: y, z: z };
+    }
+
+    pub fn dot(self, rhs: Vector3) -> f32 {
+        return self.x * rhs.x + self.y * rhs.y + self.z * rhs.z;
+    }
+
+    pub fn cross(self, rhs: Vector3) -> Vector3 {
+        return Vector3 {
+            x: self.y * rhs.z - self.z * rhs.y,
+            y: self.z * rhs.x - self.x * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x,
+        };
+    }
+
+    pub fn length(self) -> f32 {
+        return self.dot(self).sqrt();
+    }
+
+    // Zero vectors stay zero instead of turning into NaNs.
+    pub fn normalize(self) -> Vector3 {
+        let len = self.length();
+        return if len > 0.0 { self * (1.0 / len) } else { self };
+    }
+}
+
+impl Add for Vector3 {
+    type Output = Vector3;
+    fn add(self, rhs: Vector3) -> Vector3 {
+        Vector3 { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z }
+    }
+}
+
+impl Sub for Vector3 {
+    type Output = Vector3;
+    fn sub(self, rhs: Vector3) -> Vector3 {
+        Vector3 { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z }
+    }
+}
+
+impl Neg for Vector3 {
+    type Output = Vector3;
+    fn neg(self) -> Vector3 {
+        Vector3 { x: -self.x, y: -self.y, z: -self.z }
+    }
+}
+
+impl Mul<f32> for Vector3 {
+    type Output = Vector3;
+    fn mul(self, rhs: f32) -> Vector3 {
+        Vector3 { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
+    }
+}
+
 #[repr(C, align(16))]
 #[derive(Copy, Clone)]
 pub struct Vector4 {
@@ -44,6 +100,10 @@ impl Vector4 {
     pub fn from_v3_1(v: Vector3) -> Vector4 {
         return Self::from_v3_f32(v, 1.0);
     }    
+
+    pub fn xyz(self) -> Vector3 {
+        return Vector3 { x: self.x, y: self.y, z: self.z };
+    }
 }
 
 impl Add for Vector4 {
@@ -76,6 +136,86 @@ impl Mul<f32> for Vector4 {
     }
 }
 
+// Rotation as a unit quaternion, vector part first.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Quaternion {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+pub static Q_IDENTITY: Quaternion = Quaternion { x: 0.0, y: 0.0, z: 0.0, w: 1.0 };
+
+impl Quaternion {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Quaternion {
+        return Quaternion { x: x, y: y, z: z, w: w };
+    }
+
+    // Angle is in radians.
+    pub fn from_axis_angle(axis: Vector3, angle: f32) -> Quaternion {
+        let a = axis.normalize() * (angle * 0.5).sin();
+        return Quaternion { x: a.x, y: a.y, z: a.z, w: (angle * 0.5).cos() };
+    }
+
+    pub fn dot(self, rhs: Quaternion) -> f32 {
+        return self.x * rhs.x + self.y * rhs.y + self.z * rhs.z + self.w * rhs.w;
+    }
+
+    pub fn normalize(self) -> Quaternion {
+        let len = self.dot(self).sqrt();
+        if len <= 0.0 {
+            return Q_IDENTITY;
+        }
+        let inv = 1.0 / len;
+        return Quaternion { x: self.x * inv, y: self.y * inv, z: self.z * inv, w: self.w * inv };
+    }
+
+    // Normalized lerp along the shorter arc. Close enough to
+    // slerp for keyframes and blending, and much cheaper.
+    pub fn nlerp(self, rhs: Quaternion, t: f32) -> Quaternion {
+        let sign = if self.dot(rhs) < 0.0 { -1.0 } else { 1.0 };
+        let s = 1.0 - t;
+        let u = t * sign;
+        return Quaternion {
+            x: self.x * s + rhs.x * u,
+            y: self.y * s + rhs.y * u,
+            z: self.z * s + rhs.z * u,
+            w: self.w * s + rhs.w * u,
+        }.normalize();
+    }
+
+    pub fn rotate(self, v: Vector3) -> Vector3 {
+        let q = Vector3::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.0;
+        return v + t * self.w + q.cross(t);
+    }
+
+    pub fn to_matrix(self) -> Matrix4 {
+        let (x, y, z, w) = (self.x, self.y, self.z, self.w);
+        return Matrix4 {
+            v1: Vector4 { x: 1.0 - 2.0 * (y * y + z * z), y: 2.0 * (x * y + z * w), z: 2.0 * (x * z - y * w), w: 0.0 },
+            v2: Vector4 { x: 2.0 * (x * y - z * w), y: 1.0 - 2.0 * (x * x + z * z), z: 2.0 * (y * z + x * w), w: 0.0 },
+            v3: Vector4 { x: 2.0 * (x * z + y * w), y: 2.0 * (y * z - x * w), z: 1.0 - 2.0 * (x * x + y * y), w: 0.0 },
+            v4: Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+        };
+    }
+}
+
+// Hamilton product: applies rhs first, then self.
+impl Mul for Quaternion {
+    type Output = Quaternion;
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        return Quaternion {
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+        };
+    }
+}
+
 #[repr(C, align(16))]
 #[derive(Copy, Clone)]
 pub struct Matrix2 {
@@ -118,6 +258,142 @@ impl Matrix4 {
             v4: Vector4::from_v3_1(v),
         }
     }
+
+    pub fn translation(v: Vector3) -> Matrix4 {
+        let mut m = M4_IDENTITY;
+        m.v4 = Vector4::from_v3_1(v);
+        return m;
+    }
+
+    pub fn scale(v: Vector3) -> Matrix4 {
+        let mut m = M4_IDENTITY;
+        m.v1.x = v.x;
+        m.v2.y = v.y;
+        m.v3.z = v.z;
+        return m;
+    }
+
+    // Scale, then rotate, then translate.
+    pub fn from_trs(t: Vector3, r: Quaternion, s: Vector3) -> Matrix4 {
+        let mut m = r.to_matrix();
+        m.v1 = m.v1 * s.x;
+        m.v2 = m.v2 * s.y;
+        m.v3 = m.v3 * s.z;
+        m.v4 = Vector4::from_v3_1(t);
+        return m;
+    }
+
+    // Right-handed projection looking down -z into
+    // GL clip space. fov_y is in radians.
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Matrix4 {
+        let f = 1.0 / (fov_y * 0.5).tan();
+        let nf = 1.0 / (near - far);
+        return Matrix4 {
+            v1: Vector4 { x: f / aspect, y: 0.0, z: 0.0, w: 0.0 },
+            v2: Vector4 { x: 0.0, y: f, z: 0.0, w: 0.0 },
+            v3: Vector4 { x: 0.0, y: 0.0, z: (far + near) * nf, w: -1.0 },
+            v4: Vector4 { x: 0.0, y: 0.0, z: 2.0 * far * near * nf, w: 0.0 },
+        };
+    }
+
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix4 {
+        let (w, h, d) = (right - left, top - bottom, far - near);
+        return Matrix4 {
+            v1: Vector4 { x: 2.0 / w, y: 0.0, z: 0.0, w: 0.0 },
+            v2: Vector4 { x: 0.0, y: 2.0 / h, z: 0.0, w: 0.0 },
+            v3: Vector4 { x: 0.0, y: 0.0, z: -2.0 / d, w: 0.0 },
+            v4: Vector4 { x: -(right + left) / w, y: -(top + bottom) / h, z: -(far + near) / d, w: 1.0 },
+        };
+    }
+
+    // View matrix for an eye at `eye` looking at `target`.
+    pub fn look_at(eye: Vector3, target: Vector3, up: Vector3) -> Matrix4 {
+        let f = (target - eye).normalize();
+        let s = f.cross(up).normalize();
+        let u = s.cross(f);
+        return Matrix4 {
+            v1: Vector4 { x: s.x, y: u.x, z: -f.x, w: 0.0 },
+            v2: Vector4 { x: s.y, y: u.y, z: -f.y, w: 0.0 },
+            v3: Vector4 { x: s.z, y: u.z, z: -f.z, w: 0.0 },
+            v4: Vector4 { x: -s.dot(eye), y: -u.dot(eye), z: f.dot(eye), w: 1.0 },
+        };
+    }
+
+    pub fn to_array(&self) -> [f32; 16] {
+        let c = [self.v1, self.v2, self.v3, self.v4];
+        let mut out = [0.0; 16];
+        for i in 0..4 {
+            out[i * 4] = c[i].x;
+            out[i * 4 + 1] = c[i].y;
+            out[i * 4 + 2] = c[i].z;
+            out[i * 4 + 3] = c[i].w;
+        }
+        return out;
+    }
+
+    pub fn from_array(a: &[f32; 16]) -> Matrix4 {
+        let col = |i: usize| Vector4 { x: a[i * 4], y: a[i * 4 + 1], z: a[i * 4 + 2], w: a[i * 4 + 3] };
+        return Matrix4 { v1: col(0), v2: col(1), v3: col(2), v4: col(3) };
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let a = self.to_array();
+        let mut t = [0.0; 16];
+        for c in 0..4 {
+            for r in 0..4 {
+                t[r * 4 + c] = a[c * 4 + r];
+            }
+        }
+        return Self::from_array(&t);
+    }
+
+    // General inverse by cofactor expansion. Returns None
+    // for singular matrices (e.g. a zero scale).
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let m = self.to_array();
+        let mut inv = [0.0f32; 16];
+        inv[0] = m[5] * m[10] * m[15] - m[5] * m[11] * m[14] - m[9] * m[6] * m[15]
+            + m[9] * m[7] * m[14] + m[13] * m[6] * m[11] - m[13] * m[7] * m[10];
+        inv[4] = -m[4] * m[10] * m[15] + m[4] * m[11] * m[14] + m[8] * m[6] * m[15]
+            - m[8] * m[7] * m[14] - m[12] * m[6] * m[11] + m[12] * m[7] * m[10];
+        inv[8] = m[4] * m[9] * m[15] - m[4] * m[11] * m[13] - m[8] * m[5] * m[15]
+            + m[8] * m[7] * m[13] + m[12] * m[5] * m[11] - m[12] * m[7] * m[9];
+        inv[12] = -m[4] * m[9] * m[14] + m[4] * m[10] * m[13] + m[8] * m[5] * m[14]
+            - m[8] * m[6] * m[13] - m[12] * m[5] * m[10] + m[12] * m[6] * m[9];
+        inv[1] = -m[1] * m[10] * m[15] + m[1] * m[11] * m[14] + m[9] * m[2] * m[15]
+            - m[9] * m[3] * m[14] - m[13] * m[2] * m[11] + m[13] * m[3] * m[10];
+        inv[5] = m[0] * m[10] * m[15] - m[0] * m[11] * m[14] - m[8] * m[2] * m[15]
+            + m[8] * m[3] * m[14] + m[12] * m[2] * m[11] - m[12] * m[3] * m[10];
+        inv[9] = -m[0] * m[9] * m[15] + m[0] * m[11] * m[13] + m[8] * m[1] * m[15]
+            - m[8] * m[3] * m[13] - m[12] * m[1] * m[11] + m[12] * m[3] * m[9];
+        inv[13] = m[0] * m[9] * m[14] - m[0] * m[10] * m[13] - m[8] * m[1] * m[14]
+            + m[8] * m[2] * m[13] + m[12] * m[1] * m[10] - m[12] * m[2] * m[9];
+        inv[2] = m[1] * m[6] * m[15] - m[1] * m[7] * m[14] - m[5] * m[2] * m[15]
+            + m[5] * m[3] * m[14] + m[13] * m[2] * m[7] - m[13] * m[3] * m[6];
+        inv[6] = -m[0] * m[6] * m[15] + m[0] * m[7] * m[14] + m[4] * m[2] * m[15]
+            - m[4] * m[3] * m[14] - m[12] * m[2] * m[7] + m[12] * m[3] * m[6];
+        inv[10] = m[0] * m[5] * m[15] - m[0] * m[7] * m[13] - m[4] * m[1] * m[15]
+            + m[4] * m[3] * m[13] + m[12] * m[1] * m[7] - m[12] * m[3] * m[5];
+        inv[14] = -m[0] * m[5] * m[14] + m[0] * m[6] * m[13] + m[4] * m[1] * m[14]
+            - m[4] * m[2] * m[13] - m[12] * m[1] * m[6] + m[12] * m[2] * m[5];
+        inv[3] = -m[1] * m[6] * m[11] + m[1] * m[7] * m[10] + m[5] * m[2] * m[11]
+            - m[5] * m[3] * m[10] - m[9] * m[2] * m[7] + m[9] * m[3] * m[6];
+        inv[7] = m[0] * m[6] * m[11] - m[0] * m[7] * m[10] - m[4] * m[2] * m[11]
+            + m[4] * m[3] * m[10] + m[8] * m[2] * m[7] - m[8] * m[3] * m[6];
+        inv[11] = -m[0] * m[5] * m[11] + m[0] * m[7] * m[9] + m[4] * m[1] * m[11]
+            - m[4] * m[3] * m[9] - m[8] * m[1] * m[7] + m[8] * m[3] * m[5];
+        inv[15] = m[0] * m[5] * m[10] - m[0] * m[6] * m[9] - m[4] * m[1] * m[10]
+            + m[4] * m[2] * m[9] + m[8] * m[1] * m[6] - m[8] * m[2] * m[5];
+        let det = m[0] * inv[0] + m[1] * inv[4] + m[2] * inv[8] + m[3] * inv[12];
+        if det.abs() < f32::EPSILON * f32::EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        for v in inv.iter_mut() {
+            *v *= inv_det;
+        }
+        return Some(Self::from_array(&inv));
+    }
 }
 
 impl Mul<f32> for Matrix4 {
@@ -151,3 +427,80 @@ impl Mul for Matrix4 {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        return (a - b).abs() < 1e-4;
+    }
+
+    fn close_m4(a: &Matrix4, b: &Matrix4) -> bool {
+        return a.to_array().iter().zip(b.to_array().iter()).all(|(x, y)| close(*x, *y));
+    }
+
+    fn transform(m: &Matrix4, p: Vector3) -> Vector3 {
+        let v = *m * Vector4::from_v3_1(p);
+        return Vector3::new(v.x / v.w, v.y / v.w, v.z / v.w);
+    }
+
+    #[test]
+    fn quaternions_rotate_and_compose() {
+        let quarter = Quaternion::from_axis_angle(Vector3::new(0.0, 0.0, 2.0), std::f32::consts::FRAC_PI_2);
+        let v = quarter.rotate(Vector3::new(1.0, 0.0, 0.0));
+        assert!(close(v.x, 0.0) && close(v.y, 1.0) && close(v.z, 0.0));
+        let half = quarter * quarter;
+        let v = half.rotate(Vector3::new(1.0, 0.0, 0.0));
+        assert!(close(v.x, -1.0) && close(v.y, 0.0));
+        // The matrix agrees with rotate().
+        let m = quarter.to_matrix() * Vector4::from_v3_0(Vector3::new(0.0, 1.0, 0.0));
+        assert!(close(m.x, -1.0) && close(m.y, 0.0));
+    }
+
+    #[test]
+    fn nlerp_takes_the_short_arc() {
+        let a = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), 0.0);
+        let b = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), 1.0);
+        let mid = a.nlerp(b, 0.5);
+        assert!(close(mid.dot(mid), 1.0));
+        assert!(close(mid.y, (0.25f32).sin()) && close(mid.w, (0.25f32).cos()));
+        // -b is the same rotation, and blends the same way.
+        let flipped = a.nlerp(Quaternion::new(-b.x, -b.y, -b.z, -b.w), 0.5);
+        assert!(close(flipped.dot(mid).abs(), 1.0));
+        assert!(close(a.nlerp(b, 1.0).dot(b), 1.0));
+    }
+
+    #[test]
+    fn inverse_undoes_and_refuses_singular() {
+        let m = Matrix4::from_trs(Vector3::new(1.0, -2.0, 3.0),
+            Quaternion::from_axis_angle(Vector3::new(1.0, 1.0, 0.0), 0.7), Vector3::new(2.0, 0.5, 1.5));
+        let inv = m.inverse().unwrap();
+        assert!(close_m4(&(m * inv), &M4_IDENTITY));
+        assert!(close_m4(&(inv * m), &M4_IDENTITY));
+        assert!(Matrix4::scale(Vector3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn look_at_puts_the_target_down_minus_z() {
+        let eye = Vector3::new(3.0, 4.0, 5.0);
+        let view = Matrix4::look_at(eye, Vector3::new(3.0, 4.0, -5.0), Vector3::new(0.0, 1.0, 0.0));
+        let e = transform(&view, eye);
+        assert!(close(e.x, 0.0) && close(e.y, 0.0) && close(e.z, 0.0));
+        let t = transform(&view, Vector3::new(3.0, 4.0, -5.0));
+        assert!(close(t.x, 0.0) && close(t.y, 0.0) && close(t.z, -10.0));
+        let up = transform(&view, Vector3::new(3.0, 5.0, 5.0));
+        assert!(close(up.y, 1.0));
+    }
+
+    #[test]
+    fn perspective_maps_near_and_far_to_clip_bounds() {
+        let proj = Matrix4::perspective(std::f32::consts::FRAC_PI_2, 2.0, 0.5, 100.0);
+        assert!(close(transform(&proj, Vector3::new(0.0, 0.0, -0.5)).z, -1.0));
+        assert!(close(transform(&proj, Vector3::new(0.0, 0.0, -100.0)).z, 1.0));
+        // A 90 degree fov reaches the top edge at y == depth,
+        // and the aspect ratio halves x.
+        let edge = transform(&proj, Vector3::new(4.0, 2.0, -2.0));
+        assert!(close(edge.x, 1.0) && close(edge.y, 1.0));
+    }
+}
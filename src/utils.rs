@@ -16,3 +16,76 @@ pub fn slice_clamp<T>(arr: &[T], range: Range) {
 }
 */
 
+// Cursor over a byte slice for parsing the binary
+// asset formats. Every read returns None instead of
+// panicking when it would run past the end, so
+// truncated files turn into format errors.
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> ByteReader<'a> {
+        return ByteReader { data: data, pos: 0 };
+    }
+
+    pub fn at(data: &'a [u8], pos: usize) -> ByteReader<'a> {
+        return ByteReader { data: data, pos: pos };
+    }
+
+    pub fn position(&self) -> usize {
+        return self.pos;
+    }
+
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    pub fn skip(&mut self, amt: usize) {
+        self.pos = self.pos.saturating_add(amt);
+    }
+
+    pub fn remaining(&self) -> usize {
+        return self.data.len().saturating_sub(self.pos);
+    }
+
+    pub fn bytes(&mut self, amt: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(amt)?;
+        let res = self.data.get(self.pos..end)?;
+        self.pos = end;
+        return Some(res);
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        return self.bytes(N)?.try_into().ok();
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        return Some(self.array::<1>()?[0]);
+    }
+
+    pub fn u16_le(&mut self) -> Option<u16> {
+        return Some(u16::from_le_bytes(self.array()?));
+    }
+
+    pub fn u32_le(&mut self) -> Option<u32> {
+        return Some(u32::from_le_bytes(self.array()?));
+    }
+
+    pub fn f32_le(&mut self) -> Option<f32> {
+        return Some(f32::from_le_bytes(self.array()?));
+    }
+
+    pub fn u16_be(&mut self) -> Option<u16> {
+        return Some(u16::from_be_bytes(self.array()?));
+    }
+
+    pub fn i16_be(&mut self) -> Option<i16> {
+        return Some(i16::from_be_bytes(self.array()?));
+    }
+
+    pub fn u32_be(&mut self) -> Option<u32> {
+        return Some(u32::from_be_bytes(self.array()?));
+    }
+}